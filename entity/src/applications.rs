@@ -14,7 +14,8 @@ pub struct Model {
     pub preset_id: Option<String>,
     pub title: Option<String>,
     pub user_ip: Option<String>,
-    pub created: Option<String>,
+    #[serde(default, deserialize_with = "crate::parse::optional_timestamp")]
+    pub created: Option<DateTimeUtc>,
     pub username: Option<String>,
     pub user_id: Option<String>,
     pub user_data: Option<serde_json::Value>,
@@ -0,0 +1,34 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm::entity::prelude::*;
+use serde::Deserialize;
+
+/// Something `verify --save-gaps` found missing: fewer threads or posts stored than Enjin reported.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Deserialize)]
+#[sea_orm(table_name = "completeness_gaps")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub gap_id: i32,
+    /// When the `verify` that found it ran.
+    pub checked_at: DateTimeUtc,
+    /// `preset`, `subforum` or `thread`.
+    pub level: String,
+    pub item_id: String,
+    /// The subforum of a thread, or the preset of a subforum.
+    pub parent_id: Option<String>,
+    /// `threads` or `posts`.
+    pub what: String,
+    /// What Enjin reported. Not set for a thread that was listed but never fetched.
+    pub expected: Option<i64>,
+    pub stored: i64,
+    /// Comma-separated thread pages the crawl queue has as not done, if it knows.
+    pub missing_pages: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub type CompletenessGap = Model;
@@ -0,0 +1,31 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm::entity::prelude::*;
+use serde::Deserialize;
+
+/// One unit of crawl work (a preset, a page of a forum index or thread, an application or an image)
+/// and how far we've got with it.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Deserialize)]
+#[sea_orm(table_name = "crawl_queue")]
+pub struct Model {
+    /// `kind/item_id` or `kind/item_id/page`.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub unit_id: String,
+    pub kind: String,
+    pub item_id: String,
+    pub page: Option<i32>,
+    /// What this unit was found in: the forum of a thread page, the type of an application, etc.
+    pub parent_id: Option<String>,
+    /// `pending`, `in_progress`, `done`, `failed` or `held` (set aside while `retry-failed` runs).
+    pub state: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub type CrawlUnit = Model;
@@ -0,0 +1,32 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm::entity::prelude::*;
+use serde::Deserialize;
+
+/// A unit of crawl work that was skipped after failing, kept until `retry-failed` gets it.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Deserialize)]
+#[sea_orm(table_name = "failed_items")]
+pub struct Model {
+    /// Same as `crawl_queue.unit_id`.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub unit_id: String,
+    pub kind: String,
+    pub item_id: String,
+    pub page: Option<i32>,
+    pub parent_id: Option<String>,
+    /// What kind of failure it was, like `no_access` or `timeout`.
+    pub error_class: String,
+    pub last_error: String,
+    /// When it last failed.
+    pub failed_at: DateTimeUtc,
+    /// How many runs it has failed in.
+    pub failures: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub type FailedItem = Model;
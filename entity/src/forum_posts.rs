@@ -10,20 +10,55 @@ use serde::Deserialize;
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub post_id: String,
-    pub post_time: String,
+    #[serde(deserialize_with = "crate::parse::timestamp")]
+    pub post_time: DateTimeUtc,
     pub post_content: String,
     pub post_user_id: Option<String>,
-    pub last_edit_time: String,
-    pub post_unhidden: String,
-    pub post_admin_hidden: String,
-    pub post_locked: String,
+    /// Not set if the post was never edited.
+    #[serde(default, deserialize_with = "crate::parse::optional_timestamp")]
+    pub last_edit_time: Option<DateTimeUtc>,
+    #[serde(deserialize_with = "crate::parse::flag")]
+    pub post_unhidden: bool,
+    #[serde(deserialize_with = "crate::parse::flag")]
+    pub post_admin_hidden: bool,
+    #[serde(deserialize_with = "crate::parse::flag")]
+    pub post_locked: bool,
     pub last_edit_user: String,
     pub post_username: String,
     pub thread_id: Option<String>,
+    /// When an extraction last fetched the post.
+    pub last_seen_at: Option<DateTimeUtc>,
+    /// When a full extraction found the post gone from Enjin. Cleared if it shows up again.
+    pub deleted_detected_at: Option<DateTimeUtc>,
+    /// Which page of its thread the post was on when it was last fetched, and where on the page,
+    /// counting from 1, so the thread can be shown in its original order.
+    pub thread_page: Option<i64>,
+    pub page_position: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::forum_threads::Entity",
+        from = "Column::ThreadId",
+        to = "super::forum_threads::Column::ThreadId"
+    )]
+    ForumThreads,
+    #[sea_orm(has_many = "super::post_revisions::Entity")]
+    PostRevisions,
+}
+
+impl Related<super::forum_threads::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ForumThreads.def()
+    }
+}
+
+impl Related<super::post_revisions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PostRevisions.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
 
@@ -16,7 +16,16 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::subforums::Entity")]
+    Subforums,
+}
+
+impl Related<super::subforums::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Subforums.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
 
@@ -11,16 +11,68 @@ pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub thread_id: String,
     pub thread_subject: String,
-    pub thread_views: String,
+    #[serde(deserialize_with = "crate::parse::count")]
+    pub thread_views: i64,
     pub thread_type: String,
     pub thread_status: String,
     pub forum_id: String,
     pub username: Option<String>,
     pub category_id: String,
+    #[serde(default, deserialize_with = "crate::parse::optional_count")]
+    pub thread_replies: Option<i64>,
+    #[serde(default, deserialize_with = "crate::parse::optional_timestamp")]
+    pub thread_lastpost_time: Option<DateTimeUtc>,
+    /// How many posts and pages Enjin said the thread had, the last time one of its pages was fetched.
+    pub total_items: Option<i64>,
+    pub total_pages: Option<i64>,
+    /// When an extraction last listed the thread.
+    pub last_seen_at: Option<DateTimeUtc>,
+    /// When a full extraction found the thread gone from Enjin. Cleared if it shows up again.
+    pub deleted_detected_at: Option<DateTimeUtc>,
+    /// Who started the thread, and who posted in it last.
+    pub thread_user_id: Option<String>,
+    pub thread_lastpost_user_id: Option<String>,
+    pub thread_lastpost_username: Option<String>,
+    /// Where the thread was moved to, as Enjin sends it.
+    pub thread_moved_id: Option<String>,
+    #[serde(default, deserialize_with = "crate::parse::optional_flag")]
+    pub thread_hidden: Option<bool>,
+    /// Everything the listing had for the thread, as JSON, including what isn't modelled above.
+    /// Where it was listed is in `thread_placements`.
+    pub listing_json: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::subforums::Entity",
+        from = "Column::ForumId",
+        to = "super::subforums::Column::ForumId"
+    )]
+    Subforums,
+    #[sea_orm(has_many = "super::forum_posts::Entity")]
+    ForumPosts,
+    #[sea_orm(has_many = "super::thread_placements::Entity")]
+    ThreadPlacements,
+}
+
+impl Related<super::subforums::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Subforums.def()
+    }
+}
+
+impl Related<super::forum_posts::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ForumPosts.def()
+    }
+}
+
+impl Related<super::thread_placements::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ThreadPlacements.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
 
@@ -6,8 +6,15 @@ pub mod prelude;
 
 pub mod applications;
 pub mod category_names;
+pub mod completeness_gaps;
+pub mod crawl_queue;
+pub mod failed_items;
 pub mod forum_posts;
 pub mod forum_presets;
 pub mod forum_threads;
 pub mod images;
+pub mod parse;
+pub mod post_revisions;
+pub mod runs;
 pub mod subforums;
+pub mod thread_placements;
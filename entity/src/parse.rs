@@ -0,0 +1,181 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use chrono::{TimeZone, Utc};
+use sea_orm::prelude::DateTimeUtc;
+use serde::{de::Error, Deserialize, Deserializer};
+use serde_json::Value;
+
+//Enjin sends timestamps (in Unix seconds), counts and flags as strings, mostly, but these take
+//numbers (and booleans, for flags) too. They're used with `#[serde(deserialize_with)]` on the
+//entity fields that hold them.
+
+/// A whole number, or `None` for null or an empty string.
+fn integer(value: Value) -> Result<Option<i64>, String> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bool(b) => Ok(Some(b as i64)),
+        Value::Number(n) => n
+            .as_i64()
+            .map(Some)
+            .ok_or_else(|| format!("expected a whole number, got {}", n)),
+        Value::String(s) if s.trim().is_empty() => Ok(None),
+        Value::String(s) => s
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("expected a whole number, got {:?}", s)),
+        other => Err(format!("expected a whole number, got {}", other)),
+    }
+}
+
+fn optional_integer<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    integer(Value::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+fn utc(seconds: i64) -> Result<DateTimeUtc, String> {
+    Utc.timestamp_opt(seconds, 0)
+        .single()
+        .ok_or_else(|| format!("{} is out of range for a timestamp", seconds))
+}
+
+/// A count that Enjin always sends; missing counts as 0.
+pub fn count<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    Ok(optional_integer(deserializer)?.unwrap_or(0))
+}
+
+pub fn optional_count<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    optional_integer(deserializer)
+}
+
+/// `"1"` (or any other number but 0) is true.
+pub fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(optional_integer(deserializer)?.unwrap_or(0) != 0)
+}
+
+pub fn optional_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    Ok(optional_integer(deserializer)?.map(|n| n != 0))
+}
+
+pub fn timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTimeUtc, D::Error> {
+    utc(optional_integer(deserializer)?.unwrap_or(0)).map_err(D::Error::custom)
+}
+
+/// Enjin sends 0 for a time that never was, like the last edit of a post nobody edited.
+pub fn optional_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTimeUtc>, D::Error> {
+    match optional_integer(deserializer)? {
+        None | Some(0) => Ok(None),
+        Some(seconds) => utc(seconds).map(Some).map_err(D::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Fields {
+        #[serde(default, deserialize_with = "count")]
+        count: i64,
+        #[serde(default, deserialize_with = "optional_count")]
+        optional_count: Option<i64>,
+        #[serde(default, deserialize_with = "flag")]
+        flag: bool,
+        #[serde(default, deserialize_with = "optional_flag")]
+        optional_flag: Option<bool>,
+        #[serde(default = "epoch", deserialize_with = "timestamp")]
+        timestamp: DateTimeUtc,
+        #[serde(default, deserialize_with = "optional_timestamp")]
+        optional_timestamp: Option<DateTimeUtc>,
+    }
+
+    fn epoch() -> DateTimeUtc {
+        utc(0).unwrap()
+    }
+
+    fn parse(value: Value) -> Result<Fields, String> {
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn strings_numbers_and_booleans_are_all_understood() {
+        let from_strings = parse(json!({
+            "count": " 12 ",
+            "optional_count": "3",
+            "flag": "1",
+            "optional_flag": "0",
+            "timestamp": "1672531200",
+            "optional_timestamp": "1672531260",
+        }))
+        .unwrap();
+        let from_numbers = parse(json!({
+            "count": 12,
+            "optional_count": 3,
+            "flag": true,
+            "optional_flag": false,
+            "timestamp": 1672531200,
+            "optional_timestamp": 1672531260,
+        }))
+        .unwrap();
+        assert_eq!(from_strings, from_numbers);
+        assert_eq!(
+            from_strings,
+            Fields {
+                count: 12,
+                optional_count: Some(3),
+                flag: true,
+                optional_flag: Some(false),
+                timestamp: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+                optional_timestamp: Some(Utc.with_ymd_and_hms(2023, 1, 1, 0, 1, 0).unwrap()),
+            }
+        );
+    }
+
+    #[test]
+    fn nothing_is_zero_or_none() {
+        let empty = Fields {
+            count: 0,
+            optional_count: None,
+            flag: false,
+            optional_flag: None,
+            timestamp: epoch(),
+            optional_timestamp: None,
+        };
+        assert_eq!(parse(json!({})).unwrap(), empty);
+        let blank = json!({
+            "count": "",
+            "optional_count": null,
+            "flag": " ",
+            "optional_flag": "",
+            "timestamp": null,
+            "optional_timestamp": "",
+        });
+        assert_eq!(parse(blank).unwrap(), empty);
+        //Enjin's way of saying "never".
+        let never = parse(json!({ "optional_timestamp": "0" })).unwrap();
+        assert_eq!(never.optional_timestamp, None);
+    }
+
+    #[test]
+    fn anything_else_is_an_error() {
+        let e = parse(json!({ "count": "many" })).unwrap_err();
+        assert!(
+            e.starts_with("expected a whole number, got \"many\""),
+            "{}",
+            e
+        );
+        let e = parse(json!({ "optional_count": 1.5 })).unwrap_err();
+        assert!(e.starts_with("expected a whole number, got 1.5"), "{}", e);
+        let e = parse(json!({ "flag": ["1"] })).unwrap_err();
+        assert!(
+            e.starts_with("expected a whole number, got [\"1\"]"),
+            "{}",
+            e
+        );
+        let e = parse(json!({ "timestamp": i64::MAX })).unwrap_err();
+        assert!(e.contains("is out of range for a timestamp"), "{}", e);
+    }
+}
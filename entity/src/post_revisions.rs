@@ -0,0 +1,53 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm::entity::prelude::*;
+use serde::Deserialize;
+
+/// One version of a post, as first seen by a run. `forum_posts` only holds the latest one.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Deserialize)]
+#[sea_orm(table_name = "post_revisions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub revision_id: i32,
+    pub post_id: String,
+    pub post_content: String,
+    pub last_edit_time: Option<DateTimeUtc>,
+    pub last_edit_user: String,
+    /// The run that saw this version, and when. Neither is known for the version a database already
+    /// had from before revisions were kept.
+    pub run_id: Option<i32>,
+    pub observed_at: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::forum_posts::Entity",
+        from = "Column::PostId",
+        to = "super::forum_posts::Column::PostId"
+    )]
+    ForumPosts,
+    #[sea_orm(
+        belongs_to = "super::runs::Entity",
+        from = "Column::RunId",
+        to = "super::runs::Column::RunId"
+    )]
+    Runs,
+}
+
+impl Related<super::forum_posts::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ForumPosts.def()
+    }
+}
+
+impl Related<super::runs::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Runs.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub type PostRevision = Model;
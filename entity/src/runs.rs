@@ -0,0 +1,53 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm::entity::prelude::*;
+use serde::Deserialize;
+
+/// One run of a command that fetches from Enjin, and what it got.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Deserialize)]
+#[sea_orm(table_name = "runs")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub run_id: i32,
+    /// `extract` or `retry-failed`.
+    pub command: String,
+    pub started_at: DateTimeUtc,
+    /// Not set if the run is still going or crashed.
+    pub finished_at: Option<DateTimeUtc>,
+    pub exit_code: Option<i32>,
+    pub encuum_version: String,
+    /// A hash of `settings`, so runs with the same settings are easy to find.
+    pub config_fingerprint: String,
+    /// The settings the run used as JSON, without the password, session ID or email.
+    pub settings: String,
+    pub presets: i64,
+    pub subforums: i64,
+    pub threads: i64,
+    pub posts: i64,
+    pub images: i64,
+    pub applications: i64,
+    /// Items that were skipped after failing.
+    pub errors: i64,
+    /// `errors` broken down by `failed_items.error_class`, as a JSON object.
+    pub errors_by_class: String,
+    /// For `extract`, when the extraction it started or resumed began. Threads and posts not seen
+    /// since then are the ones a full extraction marks deleted.
+    pub crawl_started_at: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::post_revisions::Entity")]
+    PostRevisions,
+}
+
+impl Related<super::post_revisions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PostRevisions.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub type Run = Model;
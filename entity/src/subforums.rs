@@ -21,7 +21,36 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::forum_presets::Entity",
+        from = "Column::PresetId",
+        to = "super::forum_presets::Column::PresetId"
+    )]
+    ForumPresets,
+    #[sea_orm(has_many = "super::forum_threads::Entity")]
+    ForumThreads,
+    #[sea_orm(has_many = "super::thread_placements::Entity")]
+    ThreadPlacements,
+}
+
+impl Related<super::forum_presets::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ForumPresets.def()
+    }
+}
+
+impl Related<super::forum_threads::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ForumThreads.def()
+    }
+}
+
+impl Related<super::thread_placements::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ThreadPlacements.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
 
@@ -0,0 +1,58 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm::entity::prelude::*;
+use serde::Deserialize;
+
+/// Where a subforum's listing had a thread, the last time that page of the listing was crawled.
+/// A thread listed by several subforums, like a global announcement, has a place in each.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Deserialize)]
+#[sea_orm(table_name = "thread_placements")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub forum_id: String,
+    /// Which list of the listing it was in: `announcement_global`, `announcement_local`,
+    /// `notices`, `sticky` or `threads`.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub listing: String,
+    /// The page of the listing, and the place in the list, counting from 1. The pinned lists are on
+    /// every page, so their threads have a place on each.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub listing_page: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub listing_position: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub thread_id: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::subforums::Entity",
+        from = "Column::ForumId",
+        to = "super::subforums::Column::ForumId"
+    )]
+    Subforums,
+    #[sea_orm(
+        belongs_to = "super::forum_threads::Entity",
+        from = "Column::ThreadId",
+        to = "super::forum_threads::Column::ThreadId"
+    )]
+    ForumThreads,
+}
+
+impl Related<super::subforums::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Subforums.def()
+    }
+}
+
+impl Related<super::forum_threads::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ForumThreads.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub type ThreadPlacement = Model;
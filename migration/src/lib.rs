@@ -4,12 +4,307 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
+mod m20230601_000001_crawl_queue;
+mod m20230615_000001_thread_activity;
+mod m20230701_000001_failed_items;
+mod m20230715_000001_runs;
+mod m20230801_000001_thread_totals;
+mod m20230801_000002_completeness_gaps;
+mod m20230815_000001_post_revisions;
+mod m20230901_000001_tombstones;
+mod m20230915_000001_relations;
+mod m20231001_000001_typed_columns;
+mod m20231015_000001_thread_listings;
+mod m20231101_000001_post_positions;
+mod rebuild;
+
+/// A column as a migration creates it. Migrations name their tables and columns themselves,
+/// rather than taking them from the entities, so they make the same schema whatever comes later.
+pub(crate) fn col(name: &str) -> ColumnDef {
+    ColumnDef::new(Alias::new(name))
+}
+
+/// A foreign key from `column` of `table` to the column of the same name in `parent`.
+pub(crate) fn foreign_key(table: &str, column: &str, parent: &str) -> ForeignKeyCreateStatement {
+    ForeignKey::create()
+        .from(Alias::new(table), Alias::new(column))
+        .to(Alias::new(parent), Alias::new(column))
+        .to_owned()
+}
 
 pub struct Migrator;
 
+impl Migrator {
+    /// Every migration in the order they're applied, each with what it changes.
+    fn history() -> Vec<(Box<dyn MigrationTrait>, &'static str)> {
+        vec![
+            (
+                Box::new(m20220101_000001_create_table::Migration),
+                "create the forum, image and application tables",
+            ),
+            (
+                Box::new(m20230601_000001_crawl_queue::Migration),
+                "add the crawl_queue table, so interrupted extractions can resume",
+            ),
+            (
+                Box::new(m20230615_000001_thread_activity::Migration),
+                "add each thread's reply count and last post time, for extract --update",
+            ),
+            (
+                Box::new(m20230701_000001_failed_items::Migration),
+                "add the failed_items table, for retry-failed",
+            ),
+            (
+                Box::new(m20230715_000001_runs::Migration),
+                "add the runs table, recording each run of encuum",
+            ),
+            (
+                Box::new(m20230801_000001_thread_totals::Migration),
+                "add each thread's post and page totals, for verify",
+            ),
+            (
+                Box::new(m20230801_000002_completeness_gaps::Migration),
+                "add the completeness_gaps table, for verify --save-gaps",
+            ),
+            (
+                Box::new(m20230815_000001_post_revisions::Migration),
+                "add the post_revisions table, keeping every version of a post",
+            ),
+            (
+                Box::new(m20230901_000001_tombstones::Migration),
+                "add when threads and posts were last seen and found deleted",
+            ),
+            (
+                Box::new(m20230915_000001_relations::Migration),
+                "add foreign keys between presets, subforums, threads, posts and revisions",
+            ),
+            (
+                Box::new(m20231001_000001_typed_columns::Migration),
+                "store timestamps, counts and flags as such, instead of as strings and Unix seconds",
+            ),
+            (
+                Box::new(m20231015_000001_thread_listings::Migration),
+                "add the rest of each thread's listing, and the thread_placements table of where threads are listed",
+            ),
+            (
+                Box::new(m20231101_000001_post_positions::Migration),
+                "add the page of its thread each post was on, and its place on the page",
+            ),
+        ]
+    }
+
+    /// The migrations `db` hasn't had yet, by name, in the order `up` applies them, each with
+    /// what it changes. Fails if `db` has had migrations we don't know, from a newer encuum.
+    pub async fn pending<C: ConnectionTrait>(db: &C) -> Result<Vec<(String, &'static str)>, DbErr> {
+        let applied: Vec<String> = Self::get_migration_models(db)
+            .await?
+            .into_iter()
+            .map(|m| m.version)
+            .collect();
+        let history = Self::history();
+        if let Some(unknown) = applied
+            .iter()
+            .find(|v| !history.iter().any(|(m, _)| m.name() == v.as_str()))
+        {
+            return Err(DbErr::Custom(format!(
+                "The database has migration {}, which this version of encuum doesn't know; it was upgraded by a newer one",
+                unknown
+            )));
+        }
+        Ok(history
+            .into_iter()
+            .filter(|(m, _)| !applied.iter().any(|v| v == m.name()))
+            .map(|(m, summary)| (m.name().to_string(), summary))
+            .collect())
+    }
+}
+
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220101_000001_create_table::Migration)]
+        Self::history().into_iter().map(|(m, _)| m).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm_migration::sea_orm::{Database, DatabaseConnection, QueryResult, Statement};
+
+    /// A database of a test's own, in the current directory, deleted again when it's dropped.
+    pub(crate) struct TestDb {
+        pub(crate) conn: DatabaseConnection,
+        file: String,
+    }
+
+    impl TestDb {
+        pub(crate) async fn new(name: &str) -> TestDb {
+            let file = format!("migration-test-{}-{}.db", name, std::process::id());
+            remove(&file);
+            let conn = Database::connect(format!("sqlite://./{}?mode=rwc", file))
+                .await
+                .unwrap();
+            TestDb { conn, file }
+        }
+
+        pub(crate) async fn query(&self, sql: &str) -> Vec<QueryResult> {
+            self.conn
+                .query_all(Statement::from_string(
+                    self.conn.get_database_backend(),
+                    sql.to_string(),
+                ))
+                .await
+                .unwrap()
+        }
+
+        pub(crate) async fn execute(&self, sql: &str) {
+            self.conn.execute_unprepared(sql).await.unwrap();
+        }
+    }
+
+    fn remove(file: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", file, suffix));
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            remove(&self.file);
+        }
+    }
+
+    /// Rows as the first version of encuum stored them, with everything a string, and a post
+    /// whose thread it never got.
+    const BASELINE_ROWS: &str = "
+        INSERT INTO forum_presets VALUES ('1', 'Forums', 1, 3);
+        INSERT INTO category_names VALUES ('5', 'Community');
+        INSERT INTO subforums VALUES ('Welcome', '1', '5', 'Community', '10', 'General', 'Chat', NULL, 'forum');
+        INSERT INTO forum_threads VALUES ('100', 'Hello', '42', 'normal', 'open', '10', 'alice', '5');
+        INSERT INTO forum_posts VALUES
+            ('1000', '1690000000', 'First', '7', '0', '1', '0', '0', '', 'alice', '100'),
+            ('1001', '1690000060', 'Edited', '8', '1690000100', '1', '0', '1', 'bob', 'bob', '100'),
+            ('1002', '1690000120', 'Orphan', '9', '0', '0', '1', '0', '', 'carol', '999');
+        INSERT INTO images VALUES ('https://example.com/a.png', x'89504e47');
+        INSERT INTO applications VALUES ('20', '1', '1', 'Join', NULL, '1690000000', 'dave', '11', '{}');
+    ";
+
+    #[tokio::test]
+    async fn a_baseline_database_is_upgraded_with_its_rows() {
+        let db = TestDb::new("baseline").await;
+        Migrator::up(&db.conn, Some(1)).await.unwrap();
+        db.execute(BASELINE_ROWS).await;
+        let pending = Migrator::pending(&db.conn).await.unwrap();
+        assert_eq!(pending.len(), Migrator::migrations().len() - 1);
+        assert_eq!(pending[0].0, "m20230601_000001_crawl_queue");
+
+        Migrator::up(&db.conn, None).await.unwrap();
+        assert!(Migrator::pending(&db.conn).await.unwrap().is_empty());
+
+        let posts = db.query("SELECT * FROM forum_posts ORDER BY post_id").await;
+        let post =
+            |i: usize, column: &str| -> Option<String> { posts[i].try_get("", column).unwrap() };
+        assert_eq!(posts.len(), 3);
+        assert_eq!(post(0, "post_time").unwrap(), "2023-07-22T04:26:40+00:00");
+        assert_eq!(post(0, "last_edit_time"), None);
+        assert_eq!(
+            post(1, "last_edit_time").unwrap(),
+            "2023-07-22T04:28:20+00:00"
+        );
+        assert_eq!(post(1, "post_content").unwrap(), "Edited");
+        //The orphan is kept for `verify` to report.
+        assert_eq!(post(2, "thread_id").unwrap(), "999");
+        let flags: Vec<(bool, bool, bool)> = posts
+            .iter()
+            .map(|p| {
+                let flag = |column| p.try_get::<bool>("", column).unwrap();
+                (
+                    flag("post_unhidden"),
+                    flag("post_admin_hidden"),
+                    flag("post_locked"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                (true, false, false),
+                (true, false, true),
+                (false, true, false)
+            ]
+        );
+        let threads = db
+            .query("SELECT thread_views, forum_id FROM forum_threads")
+            .await;
+        assert_eq!(threads[0].try_get::<i64>("", "thread_views").unwrap(), 42);
+        assert_eq!(threads[0].try_get::<String>("", "forum_id").unwrap(), "10");
+        let created = db.query("SELECT created FROM applications").await;
+        assert_eq!(
+            created[0].try_get::<String>("", "created").unwrap(),
+            "2023-07-22T04:26:40+00:00"
+        );
+        for (table, count) in [
+            ("forum_presets", 1),
+            ("category_names", 1),
+            ("subforums", 1),
+            ("images", 1),
+        ] {
+            let rows = db.query(&format!("SELECT * FROM {}", table)).await;
+            assert_eq!(rows.len(), count, "{}", table);
+        }
+
+        for (table, parent) in [
+            ("subforums", "forum_presets"),
+            ("forum_threads", "subforums"),
+            ("forum_posts", "forum_threads"),
+            ("post_revisions", "forum_posts"),
+        ] {
+            let keys = db
+                .query(&format!("PRAGMA foreign_key_list(\"{}\")", table))
+                .await;
+            let parents: Vec<String> = keys
+                .iter()
+                .map(|k| k.try_get("", "table").unwrap())
+                .collect();
+            assert!(
+                parents.contains(&parent.to_string()),
+                "{}: {:?}",
+                table,
+                parents
+            );
+        }
+        let indices: Vec<String> = db
+            .query("SELECT name FROM sqlite_master WHERE type = 'index'")
+            .await
+            .iter()
+            .map(|row| row.try_get("", "name").unwrap())
+            .collect();
+        for index in [
+            "idx_subforums_preset_id",
+            "idx_forum_threads_forum_id",
+            "idx_forum_posts_thread_id",
+            "idx_post_revisions_run_id",
+        ] {
+            assert!(indices.contains(&index.to_string()), "{:?}", indices);
+        }
+        //The rebuilds turn foreign keys back on, so the orphan is all that's wrong.
+        let violations = db.query("PRAGMA foreign_key_check").await;
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].try_get::<String>("", "table").unwrap(),
+            "forum_posts"
+        );
+        let enforced = db.query("PRAGMA foreign_keys").await;
+        assert_eq!(enforced[0].try_get::<i32>("", "foreign_keys").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn migrations_from_a_newer_encuum_are_refused() {
+        let db = TestDb::new("newer").await;
+        Migrator::up(&db.conn, None).await.unwrap();
+        db.execute("INSERT INTO seaql_migrations VALUES ('m20991231_000001_future', 0)")
+            .await;
+        let e = Migrator::pending(&db.conn).await.unwrap_err();
+        assert!(e.to_string().contains("m20991231_000001_future"), "{}", e);
     }
 }
@@ -1,46 +1,115 @@
 /// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
 /// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
 /// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
-use entity::prelude::*;
-use sea_orm_migration::{prelude::*, sea_orm::Schema};
+use crate::col;
+use sea_orm_migration::prelude::*;
 
+/// The tables as the first version of encuum made them. Later migrations change them, so they're
+/// spelled out here rather than made from the entities, which only ever describe the latest schema.
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
+const TABLES: [&str; 7] = [
+    "subforums",
+    "images",
+    "category_names",
+    "forum_posts",
+    "forum_presets",
+    "forum_threads",
+    "applications",
+];
+
+fn tables() -> [TableCreateStatement; 7] {
+    [
+        Table::create()
+            .table(Alias::new("subforums"))
+            .col(col("title_welcome").string())
+            .col(col("preset_id").string().not_null())
+            .col(col("category_id").string().not_null())
+            .col(col("category_name").string().not_null())
+            .col(col("forum_id").string().not_null().primary_key())
+            .col(col("forum_name").string().not_null())
+            .col(col("forum_description").string().not_null())
+            .col(col("parent_id").string())
+            .col(col("forum_type").string().not_null())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("images"))
+            .col(col("image_url").string().not_null().primary_key())
+            .col(col("image_content").binary())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("category_names"))
+            .col(col("category_id").string().not_null().primary_key())
+            .col(col("category_name").string().not_null())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_posts"))
+            .col(col("post_id").string().not_null().primary_key())
+            .col(col("post_time").string().not_null())
+            .col(col("post_content").string().not_null())
+            .col(col("post_user_id").string())
+            .col(col("last_edit_time").string().not_null())
+            .col(col("post_unhidden").string().not_null())
+            .col(col("post_admin_hidden").string().not_null())
+            .col(col("post_locked").string().not_null())
+            .col(col("last_edit_user").string().not_null())
+            .col(col("post_username").string().not_null())
+            .col(col("thread_id").string())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_presets"))
+            .col(col("preset_id").string().not_null().primary_key())
+            .col(col("title_welcome").string().not_null())
+            .col(col("total_threads").integer().not_null())
+            .col(col("total_posts").integer().not_null())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_threads"))
+            .col(col("thread_id").string().not_null().primary_key())
+            .col(col("thread_subject").string().not_null())
+            .col(col("thread_views").string().not_null())
+            .col(col("thread_type").string().not_null())
+            .col(col("thread_status").string().not_null())
+            .col(col("forum_id").string().not_null())
+            .col(col("username").string())
+            .col(col("category_id").string().not_null())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("applications"))
+            .col(col("application_id").string().not_null().primary_key())
+            .col(col("site_id").string())
+            .col(col("preset_id").string())
+            .col(col("title").string())
+            .col(col("user_ip").string())
+            .col(col("created").string())
+            .col(col("username").string())
+            .col(col("user_id").string())
+            .col(col("user_data").string())
+            .to_owned(),
+    ]
+}
+
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-        let be = manager.get_database_backend();
-        let schema = Schema::new(be);
-        let mut tcs: Vec<TableCreateStatement> = vec![];
-        tcs.push(Schema::create_table_from_entity(&schema, Subforums));
-        tcs.push(Schema::create_table_from_entity(&schema, Images));
-        tcs.push(Schema::create_table_from_entity(&schema, CategoryNames));
-        tcs.push(Schema::create_table_from_entity(&schema, ForumPosts));
-        tcs.push(Schema::create_table_from_entity(&schema, ForumPresets));
-        tcs.push(Schema::create_table_from_entity(&schema, ForumThreads));
-        tcs.push(Schema::create_table_from_entity(&schema, Applications));
-        for tc in tcs {
-            manager.create_table(tc).await.unwrap();
+        for table in tables() {
+            manager.create_table(table).await?;
         }
         Ok(())
     }
 
     async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-        for i in vec![
-            "Subforums",
-            "Images",
-            "CategoryNames",
-            "ForumPosts",
-            "ForumPresets",
-            "ForumThreads",
-            "Applications",
-        ] {
-            let mut t = Table::drop();
-            t.table(Alias::new(i));
-            manager.drop_table(t).await.ok();
+        for table in TABLES {
+            manager
+                .drop_table(
+                    Table::drop()
+                        .table(Alias::new(table))
+                        .if_exists()
+                        .to_owned(),
+                )
+                .await?;
         }
-
         Ok(())
     }
 }
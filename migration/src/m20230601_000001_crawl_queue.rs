@@ -0,0 +1,46 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::col;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alias::new("crawl_queue"))
+                    .col(col("unit_id").string().not_null().primary_key())
+                    .col(col("kind").string().not_null())
+                    .col(col("item_id").string().not_null())
+                    .col(col("page").integer())
+                    .col(col("parent_id").string())
+                    .col(col("state").string().not_null())
+                    .col(col("attempts").integer().not_null())
+                    .col(col("last_error").string())
+                    .to_owned(),
+            )
+            .await?;
+        //The crawl keeps asking for the pending units of one kind.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_crawl_queue_state_kind")
+                    .table(Alias::new("crawl_queue"))
+                    .col(Alias::new("state"))
+                    .col(Alias::new("kind"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Alias::new("crawl_queue")).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,49 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::col;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alias::new("runs"))
+                    .col(
+                        col("run_id")
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(col("command").string().not_null())
+                    .col(col("started_at").big_integer().not_null())
+                    .col(col("finished_at").big_integer())
+                    .col(col("exit_code").integer())
+                    .col(col("encuum_version").string().not_null())
+                    .col(col("config_fingerprint").string().not_null())
+                    .col(col("settings").string().not_null())
+                    .col(col("presets").big_integer().not_null())
+                    .col(col("subforums").big_integer().not_null())
+                    .col(col("threads").big_integer().not_null())
+                    .col(col("posts").big_integer().not_null())
+                    .col(col("images").big_integer().not_null())
+                    .col(col("applications").big_integer().not_null())
+                    .col(col("errors").big_integer().not_null())
+                    .col(col("errors_by_class").string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Alias::new("runs")).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,48 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::col;
+use sea_orm_migration::prelude::*;
+
+/// The post and page counts from the thread itself, which `verify` checks the stored posts against.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+fn columns() -> [ColumnDef; 2] {
+    [
+        col("total_items").string().to_owned(),
+        col("total_pages").string().to_owned(),
+    ]
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        //SQLite only takes one column per ALTER TABLE.
+        for mut column in columns() {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new("forum_threads"))
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in columns() {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new("forum_threads"))
+                        .drop_column(Alias::new(&column.get_column_name()))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
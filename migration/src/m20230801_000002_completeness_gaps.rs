@@ -0,0 +1,46 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::col;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alias::new("completeness_gaps"))
+                    .col(
+                        col("gap_id")
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(col("checked_at").big_integer().not_null())
+                    .col(col("level").string().not_null())
+                    .col(col("item_id").string().not_null())
+                    .col(col("parent_id").string())
+                    .col(col("what").string().not_null())
+                    .col(col("expected").big_integer())
+                    .col(col("stored").big_integer().not_null())
+                    .col(col("missing_pages").string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(Alias::new("completeness_gaps"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
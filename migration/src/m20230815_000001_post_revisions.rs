@@ -0,0 +1,50 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::col;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alias::new("post_revisions"))
+                    .col(
+                        col("revision_id")
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(col("post_id").string().not_null())
+                    .col(col("post_content").string().not_null())
+                    .col(col("last_edit_time").string().not_null())
+                    .col(col("last_edit_user").string().not_null())
+                    .col(col("run_id").integer())
+                    .col(col("observed_at").big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        //Every saved post looks up its revisions.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_post_revisions_post_id")
+                    .table(Alias::new("post_revisions"))
+                    .col(Alias::new("post_id"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Alias::new("post_revisions")).to_owned())
+            .await
+    }
+}
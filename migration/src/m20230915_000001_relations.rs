@@ -0,0 +1,128 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::{col, foreign_key, rebuild};
+use sea_orm_migration::prelude::*;
+
+/// Foreign keys from each subforum to its preset, thread to its subforum, post to its thread and
+/// revision to its post and run, and indices on those columns.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// The tables with foreign keys, parents first, as they are at this point.
+fn tables() -> [TableCreateStatement; 4] {
+    [
+        Table::create()
+            .table(Alias::new("subforums"))
+            .col(col("title_welcome").string())
+            .col(col("preset_id").string().not_null())
+            .col(col("category_id").string().not_null())
+            .col(col("category_name").string().not_null())
+            .col(col("forum_id").string().not_null().primary_key())
+            .col(col("forum_name").string().not_null())
+            .col(col("forum_description").string().not_null())
+            .col(col("parent_id").string())
+            .col(col("forum_type").string().not_null())
+            .foreign_key(&mut foreign_key("subforums", "preset_id", "forum_presets"))
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_threads"))
+            .col(col("thread_id").string().not_null().primary_key())
+            .col(col("thread_subject").string().not_null())
+            .col(col("thread_views").string().not_null())
+            .col(col("thread_type").string().not_null())
+            .col(col("thread_status").string().not_null())
+            .col(col("forum_id").string().not_null())
+            .col(col("username").string())
+            .col(col("category_id").string().not_null())
+            .col(col("thread_replies").string())
+            .col(col("thread_lastpost_time").string())
+            .col(col("total_items").string())
+            .col(col("total_pages").string())
+            .col(col("last_seen_at").big_integer())
+            .col(col("deleted_detected_at").big_integer())
+            .foreign_key(&mut foreign_key("forum_threads", "forum_id", "subforums"))
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_posts"))
+            .col(col("post_id").string().not_null().primary_key())
+            .col(col("post_time").string().not_null())
+            .col(col("post_content").string().not_null())
+            .col(col("post_user_id").string())
+            .col(col("last_edit_time").string().not_null())
+            .col(col("post_unhidden").string().not_null())
+            .col(col("post_admin_hidden").string().not_null())
+            .col(col("post_locked").string().not_null())
+            .col(col("last_edit_user").string().not_null())
+            .col(col("post_username").string().not_null())
+            .col(col("thread_id").string())
+            .col(col("last_seen_at").big_integer())
+            .col(col("deleted_detected_at").big_integer())
+            .foreign_key(&mut foreign_key(
+                "forum_posts",
+                "thread_id",
+                "forum_threads",
+            ))
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("post_revisions"))
+            .col(
+                col("revision_id")
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(col("post_id").string().not_null())
+            .col(col("post_content").string().not_null())
+            .col(col("last_edit_time").string().not_null())
+            .col(col("last_edit_user").string().not_null())
+            .col(col("run_id").integer())
+            .col(col("observed_at").big_integer())
+            .foreign_key(&mut foreign_key("post_revisions", "post_id", "forum_posts"))
+            .foreign_key(&mut foreign_key("post_revisions", "run_id", "runs"))
+            .to_owned(),
+    ]
+}
+
+const INDICES: [(&str, &str, &str); 4] = [
+    ("idx_subforums_preset_id", "subforums", "preset_id"),
+    ("idx_forum_threads_forum_id", "forum_threads", "forum_id"),
+    ("idx_forum_posts_thread_id", "forum_posts", "thread_id"),
+    ("idx_post_revisions_run_id", "post_revisions", "run_id"),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let mut batch = String::new();
+        for table in tables() {
+            batch += &rebuild::rebuild(manager, table).await?;
+        }
+        rebuild::run(manager, &batch).await?;
+
+        for (name, table, column) in INDICES {
+            manager
+                .create_index(
+                    Index::create()
+                        .name(name)
+                        .table(Alias::new(table))
+                        .col(Alias::new(column))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        //The foreign keys stay: encuum has relied on them ever since, so there's no going back to
+        //a schema without them.
+        for (name, table, _) in INDICES {
+            manager
+                .drop_index(Index::drop().name(name).table(Alias::new(table)).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
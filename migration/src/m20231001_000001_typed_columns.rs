@@ -0,0 +1,382 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::{col, foreign_key, rebuild};
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+/// Timestamps, counts and flags as UTC timestamps, integers and booleans, instead of the strings
+/// Enjin sends them as, and encuum's own times as UTC timestamps instead of Unix seconds.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Timestamp,
+    /// 0 or nothing means there isn't one.
+    OptionalTimestamp,
+    Count,
+    OptionalCount,
+    Flag,
+}
+
+const CONVERSIONS: [(&str, &str, Kind); 22] = [
+    ("forum_threads", "thread_views", Kind::Count),
+    ("forum_threads", "thread_replies", Kind::OptionalCount),
+    (
+        "forum_threads",
+        "thread_lastpost_time",
+        Kind::OptionalTimestamp,
+    ),
+    ("forum_threads", "total_items", Kind::OptionalCount),
+    ("forum_threads", "total_pages", Kind::OptionalCount),
+    ("forum_posts", "post_time", Kind::Timestamp),
+    ("forum_posts", "last_edit_time", Kind::OptionalTimestamp),
+    ("forum_posts", "post_unhidden", Kind::Flag),
+    ("forum_posts", "post_admin_hidden", Kind::Flag),
+    ("forum_posts", "post_locked", Kind::Flag),
+    ("post_revisions", "last_edit_time", Kind::OptionalTimestamp),
+    ("applications", "created", Kind::OptionalTimestamp),
+    ("forum_threads", "last_seen_at", Kind::OptionalTimestamp),
+    (
+        "forum_threads",
+        "deleted_detected_at",
+        Kind::OptionalTimestamp,
+    ),
+    ("forum_posts", "last_seen_at", Kind::OptionalTimestamp),
+    (
+        "forum_posts",
+        "deleted_detected_at",
+        Kind::OptionalTimestamp,
+    ),
+    ("post_revisions", "observed_at", Kind::OptionalTimestamp),
+    ("runs", "started_at", Kind::Timestamp),
+    ("runs", "finished_at", Kind::OptionalTimestamp),
+    ("runs", "crawl_started_at", Kind::OptionalTimestamp),
+    ("failed_items", "failed_at", Kind::Timestamp),
+    ("completeness_gaps", "checked_at", Kind::Timestamp),
+];
+
+impl Kind {
+    fn optional(self) -> bool {
+        matches!(self, Kind::OptionalTimestamp | Kind::OptionalCount)
+    }
+}
+
+/// The condition for what's left as a string in `column`, so the conversion is fine to run again.
+fn unconverted(column: &str, kind: Kind) -> String {
+    let c = format!("\"{}\"", column);
+    match kind {
+        Kind::Timestamp | Kind::OptionalTimestamp => format!("{} NOT LIKE '%-%'", c),
+        Kind::Count | Kind::OptionalCount | Kind::Flag => format!("typeof({}) = 'text'", c),
+    }
+}
+
+/// The UPDATE that converts what's left as a string in `column`. Only whole numbers are read, as
+/// `entity::parse` reads them, and an empty string is nothing, as Enjin means it. Anything else
+/// can't be converted: optional columns are left empty for it, and `unconvertible` reports it in
+/// the others, empty strings included, rather than making up a value. Timestamps are written the
+/// way a bound `DateTimeUtc` is, so they sort and compare as text.
+fn convert(table: &str, column: &str, kind: Kind) -> String {
+    let c = format!("\"{}\"", column);
+    let number = format!("trim({}) <> '' AND trim({}) NOT GLOB '*[^0-9]*'", c, c);
+    let utc = format!(
+        "strftime('%Y-%m-%dT%H:%M:%S+00:00', CAST({} AS INTEGER), 'unixepoch')",
+        c
+    );
+    let value = match kind {
+        Kind::Timestamp => utc,
+        Kind::OptionalTimestamp => format!(
+            "CASE WHEN {} AND CAST({} AS INTEGER) > 0 THEN {} END",
+            number, c, utc
+        ),
+        Kind::Count => format!("CAST({} AS INTEGER)", c),
+        Kind::OptionalCount => format!("CASE WHEN {} THEN CAST({} AS INTEGER) END", number, c),
+        Kind::Flag => format!("CAST({} AS INTEGER) <> 0", c),
+    };
+    let convertible = match kind.optional() {
+        true => "1".to_string(),
+        false => number,
+    };
+    format!(
+        "UPDATE \"{}\" SET {} = {} WHERE {} AND {}",
+        table,
+        c,
+        value,
+        unconverted(column, kind),
+        convertible
+    )
+}
+
+/// What `convert` left as a string in a column that can't be empty, as the number of rows and one
+/// of the strings, to be fixed (or the rows deleted) before upgrading again.
+async fn unconvertible(
+    manager: &SchemaManager<'_>,
+    table: &str,
+    column: &str,
+    kind: Kind,
+) -> Result<Option<(i64, String)>, DbErr> {
+    let sql = format!(
+        "SELECT count(*) AS n, min(\"{}\") AS example FROM \"{}\" WHERE {}",
+        column,
+        table,
+        unconverted(column, kind)
+    );
+    let row = manager
+        .get_connection()
+        .query_one(Statement::from_string(manager.get_database_backend(), sql))
+        .await?;
+    match row {
+        Some(row) if row.try_get::<i64>("", "n")? > 0 => {
+            Ok(Some((row.try_get("", "n")?, row.try_get("", "example")?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The tables with columns that change type or can now be empty, parents first, as they are
+/// after this. The applications table only needs its timestamps converted.
+fn tables() -> [TableCreateStatement; 6] {
+    [
+        Table::create()
+            .table(Alias::new("runs"))
+            .col(
+                col("run_id")
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(col("command").string().not_null())
+            .col(col("started_at").timestamp_with_time_zone().not_null())
+            .col(col("finished_at").timestamp_with_time_zone())
+            .col(col("exit_code").integer())
+            .col(col("encuum_version").string().not_null())
+            .col(col("config_fingerprint").string().not_null())
+            .col(col("settings").string().not_null())
+            .col(col("presets").big_integer().not_null())
+            .col(col("subforums").big_integer().not_null())
+            .col(col("threads").big_integer().not_null())
+            .col(col("posts").big_integer().not_null())
+            .col(col("images").big_integer().not_null())
+            .col(col("applications").big_integer().not_null())
+            .col(col("errors").big_integer().not_null())
+            .col(col("errors_by_class").string().not_null())
+            .col(col("crawl_started_at").timestamp_with_time_zone())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_threads"))
+            .col(col("thread_id").string().not_null().primary_key())
+            .col(col("thread_subject").string().not_null())
+            .col(col("thread_views").big_integer().not_null())
+            .col(col("thread_type").string().not_null())
+            .col(col("thread_status").string().not_null())
+            .col(col("forum_id").string().not_null())
+            .col(col("username").string())
+            .col(col("category_id").string().not_null())
+            .col(col("thread_replies").big_integer())
+            .col(col("thread_lastpost_time").timestamp_with_time_zone())
+            .col(col("total_items").big_integer())
+            .col(col("total_pages").big_integer())
+            .col(col("last_seen_at").timestamp_with_time_zone())
+            .col(col("deleted_detected_at").timestamp_with_time_zone())
+            .foreign_key(&mut foreign_key("forum_threads", "forum_id", "subforums"))
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("forum_posts"))
+            .col(col("post_id").string().not_null().primary_key())
+            .col(col("post_time").timestamp_with_time_zone().not_null())
+            .col(col("post_content").string().not_null())
+            .col(col("post_user_id").string())
+            .col(col("last_edit_time").timestamp_with_time_zone())
+            .col(col("post_unhidden").boolean().not_null())
+            .col(col("post_admin_hidden").boolean().not_null())
+            .col(col("post_locked").boolean().not_null())
+            .col(col("last_edit_user").string().not_null())
+            .col(col("post_username").string().not_null())
+            .col(col("thread_id").string())
+            .col(col("last_seen_at").timestamp_with_time_zone())
+            .col(col("deleted_detected_at").timestamp_with_time_zone())
+            .foreign_key(&mut foreign_key(
+                "forum_posts",
+                "thread_id",
+                "forum_threads",
+            ))
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("post_revisions"))
+            .col(
+                col("revision_id")
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(col("post_id").string().not_null())
+            .col(col("post_content").string().not_null())
+            .col(col("last_edit_time").timestamp_with_time_zone())
+            .col(col("last_edit_user").string().not_null())
+            .col(col("run_id").integer())
+            .col(col("observed_at").timestamp_with_time_zone())
+            .foreign_key(&mut foreign_key("post_revisions", "post_id", "forum_posts"))
+            .foreign_key(&mut foreign_key("post_revisions", "run_id", "runs"))
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("failed_items"))
+            .col(col("unit_id").string().not_null().primary_key())
+            .col(col("kind").string().not_null())
+            .col(col("item_id").string().not_null())
+            .col(col("page").integer())
+            .col(col("parent_id").string())
+            .col(col("error_class").string().not_null())
+            .col(col("last_error").string().not_null())
+            .col(col("failed_at").timestamp_with_time_zone().not_null())
+            .col(col("failures").integer().not_null())
+            .to_owned(),
+        Table::create()
+            .table(Alias::new("completeness_gaps"))
+            .col(
+                col("gap_id")
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(col("checked_at").timestamp_with_time_zone().not_null())
+            .col(col("level").string().not_null())
+            .col(col("item_id").string().not_null())
+            .col(col("parent_id").string())
+            .col(col("what").string().not_null())
+            .col(col("expected").big_integer())
+            .col(col("stored").big_integer().not_null())
+            .col(col("missing_pages").string())
+            .to_owned(),
+    ]
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let mut batch = String::new();
+        for table in tables() {
+            batch += &rebuild::rebuild(manager, table).await?;
+        }
+        rebuild::run(manager, &batch).await?;
+
+        let conn = manager.get_connection();
+        let mut problems = vec![];
+        for (table, column, kind) in CONVERSIONS {
+            conn.execute_unprepared(&convert(table, column, kind))
+                .await?;
+            if kind.optional() {
+                continue;
+            }
+            if let Some((n, example)) = unconvertible(manager, table, column, kind).await? {
+                problems.push(format!(
+                    "{}.{} has {} value(s) that aren't numbers, like {:?}",
+                    table, column, n, example
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "Some values can't be converted; fix or delete those rows and upgrade again: {}",
+                problems.join("; ")
+            )));
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        //The strings can't come back: encuum no longer reads them.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{tests::TestDb, Migrator};
+    use sea_orm_migration::MigratorTrait;
+
+    /// How many migrations come before this one.
+    const BEFORE: u32 = 10;
+
+    async fn before_typed_columns(name: &str) -> TestDb {
+        let db = TestDb::new(name).await;
+        Migrator::up(&db.conn, Some(BEFORE)).await.unwrap();
+        db.execute(
+            "INSERT INTO forum_presets VALUES ('1', 'Forums', 1, 2);
+            INSERT INTO subforums VALUES ('Welcome', '1', '5', 'Community', '10', 'General', 'Chat', NULL, 'forum');",
+        )
+        .await;
+        db
+    }
+
+    #[tokio::test]
+    async fn only_numbers_are_converted_and_optional_columns_are_emptied_of_the_rest() {
+        let db = before_typed_columns("convert").await;
+        db.execute(
+            "INSERT INTO forum_threads (thread_id, thread_subject, thread_views, thread_type, thread_status, forum_id, username, category_id, thread_replies, thread_lastpost_time, total_items)
+            VALUES ('100', 'Hello', ' 42 ', 'normal', 'open', '10', 'alice', '5', 'n/a', 'yesterday', '');
+            INSERT INTO forum_posts (post_id, post_time, post_content, post_user_id, last_edit_time, post_unhidden, post_admin_hidden, post_locked, last_edit_user, post_username, thread_id)
+            VALUES ('1000', '1690000000', 'First', '7', 'never', '1', '0', '0', '', 'alice', '100');",
+        )
+        .await;
+        Migrator::up(&db.conn, Some(1)).await.unwrap();
+
+        let thread = &db.query("SELECT * FROM forum_threads").await[0];
+        assert_eq!(thread.try_get::<i64>("", "thread_views").unwrap(), 42);
+        for column in ["thread_replies", "thread_lastpost_time", "total_items"] {
+            let value: Option<String> = thread.try_get("", column).unwrap();
+            assert_eq!(value, None, "{}", column);
+        }
+        let post = &db.query("SELECT * FROM forum_posts").await[0];
+        let time: String = post.try_get("", "post_time").unwrap();
+        assert_eq!(time, "2023-07-22T04:26:40+00:00");
+        let edited: Option<String> = post.try_get("", "last_edit_time").unwrap();
+        assert_eq!(edited, None);
+        assert!(post.try_get::<bool>("", "post_unhidden").unwrap());
+        assert!(!post.try_get::<bool>("", "post_admin_hidden").unwrap());
+    }
+
+    #[tokio::test]
+    async fn what_cant_be_converted_where_something_is_needed_is_reported() {
+        let db = before_typed_columns("unconvertible").await;
+        db.execute(
+            "INSERT INTO forum_threads (thread_id, thread_subject, thread_views, thread_type, thread_status, forum_id, username, category_id)
+            VALUES ('100', 'Hello', 'lots', 'normal', 'open', '10', 'alice', '5');
+            INSERT INTO forum_posts (post_id, post_time, post_content, post_user_id, last_edit_time, post_unhidden, post_admin_hidden, post_locked, last_edit_user, post_username, thread_id)
+            VALUES ('1000', '1690000000', 'First', '7', '0', 'yes', '0', ' ', '', 'alice', '100');",
+        )
+        .await;
+        let e = Migrator::up(&db.conn, Some(1))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.contains(
+                "forum_threads.thread_views has 1 value(s) that aren't numbers, like \"lots\""
+            ) && e.contains(
+                "forum_posts.post_unhidden has 1 value(s) that aren't numbers, like \"yes\""
+            ) && e
+                .contains("forum_posts.post_locked has 1 value(s) that aren't numbers, like \" \""),
+            "{}",
+            e
+        );
+        //Nothing was made up for them, and once they're fixed the upgrade goes through.
+        let thread = &db.query("SELECT thread_views FROM forum_threads").await[0];
+        assert_eq!(
+            thread.try_get::<String>("", "thread_views").unwrap(),
+            "lots"
+        );
+        db.execute(
+            "UPDATE forum_threads SET thread_views = '7'; UPDATE forum_posts SET post_unhidden = '1', post_locked = '0';",
+        )
+        .await;
+        Migrator::up(&db.conn, Some(1)).await.unwrap();
+        let thread = &db.query("SELECT thread_views FROM forum_threads").await[0];
+        assert_eq!(thread.try_get::<i64>("", "thread_views").unwrap(), 7);
+    }
+}
@@ -0,0 +1,149 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::{col, foreign_key};
+use sea_orm_migration::prelude::*;
+
+/// The rest of what a subforum listing says about each thread, and where in the listings it was,
+/// in a table of its own: a thread can be listed by several subforums (global announcements are
+/// listed by all of them).
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+fn columns() -> [ColumnDef; 6] {
+    [
+        col("thread_user_id").string().to_owned(),
+        col("thread_lastpost_user_id").string().to_owned(),
+        col("thread_lastpost_username").string().to_owned(),
+        col("thread_moved_id").string().to_owned(),
+        col("thread_hidden").boolean().to_owned(),
+        col("listing_json").string().to_owned(),
+    ]
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        //SQLite only takes one column per ALTER TABLE.
+        for mut column in columns() {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new("forum_threads"))
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alias::new("thread_placements"))
+                    .col(col("forum_id").string().not_null())
+                    .col(col("listing").string().not_null())
+                    .col(col("listing_page").big_integer().not_null())
+                    .col(col("listing_position").big_integer().not_null())
+                    .col(col("thread_id").string().not_null())
+                    .primary_key(
+                        Index::create()
+                            .col(Alias::new("forum_id"))
+                            .col(Alias::new("listing"))
+                            .col(Alias::new("listing_page"))
+                            .col(Alias::new("listing_position"))
+                            .col(Alias::new("thread_id")),
+                    )
+                    .foreign_key(&mut foreign_key(
+                        "thread_placements",
+                        "forum_id",
+                        "subforums",
+                    ))
+                    .foreign_key(&mut foreign_key(
+                        "thread_placements",
+                        "thread_id",
+                        "forum_threads",
+                    ))
+                    .to_owned(),
+            )
+            .await?;
+        //The primary key covers `forum_id`.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_thread_placements_thread_id")
+                    .table(Alias::new("thread_placements"))
+                    .col(Alias::new("thread_id"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(Alias::new("thread_placements"))
+                    .to_owned(),
+            )
+            .await?;
+        for column in columns() {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new("forum_threads"))
+                        .drop_column(Alias::new(&column.get_column_name()))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{tests::TestDb, Migrator};
+    use sea_orm_migration::{sea_orm::ConnectionTrait, MigratorTrait};
+
+    /// How many migrations come before this one.
+    const BEFORE: u32 = 11;
+
+    #[tokio::test]
+    async fn a_thread_can_be_placed_in_several_subforums_listings() {
+        let db = TestDb::new("placements").await;
+        Migrator::up(&db.conn, Some(BEFORE)).await.unwrap();
+        db.execute(
+            "INSERT INTO forum_presets VALUES ('1', 'Forums', 1, 3);
+            INSERT INTO subforums VALUES ('Welcome', '1', '5', 'Community', '10', 'General', 'Chat', NULL, 'forum');
+            INSERT INTO subforums VALUES ('Welcome', '1', '5', 'Community', '12', 'Off Topic', 'Cats', '10', 'forum');
+            INSERT INTO forum_threads (thread_id, thread_subject, thread_views, thread_type, thread_status, forum_id, category_id)
+            VALUES ('100', 'Hello', 1, 'normal', 'open', '10', '5');",
+        )
+        .await;
+        Migrator::up(&db.conn, Some(1)).await.unwrap();
+
+        db.execute(
+            "INSERT INTO thread_placements VALUES ('10', 'threads', 2, 3, '100'), ('12', 'announcement_global', 1, 1, '100')",
+        )
+        .await;
+        let placed = db.query("SELECT * FROM thread_placements").await;
+        assert_eq!(placed.len(), 2);
+        //A placement in a subforum that isn't stored is refused.
+        let orphan = db
+            .conn
+            .execute_unprepared(
+                "INSERT INTO thread_placements VALUES ('99', 'threads', 1, 1, '100')",
+            )
+            .await;
+        assert!(orphan.is_err());
+
+        Migrator::down(&db.conn, Some(1)).await.unwrap();
+        let tables = db
+            .query("SELECT name FROM sqlite_master WHERE name = 'thread_placements'")
+            .await;
+        assert!(tables.is_empty());
+        let columns = db.query("PRAGMA table_info(forum_threads)").await;
+        assert!(!columns
+            .iter()
+            .any(|c| c.try_get::<String>("", "name").unwrap() == "listing_json"));
+    }
+}
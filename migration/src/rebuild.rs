@@ -0,0 +1,211 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, QueryResult, RuntimeErr, Statement},
+    SchemaManagerConnection,
+};
+use sqlx::Executor;
+
+//SQLite can't change a column's type or add a foreign key to a table, so migrations that do
+//either copy the table into a new one, as the migration spells it out, keeping its rows and
+//indices. Foreign key enforcement has to be off while the tables are swapped, and for rows that
+//already point at a missing parent, which `verify` reports. That's a setting of the connection
+//which can't be changed inside a transaction, so the statements for every table are run as one
+//batch on a connection taken from the pool for it.
+
+async fn query_all(manager: &SchemaManager<'_>, sql: String) -> Result<Vec<QueryResult>, DbErr> {
+    manager
+        .get_connection()
+        .query_all(Statement::from_string(manager.get_database_backend(), sql))
+        .await
+}
+
+/// The name of the table `create` makes.
+fn table_name(create: &TableCreateStatement) -> Result<String, DbErr> {
+    match create.get_table_name() {
+        Some(TableRef::Table(table)) => Ok(table.to_string()),
+        _ => Err(DbErr::Migration(
+            "A rebuilt table needs a plain name".to_string(),
+        )),
+    }
+}
+
+/// The statements that recreate the table `create` makes, as it makes it, keeping the rows and
+/// indices the table has. `create` must have exactly the columns the table has now, so nothing is
+/// lost and nothing is left empty.
+pub(crate) async fn rebuild(
+    manager: &SchemaManager<'_>,
+    mut create: TableCreateStatement,
+) -> Result<String, DbErr> {
+    let table = table_name(&create)?;
+    let new_table = format!("{}_new", table);
+    let wanted: Vec<String> = create
+        .get_columns()
+        .iter()
+        .map(|c| c.get_column_name())
+        .collect();
+    let mut existing = vec![];
+    for row in query_all(manager, format!("PRAGMA table_info(\"{}\")", table)).await? {
+        existing.push(row.try_get::<String>("", "name")?);
+    }
+    let mut sorted_wanted = wanted.clone();
+    sorted_wanted.sort();
+    existing.sort();
+    if sorted_wanted != existing {
+        return Err(DbErr::Migration(format!(
+            "Can't rebuild {}: it has the columns {}, not {}",
+            table,
+            existing.join(", "),
+            sorted_wanted.join(", ")
+        )));
+    }
+    create.table(Alias::new(&new_table));
+    let columns: Vec<String> = wanted.iter().map(|c| format!("\"{}\"", c)).collect();
+    let columns = columns.join(", ");
+    //Indices go with the old table. The ones SQLite makes itself have no SQL.
+    let indices = query_all(
+        manager,
+        format!(
+            "SELECT sql FROM sqlite_master WHERE type = 'index' AND tbl_name = '{}' AND sql IS NOT NULL",
+            table
+        ),
+    )
+    .await?
+    .iter()
+    .map(|row| row.try_get::<String>("", "sql"))
+    .collect::<Result<Vec<_>, _>>()?;
+    Ok(format!(
+        "{}; INSERT INTO \"{}\" ({}) SELECT {} FROM \"{}\"; DROP TABLE \"{}\"; ALTER TABLE \"{}\" RENAME TO \"{}\"; {}",
+        create.to_string(SqliteQueryBuilder),
+        new_table,
+        columns,
+        columns,
+        table,
+        table,
+        new_table,
+        table,
+        indices
+            .iter()
+            .map(|sql| format!("{};", sql))
+            .collect::<String>()
+    ))
+}
+
+fn exec_err(e: sqlx::Error) -> DbErr {
+    DbErr::Exec(RuntimeErr::SqlxError(e))
+}
+
+/// Runs the statements from `rebuild` for one or more tables, parents first, in one transaction.
+/// If any of them fails, none of them is kept.
+pub(crate) async fn run(manager: &SchemaManager<'_>, batch: &str) -> Result<(), DbErr> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let pool = match manager.get_connection() {
+        SchemaManagerConnection::Connection(conn) => conn.get_sqlite_connection_pool(),
+        SchemaManagerConnection::Transaction(_) => {
+            return Err(DbErr::Migration(
+                "Tables can't be rebuilt inside a transaction".to_string(),
+            ))
+        }
+    };
+    let mut conn = pool.acquire().await.map_err(exec_err)?;
+    conn.execute("PRAGMA foreign_keys = OFF")
+        .await
+        .map_err(exec_err)?;
+    let mut result = conn.execute("BEGIN").await.map(|_| ());
+    if result.is_ok() {
+        result = conn.execute(batch).await.map(|_| ());
+        result = match result {
+            Ok(()) => conn.execute("COMMIT").await.map(|_| ()),
+            Err(e) => {
+                let _ = conn.execute("ROLLBACK").await;
+                Err(e)
+            }
+        };
+    }
+    //A connection left without foreign keys mustn't go back to the pool.
+    if conn.execute("PRAGMA foreign_keys = ON").await.is_err() {
+        let _ = conn.detach();
+    }
+    result.map_err(exec_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{col, foreign_key, tests::TestDb};
+
+    fn children() -> TableCreateStatement {
+        Table::create()
+            .table(Alias::new("children"))
+            .col(col("child_id").string().not_null().primary_key())
+            .col(col("parent_id").string())
+            .foreign_key(&mut foreign_key("children", "parent_id", "parents"))
+            .to_owned()
+    }
+
+    async fn setup(name: &str) -> TestDb {
+        let db = TestDb::new(name).await;
+        db.execute(
+            "CREATE TABLE parents (parent_id TEXT PRIMARY KEY);
+            CREATE TABLE children (child_id TEXT PRIMARY KEY, parent_id TEXT);
+            CREATE INDEX idx_children_parent_id ON children (parent_id);
+            INSERT INTO parents VALUES ('1');
+            INSERT INTO children VALUES ('a', '1'), ('b', '2');",
+        )
+        .await;
+        db
+    }
+
+    async fn state(db: &TestDb) -> (usize, usize, Vec<String>, i32) {
+        let keys = db.query("PRAGMA foreign_key_list(\"children\")").await;
+        let rows = db.query("SELECT * FROM children").await;
+        let indices = db
+            .query("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'children' AND sql IS NOT NULL")
+            .await
+            .iter()
+            .map(|row| row.try_get("", "name").unwrap())
+            .collect();
+        let enforced = db.query("PRAGMA foreign_keys").await[0]
+            .try_get("", "foreign_keys")
+            .unwrap();
+        (keys.len(), rows.len(), indices, enforced)
+    }
+
+    #[tokio::test]
+    async fn a_rebuild_keeps_rows_and_indices_even_of_orphans() {
+        let db = setup("rebuild").await;
+        let manager = SchemaManager::new(&db.conn);
+        let batch = rebuild(&manager, children()).await.unwrap();
+        run(&manager, &batch).await.unwrap();
+        assert_eq!(
+            state(&db).await,
+            (1, 2, vec!["idx_children_parent_id".to_string()], 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_rebuild_changes_nothing() {
+        let db = setup("failed-rebuild").await;
+        let manager = SchemaManager::new(&db.conn);
+        let batch = rebuild(&manager, children()).await.unwrap();
+        let broken = format!("{} INSERT INTO missing VALUES (1);", batch);
+        assert!(run(&manager, &broken).await.is_err());
+        assert_eq!(
+            state(&db).await,
+            (0, 2, vec!["idx_children_parent_id".to_string()], 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn only_a_table_with_the_same_columns_is_rebuilt() {
+        let db = setup("mismatch").await;
+        let manager = SchemaManager::new(&db.conn);
+        let wider = children().col(col("extra").string()).to_owned();
+        let e = rebuild(&manager, wider).await.unwrap_err();
+        assert!(e.to_string().contains("Can't rebuild children"), "{}", e);
+    }
+}
@@ -0,0 +1,125 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::error::EnjinError;
+use crate::retry::capture_response;
+use crate::structures::*;
+use entity::applications::AppApp;
+use jsonrpsee::core::{async_trait, client::ClientT, params::ObjectParams};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Named JSON-RPC parameters, as Enjin expects them.
+pub type Params = Map<String, Value>;
+
+/// Parameters that must never end up in logs or cassettes.
+pub const SECRET_PARAMS: [&str; 3] = ["session_id", "email", "password"];
+
+/// `params` without the secret ones, for log messages.
+pub fn describe(params: &Params) -> String {
+    let shown: Params = params
+        .iter()
+        .filter(|(k, _)| !SECRET_PARAMS.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    Value::Object(shown).to_string()
+}
+
+/// Every Enjin API method encuum uses.
+///
+/// Implementors only have to provide `call`; the typed methods build the parameters and
+/// deserialize the result on top of it. That makes it easy to swap the HTTP client for
+/// something else, like a local stand-in server, a replay file or a test double.
+#[async_trait]
+pub trait EnjinApi: Send + Sync {
+    /// Makes one call and returns the raw `result` of the response.
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError>;
+
+    async fn login(&self, email: &str, password: &str) -> Result<LoginResponse, EnjinError> {
+        let params = object(json!({ "email": email, "password": password }));
+        typed(self.call("User.login", params).await?)
+    }
+
+    async fn get_categories_and_forums(
+        &self,
+        session_id: &str,
+        preset_id: &str,
+    ) -> Result<GetCafResult, EnjinError> {
+        let params = object(json!({ "session_id": session_id, "preset_id": preset_id }));
+        typed(self.call("Forum.getCategoriesAndForums", params).await?)
+    }
+
+    async fn get_forum(
+        &self,
+        session_id: &str,
+        forum_id: &str,
+        page: Option<&str>,
+    ) -> Result<GetForumResult, EnjinError> {
+        let params =
+            object(json!({ "session_id": session_id, "forum_id": forum_id, "page": page }));
+        typed(self.call("Forum.getForum", params).await?)
+    }
+
+    async fn get_thread(
+        &self,
+        session_id: &str,
+        thread_id: &str,
+        page: Option<&str>,
+    ) -> Result<GetThreadResult, EnjinError> {
+        let params =
+            object(json!({ "session_id": session_id, "thread_id": thread_id, "page": page }));
+        typed(self.call("Forum.getThread", params).await?)
+    }
+
+    async fn get_applications_list(
+        &self,
+        session_id: &str,
+        r#type: &str,
+        page: Option<u32>,
+    ) -> Result<GetApplicationsListResult, EnjinError> {
+        let params = object(json!({ "session_id": session_id, "type": r#type, "page": page }));
+        typed(self.call("Applications.getList", params).await?)
+    }
+
+    async fn get_application_types(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<HashMap<String, String>>, EnjinError> {
+        let params = object(json!({ "session_id": session_id }));
+        typed(self.call("Applications.getTypes", params).await?)
+    }
+
+    async fn get_application(
+        &self,
+        session_id: &str,
+        application_id: u32,
+    ) -> Result<AppApp, EnjinError> {
+        let params = object(json!({ "session_id": session_id, "application_id": application_id }));
+        typed(self.call("Applications.getApplication", params).await?)
+    }
+}
+
+fn object(value: Value) -> Params {
+    match value {
+        Value::Object(map) => map,
+        _ => unreachable!("params are always built from an object literal"),
+    }
+}
+
+fn typed<T: DeserializeOwned>(value: Value) -> Result<T, EnjinError> {
+    Ok(serde_json::from_value(value)?)
+}
+
+/// The default implementation: JSON-RPC over HTTP to the real site.
+#[async_trait]
+impl EnjinApi for EnjinHttpClient {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        let mut op = ObjectParams::new();
+        for (k, v) in params {
+            op.insert(&k, v)?;
+        }
+        let (outcome, response) = capture_response(self.request(method, op)).await;
+        outcome.map_err(|e| EnjinError::from_response(e, response))
+    }
+}
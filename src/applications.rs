@@ -1,154 +1,149 @@
-use crate::dumbsert;
-use crate::exposed_session;
 /// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
 /// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
 /// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
-use crate::state;
-use crate::structures::*;
+use crate::dumbsert;
+use crate::error::EnjinError;
+use crate::extractor::Extractor;
+use crate::queue::{Skipped, Unit, UnitKind};
 use entity::applications::AppApp;
 use entity::*;
-use jsonrpsee::proc_macros::rpc;
-use sea_orm::{sea_query::OnConflict, EntityTrait, Set};
-use secrecy::ExposeSecret;
-use std::collections::HashMap;
+use sea_orm::{sea_query::OnConflict, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, Set};
 use tracing::{info, warn};
 
-use crate::helpers::*;
+impl Extractor {
+    //Implement the function save_application to save an AppApp to the sqlite database using sea-orm.
+    pub async fn save_application(&self, app: &AppApp) -> anyhow::Result<()> {
+        let modd = applications::ActiveModel {
+            application_id: Set(app.application_id.clone()),
+            site_id: Set(app.site_id.clone()),
+            preset_id: Set(app.preset_id.clone()),
+            title: Set(app.title.clone()),
+            user_ip: Set(app.user_ip.clone()),
+            created: Set(app.created),
+            username: Set(app.username.clone()),
+            user_id: Set(app.user_id.clone()),
+            user_data: Set(app.user_data.clone()),
+        };
+        dumbsert!(
+            &self.conn,
+            applications::Entity,
+            &modd,
+            applications::Column::ApplicationId,
+            "Error saving application to database",
+            true
+        );
+        Ok(())
+    }
 
-#[rpc(client)]
-trait ApplicationsApi {
-    #[method(name="Applications.getList", param_kind=map)]
-    async fn get_applications_list(
-        &self,
-        session_id: &String,
-        r#type: &String,
-        page: Option<u32>,
-        site_id: Option<u32>,             //int|bool [optional]
-        application_form_id: Option<u32>, //int|bool [optional]
-        unread_only: Option<bool>,
-        search: Option<String>,
-        limit: Option<u32>,
-    ) -> Result<GetApplicationsListResult, Error>;
+    async fn crawl_app_list(&self, unit: Unit) -> anyhow::Result<()> {
+        let app_type = &unit.item_id;
+        let page = unit.page.unwrap_or(1);
+        let gar_result = self
+            .api
+            .get_applications_list(self.session()?, app_type, Some(page))
+            .await?;
+        let apps: Vec<String> = gar_result
+            .items
+            .into_iter()
+            .flatten()
+            .filter_map(|gar| gar.application_id)
+            .collect();
+        let found_some = !apps.is_empty();
+        self.enqueue(apps.iter().map(|appid| Unit::application(appid, app_type)))
+            .await?;
+        let claimed_total = match gar_result.total.and_then(|t| t.parse::<u64>().ok()) {
+            Some(t) => t,
+            None => {
+                warn!("Total applications is not a number! This is probably a bug.");
+                return Ok(());
+            }
+        };
+        //Every page so far, this run or an earlier one, queued up its applications.
+        let so_far = crawl_queue::Entity::find()
+            .filter(crawl_queue::Column::Kind.eq(UnitKind::Application.as_str()))
+            .filter(crawl_queue::Column::ParentId.eq(app_type.as_str()))
+            .count(&self.conn)
+            .await?;
+        info!(
+            "So far, got {} applications of type {}; Enjin promised us {}",
+            so_far, app_type, claimed_total
+        );
+        if so_far >= claimed_total || !found_some {
+            info!(
+                "END OF APP CATEGORY: Got {} applications of type {}; Enjin promised us {}",
+                so_far, app_type, claimed_total
+            );
+            return Ok(());
+        }
+        self.enqueue([Unit::application_list(app_type, page + 1)])
+            .await
+    }
+
+    async fn crawl_application(&self, unit: Unit) -> anyhow::Result<()> {
+        let application_id = unit.item_id.parse::<u32>().map_err(|e| {
+            let e = EnjinError::Malformed(format!("application ID `{}`: {}", unit.item_id, e));
+            Skipped::from(e)
+        })?;
+        let app = self
+            .api
+            .get_application(self.session()?, application_id)
+            .await?;
+        self.save_application(&app).await?;
+        self.stats.applications.add(1);
+        info!("Saved application {}", unit.item_id);
+        Ok(())
+    }
 
-    #[method(name="Applications.getTypes", param_kind=map)]
-    async fn get_application_types(
-        &self,
-        session_id: &String,
-    ) -> Result<Option<HashMap<String, String>>, Error>;
+    //Fetch all of the applications of every type from the website.
+    pub async fn get_apps(&self) -> anyhow::Result<()> {
+        let types = self
+            .api
+            .get_application_types(self.session()?)
+            .await?
+            .unwrap_or_else(|| {
+                warn!("Enjin sent no application types, so there are no applications to fetch.");
+                Default::default()
+            });
+        self.enqueue(types.keys().map(|t| Unit::application_list(t, 1)))
+            .await?;
+        self.crawl_apps().await
+    }
 
-    #[method(name="Applications.getApplication", param_kind=map)]
-    async fn get_application(
-        &self,
-        session_id: &String,
-        application_id: u32,
-    ) -> Result<AppApp, Error>;
+    /// Works through whatever application units are pending in the queue.
+    pub async fn crawl_apps(&self) -> anyhow::Result<()> {
+        self.crawl(UnitKind::ApplicationList, |u| self.crawl_app_list(u))
+            .await?;
+        self.crawl(UnitKind::Application, |u| self.crawl_application(u))
+            .await
+    }
 }
 
-//Implement the function save_application to save an AppApp to the sqlite database using sea-orm.
-pub async fn save_application(app: &AppApp) -> anyhow::Result<()> {
-    let modd = applications::ActiveModel {
-        application_id: Set(app.application_id.clone()),
-        site_id: Set(app.site_id.clone()),
-        preset_id: Set(app.preset_id.clone()),
-        title: Set(app.title.clone()),
-        user_ip: Set(app.user_ip.clone()),
-        created: Set(app.created.clone()),
-        username: Set(app.username.clone()),
-        user_id: Set(app.user_id.clone()),
-        user_data: Set(app.user_data.clone()),
-    };
-    dumbsert!(
-        applications::Entity,
-        &modd,
-        applications::Column::ApplicationId,
-        "Error saving application to database",
-        true
-    );
-    Ok(())
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeApi, TestExtractor};
+    use serde_json::Value;
 
-pub async fn get_app_list(types: &HashMap<String, String>) -> anyhow::Result<Vec<String>> {
-    let mut retval = vec![];
-    for (k, _v) in types {
-        //Enumerate each page of the application list and add the application ID of each item to retval.
-        let mut apps = vec![];
-        let mut claimed_total: u32 = 0;
-        let mut page = 1;
-        let mut retries: u32 = 0;
-        loop {
-            let maybe_gar_result = SEE
-                .get_applications_list(
-                    exposed_session!(),
-                    k,
-                    Some(page),
-                    None,
-                    None,
-                    None,
-                    None,
-                    None,
-                )
-                .await;
-            if let Ok(gar_result) = maybe_gar_result {
-                if let Some(gars) = gar_result.items {
-                    for gar in gars {
-                        if let Some(appid) = gar.application_id {
-                            apps.push(appid);
-                        }
-                    }
-                }
-                if gar_result.total.is_none()
-                    || gar_result.total.clone().unwrap().parse::<u32>().is_err()
-                {
-                    warn!("Total applications is not a number! This is probably a bug.");
-                    break;
-                }
-                //I'll hold you to the total you give me on the first call.
-                if page == 1 {
-                    let resp_tot = gar_result.total.unwrap().parse::<u32>().unwrap();
-                    claimed_total = resp_tot;
-                }
-                info!(
-                    "So far, got {} applications of type {}; Enjin promised us {}",
-                    apps.len(),
-                    k,
-                    claimed_total
-                );
-                if apps.len() >= claimed_total.try_into().unwrap() {
-                    info!(
-                        "END OF APP CATEGORY: Got {} applications of type {}; Enjin promised us {}",
-                        apps.len(),
-                        k,
-                        claimed_total
-                    );
-                    break;
-                }
-                page += 1;
-            } else if let Err(e) = maybe_gar_result {
-                retries += 1;
-                calculate_and_sleep(&Thing::ApplicationList, &page.to_string(), &e, &retries).await;
-                if retries > 5 {
-                    break;
-                }
-            }
-        }
-        retval.extend(apps);
+    #[tokio::test]
+    async fn a_site_without_application_types_has_no_applications_to_fetch() {
+        let api = FakeApi::new(|_, _| Ok(Value::Null));
+        let ex = TestExtractor::with_api("no-app-types", api).await;
+        ex.get_apps().await.unwrap();
+        let queued = crawl_queue::Entity::find().count(&ex.conn).await.unwrap();
+        assert_eq!(queued, 0);
     }
-    Ok(retval)
-}
 
-//Fetch all of the applications of every type from the website.
-pub async fn get_apps() -> anyhow::Result<()> {
-    let types = SEE
-        .get_application_types(exposed_session!())
-        .await?
-        .expect("No application types found - this is probably a bug");
-    let gars = get_app_list(&types).await?;
-    for gar in gars {
-        let app = SEE
-            .get_application(exposed_session!(), gar.parse::<u32>().unwrap())
-            .await?;
-        save_application(&app).await?;
-        info!("Saved application {}", gar);
+    #[tokio::test]
+    async fn an_application_id_that_isnt_a_number_is_recorded_as_failed() {
+        let api = FakeApi::new(|_, _| Ok(Value::Null));
+        let ex = TestExtractor::with_api("bad-app-id", api).await;
+        ex.enqueue([Unit::application("abc", "1"), Unit::application("", "1")])
+            .await
+            .unwrap();
+        ex.crawl_apps().await.unwrap();
+
+        let failed = failed_items::Entity::find().all(&ex.conn).await.unwrap();
+        let classes: Vec<_> = failed.iter().map(|f| f.error_class.as_str()).collect();
+        assert_eq!(classes, ["malformed", "malformed"]);
     }
-    Ok(())
 }
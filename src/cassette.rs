@@ -0,0 +1,240 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::api::{EnjinApi, Params, SECRET_PARAMS};
+use crate::error::EnjinError;
+use anyhow::Context;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use jsonrpsee::core::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+/// Stands in for the session ID in cassettes, so they can be shared without giving away a login.
+pub const CASSETTE_SESSION_ID: &str = "cassette-session";
+
+/// One call and its outcome. A cassette is a file of these as JSON lines; every line is its own
+/// gzip member, so a cassette stays readable up to the last call even if encuum is killed.
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    method: String,
+    params: BTreeMap<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<EnjinError>,
+}
+
+/// Secret parameters are never written to a cassette, and are ignored when matching calls on replay.
+fn scrub(params: &Params) -> BTreeMap<String, Value> {
+    params
+        .iter()
+        .filter(|(k, _)| !SECRET_PARAMS.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+fn key(method: &str, params: &BTreeMap<String, Value>) -> String {
+    format!("{} {}", method, serde_json::to_string(params).unwrap())
+}
+
+/// Passes every call through to `inner` and writes it to a new cassette file.
+pub struct Recorder {
+    inner: Arc<dyn EnjinApi>,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Refuses a cassette that already exists: calls from two sessions mixed up in one would be
+    /// replayed in an order neither of them made.
+    pub fn create(path: &Path, inner: Arc<dyn EnjinApi>) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| {
+                format!(
+                    "Can't create cassette {}; record to a file that doesn't exist yet",
+                    path.display()
+                )
+            })?;
+        info!("Recording API calls to {}", path.display());
+        Ok(Recorder {
+            inner,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, entry: &Entry) -> std::io::Result<()> {
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        serde_json::to_writer(&mut gz, entry)?;
+        gz.write_all(b"\n")?;
+        let bytes = gz.finish()?;
+        self.file.lock().unwrap().write_all(&bytes)
+    }
+}
+
+#[async_trait]
+impl EnjinApi for Recorder {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        let scrubbed = scrub(&params);
+        let outcome = self.inner.call(method, params).await;
+        let mut entry = Entry {
+            method: method.to_string(),
+            params: scrubbed,
+            result: None,
+            error: None,
+        };
+        match &outcome {
+            //The session ID is as good as a password; keep it out of the file.
+            Ok(_) if method == "User.login" => {
+                entry.result = Some(json!({ "session_id": CASSETTE_SESSION_ID }))
+            }
+            Ok(v) => entry.result = Some(v.clone()),
+            Err(e) => entry.error = Some(e.clone()),
+        }
+        self.write(&entry)
+            .map_err(|e| EnjinError::Other(format!("Can't write to cassette: {}", e)))?;
+        outcome
+    }
+}
+
+/// Answers calls from a cassette, without touching the network.
+///
+/// Repeated calls (retries, or the same page fetched twice) get the recorded outcomes in order;
+/// once those run out, the last one is repeated.
+pub struct Replayer {
+    entries: Mutex<HashMap<String, VecDeque<Entry>>>,
+}
+
+impl Replayer {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Can't open cassette {}", path.display()))?;
+        let mut entries: HashMap<String, VecDeque<Entry>> = HashMap::new();
+        let mut count = 0;
+        for line in BufReader::new(MultiGzDecoder::new(file)).lines() {
+            let line = line.with_context(|| format!("Can't read cassette {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line)
+                .with_context(|| format!("Bad entry in cassette {}", path.display()))?;
+            entries
+                .entry(key(&entry.method, &entry.params))
+                .or_default()
+                .push_back(entry);
+            count += 1;
+        }
+        info!("Replaying {} API calls from {}", count, path.display());
+        Ok(Replayer {
+            entries: Mutex::new(entries),
+        })
+    }
+}
+
+#[async_trait]
+impl EnjinApi for Replayer {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        if method == "User.login" {
+            return Ok(json!({ "session_id": CASSETTE_SESSION_ID }));
+        }
+        let key = key(method, &scrub(&params));
+        let mut entries = self.entries.lock().unwrap();
+        let queue = entries
+            .get_mut(&key)
+            .ok_or_else(|| EnjinError::Other(format!("Not in cassette: {}", key)))?;
+        let entry = if queue.len() > 1 {
+            queue.pop_front().unwrap()
+        } else {
+            queue.front().unwrap().clone()
+        };
+        debug!("Replaying {}", key);
+        match (entry.result, entry.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(error)) => Err(error),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeApi;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn params(value: Value) -> Params {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_recording_replays_in_order_without_its_secrets() {
+        let path = format!("encuum-test-{}.cassette.gz", std::process::id());
+        let path = Path::new(&path);
+        let _ = std::fs::remove_file(path);
+        //The first try at the thread times out; after that it has one more view each time.
+        let tries = AtomicUsize::new(0);
+        let inner = Arc::new(FakeApi::new(move |method, _| match method {
+            "User.login" => Ok(json!({ "session_id": "real-session" })),
+            _ => match tries.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(EnjinError::Timeout),
+                n => Ok(json!({ "views": n })),
+            },
+        }));
+        let thread = |session: &str| params(json!({ "session_id": session, "thread_id": "100" }));
+
+        let recorder = Recorder::create(path, inner).unwrap();
+        let login = params(json!({ "email": "alice@example.com", "password": "hunter2" }));
+        let session = recorder.call("User.login", login).await.unwrap();
+        assert_eq!(session["session_id"], "real-session");
+        for _ in 0..3 {
+            let _ = recorder
+                .call("Forum.getThread", thread("real-session"))
+                .await;
+        }
+        let e = Recorder::create(path, Arc::new(FakeApi::new(|_, _| Ok(Value::Null))));
+        assert!(e.is_err());
+        drop(recorder);
+
+        let mut recorded = String::new();
+        std::io::Read::read_to_string(
+            &mut MultiGzDecoder::new(File::open(path).unwrap()),
+            &mut recorded,
+        )
+        .unwrap();
+        for secret in ["alice@example.com", "hunter2", "real-session"] {
+            assert!(!recorded.contains(secret), "{}", secret);
+        }
+
+        let replayer = Replayer::open(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let session = replayer.call("User.login", Params::new()).await.unwrap();
+        assert_eq!(session["session_id"], CASSETTE_SESSION_ID);
+        //Matched without the session, the calls get their outcomes in order, then the last again.
+        let mut outcomes = vec![];
+        for _ in 0..4 {
+            let outcome = replayer.call("Forum.getThread", thread("other")).await;
+            outcomes.push(outcome.map_err(|e| e.class()));
+        }
+        assert_eq!(
+            outcomes,
+            [
+                Err("timeout"),
+                Ok(json!({ "views": 1 })),
+                Ok(json!({ "views": 2 })),
+                Ok(json!({ "views": 2 }))
+            ]
+        );
+        let unknown = params(json!({ "thread_id": "101" }));
+        assert!(replayer.call("Forum.getThread", unknown).await.is_err());
+    }
+}
@@ -0,0 +1,242 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use clap::{Args, Parser, Subcommand};
+use encuum::config::{ConfigSource, IdList, SiteSettings};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Everything went fine.
+pub const EXIT_SUCCESS: i32 = 0;
+/// The command failed; the reason was logged. (clap itself exits with 2 on usage errors.)
+pub const EXIT_FAILURE: i32 = 1;
+/// `verify` ran to completion but found problems in the database.
+pub const EXIT_VERIFY_FAILED: i32 = 3;
+/// The configuration couldn't be loaded or failed validation.
+pub const EXIT_BAD_CONFIG: i32 = 4;
+/// `extract` or `retry-failed` was stopped by SIGINT (Ctrl+C) or SIGTERM before it was done;
+/// running it again resumes. Same as a shell reports for a process killed by SIGINT.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+#[derive(Parser, Debug)]
+#[clap(name = "encuum", version, about = "Encuum - An Enjin Extractor")]
+pub struct Cli {
+    /// Config file to read (default: ENCUUM_CONFIG, then encuum.toml/encuum.yaml in the current directory)
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Site profile from the config file to use (default: ENCUUM_PROFILE, then `default_profile`)
+    #[clap(long, global = true)]
+    pub profile: Option<String>,
+
+    /// SQLite database file, instead of `database_file`
+    #[clap(long, global = true)]
+    pub database_file: Option<String>,
+
+    /// Log level, instead of `log_level`
+    #[clap(long, global = true)]
+    pub log_level: Option<String>,
+
+    /// Append every API call and its response to this cassette file
+    #[clap(long, global = true)]
+    pub record: Option<PathBuf>,
+
+    /// Answer API calls from this cassette file instead of contacting Enjin
+    #[clap(long, global = true)]
+    pub replay: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// The command line is the last configuration layer, on top of the config file and environment.
+    pub fn config_source(&self) -> ConfigSource {
+        let mut overrides = SiteSettings {
+            database_file: self.database_file.clone(),
+            log_level: self.log_level.clone(),
+            record: self.record.clone(),
+            replay: self.replay.clone(),
+            ..Default::default()
+        };
+        let (needs_site, needs_login) = match &self.command {
+            Command::Extract(args) => {
+                overrides.forum_ids = args.forum_ids.clone().map(IdList::List);
+                overrides.subforum_ids = args.subforum_ids.clone().map(IdList::List);
+                if args.keep_going {
+                    overrides.keep_going = Some(true);
+                }
+                if args.update {
+                    overrides.update = Some(true);
+                }
+                if args.skip_images {
+                    overrides.do_images = Some(false);
+                }
+                if args.skip_apps {
+                    overrides.do_apps = Some(false);
+                }
+                (true, true)
+            }
+            Command::RetryFailed(args) => {
+                if args.keep_going {
+                    overrides.keep_going = Some(true);
+                }
+                (true, true)
+            }
+            Command::Call(args) => (true, !args.no_session),
+            _ => (false, false),
+        };
+        ConfigSource {
+            path: self.config.clone(),
+            profile: self.profile.clone(),
+            overrides,
+            needs_site,
+            needs_login,
+            isolated: false,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Extract forums and applications from the Enjin site into the database
+    Extract(ExtractArgs),
+    /// Try the items that failed in earlier extractions again
+    RetryFailed(RetryFailedArgs),
+    /// Print how many records of each kind are stored in the database
+    Status,
+    /// Check the database for records that point at missing parents, and for threads and posts
+    /// missing compared to the totals Enjin reported
+    Verify(VerifyArgs),
+    /// Export database tables as JSON Lines files
+    Export(ExportArgs),
+    /// Search the content of stored forum posts
+    Search(SearchArgs),
+    /// Upgrade a database made by an older version of encuum, after backing it up, and print what
+    /// changed. Other commands upgrade it too, but without a backup
+    Upgrade(UpgradeArgs),
+    /// Make a single raw JSON-RPC call to the Enjin API and print the result
+    Call(CallArgs),
+    /// Serve a local stand-in for the Enjin API from a fixture directory
+    MockServer(MockServerArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    /// Comma-separated forum (preset) IDs to extract, instead of `forum_ids`
+    #[clap(long, value_delimiter = ',')]
+    pub forum_ids: Option<Vec<String>>,
+
+    /// Comma-separated subforum IDs to restrict extraction to, instead of `subforum_ids`
+    #[clap(long, value_delimiter = ',')]
+    pub subforum_ids: Option<Vec<String>>,
+
+    /// Don't extract forums
+    #[clap(long)]
+    pub skip_forums: bool,
+
+    /// Don't extract applications
+    #[clap(long)]
+    pub skip_apps: bool,
+
+    /// Don't download images found in posts
+    #[clap(long)]
+    pub skip_images: bool,
+
+    /// Skip items that keep failing instead of exiting
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// Only fetch threads that changed since the last extraction
+    #[clap(long)]
+    pub update: bool,
+
+    /// Don't extract anything; print the subforums that would be crawled and estimate how many
+    /// requests and how long it would take, from the first page of each subforum
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct RetryFailedArgs {
+    /// Skip items that keep failing instead of exiting
+    #[clap(long)]
+    pub keep_going: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Also store the missing threads and posts found in the `completeness_gaps` table, replacing
+    /// what an earlier `verify` stored there
+    #[clap(long)]
+    pub save_gaps: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// Directory to write one `<table>.jsonl` file per table into
+    #[clap(long, short)]
+    pub output: PathBuf,
+
+    /// Comma-separated tables to export (default: every table except images)
+    #[clap(long, value_delimiter = ',')]
+    pub tables: Option<Vec<String>>,
+
+    /// Leave out threads and posts that have been deleted from Enjin
+    #[clap(long)]
+    pub exclude_deleted: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    /// Text to look for in post content
+    pub pattern: String,
+
+    /// Only search posts in this thread
+    #[clap(long)]
+    pub thread_id: Option<String>,
+
+    /// Only search posts made by this username
+    #[clap(long)]
+    pub username: Option<String>,
+
+    /// Leave out posts that have been deleted from Enjin
+    #[clap(long)]
+    pub exclude_deleted: bool,
+
+    /// Maximum number of posts to print
+    #[clap(long, default_value = "50")]
+    pub limit: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct UpgradeArgs {
+    /// Don't copy the database to `<database_file>.before-upgrade-<time>` first
+    #[clap(long)]
+    pub no_backup: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CallArgs {
+    /// JSON-RPC method name, e.g. `Forum.getForum`
+    pub method: String,
+
+    /// Method parameters as a JSON object
+    #[clap(default_value = "{}")]
+    pub params: String,
+
+    /// Don't log in or add `session_id` to the parameters
+    #[clap(long)]
+    pub no_session: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct MockServerArgs {
+    /// Directory of fixtures to serve; see `encuum::mock::serve` for the layout
+    #[clap(long)]
+    pub fixtures: PathBuf,
+
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8088")]
+    pub listen: SocketAddr,
+}
@@ -0,0 +1,233 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::extractor::Extractor;
+use anyhow::{bail, Context};
+use entity::*;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbBackend, EntityTrait, JsonValue, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, Select, Statement,
+};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::info;
+
+/// Tables that `export` writes when no explicit list is given. Images are left out because they're huge.
+pub const EXPORT_TABLES: [&str; 6] = [
+    "forum_presets",
+    "category_names",
+    "subforums",
+    "forum_threads",
+    "forum_posts",
+    "applications",
+];
+
+/// A short, single-line excerpt of `content` around the first match of `pattern`.
+pub fn snippet(content: &str, pattern: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let lower = content.to_lowercase();
+    let start = lower
+        .find(&pattern.to_lowercase())
+        .map(|i| lower[..i].chars().count())
+        .unwrap_or(0)
+        .saturating_sub(40)
+        .min(chars.len());
+    let end = (start + 120).min(chars.len());
+    chars[start..end]
+        .iter()
+        .collect::<String>()
+        .replace(['\r', '\n'], " ")
+}
+
+/// Posts thread by thread, each thread in the order its pages showed them. Posts saved before
+/// encuum recorded where they were come first in their thread, by post time.
+fn posts_in_order() -> Select<forum_posts::Entity> {
+    forum_posts::Entity::find()
+        .order_by_asc(forum_posts::Column::ThreadId)
+        .order_by_asc(forum_posts::Column::ThreadPage)
+        .order_by_asc(forum_posts::Column::PagePosition)
+        .order_by_asc(forum_posts::Column::PostTime)
+}
+
+impl Extractor {
+    /// How many rows each table holds.
+    pub async fn status(&self) -> anyhow::Result<Vec<(&'static str, u64)>> {
+        let conn = &self.conn;
+        Ok(vec![
+            (
+                "forum_presets",
+                forum_presets::Entity::find().count(conn).await?,
+            ),
+            (
+                "category_names",
+                category_names::Entity::find().count(conn).await?,
+            ),
+            ("subforums", subforums::Entity::find().count(conn).await?),
+            (
+                "forum_threads",
+                forum_threads::Entity::find().count(conn).await?,
+            ),
+            (
+                "forum_posts",
+                forum_posts::Entity::find().count(conn).await?,
+            ),
+            (
+                "post_revisions",
+                post_revisions::Entity::find().count(conn).await?,
+            ),
+            ("images", images::Entity::find().count(conn).await?),
+            (
+                "applications",
+                applications::Entity::find().count(conn).await?,
+            ),
+            (
+                "failed_items",
+                failed_items::Entity::find().count(conn).await?,
+            ),
+            ("runs", runs::Entity::find().count(conn).await?),
+        ])
+    }
+
+    async fn count_rows(&self, sql: &str) -> anyhow::Result<i64> {
+        let row = self
+            .conn
+            .query_one(Statement::from_string(DbBackend::Sqlite, sql.to_owned()))
+            .await?
+            .context("COUNT query returned no rows")?;
+        Ok(row.try_get::<i64>("", "n")?)
+    }
+
+    /// Runs each consistency check and returns how many rows failed it.
+    pub async fn verify(&self) -> anyhow::Result<Vec<(&'static str, i64)>> {
+        let checks = [
+            (
+                "posts whose thread is missing",
+                "SELECT COUNT(*) AS n FROM forum_posts p LEFT JOIN forum_threads t ON p.thread_id = t.thread_id WHERE t.thread_id IS NULL",
+            ),
+            (
+                "threads whose subforum is missing",
+                "SELECT COUNT(*) AS n FROM forum_threads t LEFT JOIN subforums s ON t.forum_id = s.forum_id WHERE s.forum_id IS NULL",
+            ),
+            (
+                "subforums whose preset is missing",
+                "SELECT COUNT(*) AS n FROM subforums s LEFT JOIN forum_presets p ON s.preset_id = p.preset_id WHERE p.preset_id IS NULL",
+            ),
+        ];
+        let mut results = vec![];
+        for (what, sql) in checks {
+            results.push((what, self.count_rows(sql).await?));
+        }
+        Ok(results)
+    }
+
+    /// Every row of `table`, leaving out threads and posts marked deleted unless `include_deleted`.
+    async fn table_rows(
+        &self,
+        table: &str,
+        include_deleted: bool,
+    ) -> anyhow::Result<Vec<JsonValue>> {
+        let conn = &self.conn;
+        Ok(match table {
+            "forum_threads" if !include_deleted => {
+                forum_threads::Entity::find()
+                    .filter(forum_threads::Column::DeletedDetectedAt.is_null())
+                    .into_json()
+                    .all(conn)
+                    .await?
+            }
+            "forum_posts" if !include_deleted => {
+                posts_in_order()
+                    .filter(forum_posts::Column::DeletedDetectedAt.is_null())
+                    .into_json()
+                    .all(conn)
+                    .await?
+            }
+            "forum_presets" => forum_presets::Entity::find().into_json().all(conn).await?,
+            "category_names" => category_names::Entity::find().into_json().all(conn).await?,
+            "subforums" => subforums::Entity::find().into_json().all(conn).await?,
+            "forum_threads" => forum_threads::Entity::find().into_json().all(conn).await?,
+            "forum_posts" => posts_in_order().into_json().all(conn).await?,
+            "post_revisions" => post_revisions::Entity::find().into_json().all(conn).await?,
+            "images" => images::Entity::find().into_json().all(conn).await?,
+            "applications" => applications::Entity::find().into_json().all(conn).await?,
+            "failed_items" => failed_items::Entity::find().into_json().all(conn).await?,
+            "runs" => runs::Entity::find().into_json().all(conn).await?,
+            "completeness_gaps" => {
+                completeness_gaps::Entity::find()
+                    .into_json()
+                    .all(conn)
+                    .await?
+            }
+            _ => bail!("Unknown table: {}", table),
+        })
+    }
+
+    pub async fn export(
+        &self,
+        output: &Path,
+        tables: &[String],
+        include_deleted: bool,
+    ) -> anyhow::Result<()> {
+        create_dir_all(output).with_context(|| format!("Can't create {}", output.display()))?;
+        for table in tables {
+            let rows = self.table_rows(table, include_deleted).await?;
+            let path = output.join(format!("{}.jsonl", table));
+            let mut writer = BufWriter::new(
+                File::create(&path).with_context(|| format!("Can't create {}", path.display()))?,
+            );
+            for row in &rows {
+                serde_json::to_writer(&mut writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+            info!(
+                "Exported {} rows of {} to {}",
+                rows.len(),
+                table,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        pattern: &str,
+        thread_id: Option<&String>,
+        username: Option<&String>,
+        include_deleted: bool,
+        limit: u64,
+    ) -> anyhow::Result<Vec<forum_posts::Model>> {
+        let mut query = posts_in_order().filter(forum_posts::Column::PostContent.contains(pattern));
+        if !include_deleted {
+            query = query.filter(forum_posts::Column::DeletedDetectedAt.is_null());
+        }
+        if let Some(tid) = thread_id {
+            query = query.filter(forum_posts::Column::ThreadId.eq(tid.clone()));
+        }
+        if let Some(un) = username {
+            query = query.filter(forum_posts::Column::PostUsername.eq(un.clone()));
+        }
+        Ok(query.limit(limit).all(&self.conn).await?)
+    }
+
+    /// Makes one raw JSON-RPC call, adding our `session_id` to `params` unless `with_session` is false.
+    pub async fn call(
+        &self,
+        method: &str,
+        params: &str,
+        with_session: bool,
+    ) -> anyhow::Result<serde_json::Value> {
+        let parsed: serde_json::Value =
+            serde_json::from_str(params).context("params must be a JSON object")?;
+        let mut map = match parsed {
+            serde_json::Value::Object(m) => m,
+            _ => bail!("params must be a JSON object"),
+        };
+        if with_session && !map.contains_key("session_id") {
+            map.insert("session_id".to_string(), self.session()?.clone().into());
+        }
+        Ok(self.api.call(method, map).await?)
+    }
+}
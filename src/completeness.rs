@@ -0,0 +1,323 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::extractor::Extractor;
+use crate::queue::{UnitKind, UnitState};
+use crate::runs::now;
+use entity::{completeness_gaps, crawl_queue, failed_items};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbBackend, EntityTrait, QueryFilter, QueryResult, Set, Statement,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Fewer threads or posts stored for a preset, subforum or thread than Enjin reported.
+#[derive(Debug, Clone)]
+pub struct Gap {
+    /// `preset`, `subforum` or `thread`.
+    pub level: &'static str,
+    pub item_id: String,
+    /// The subforum of a thread, or the preset of a subforum.
+    pub parent_id: Option<String>,
+    /// `threads` or `posts`.
+    pub what: &'static str,
+    /// Not known for a thread that was listed but never fetched, on an old enough database.
+    pub expected: Option<i64>,
+    pub stored: i64,
+    /// Thread pages that the crawl queue or `failed_items` has as not done, or that none of the
+    /// stored posts came from.
+    pub missing_pages: BTreeSet<u32>,
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.level, self.item_id)?;
+        if let Some(parent) = &self.parent_id {
+            write!(f, " (in {})", parent)?;
+        }
+        match self.expected {
+            Some(expected) => write!(f, ": {} of {} {} stored", self.stored, expected, self.what)?,
+            None => write!(f, ": no {} stored", self.what)?,
+        }
+        if !self.missing_pages.is_empty() {
+            let pages: Vec<String> = self.missing_pages.iter().map(u32::to_string).collect();
+            write!(f, "; missing page {}", pages.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Every thread still on Enjin with the posts Enjin reported for it (from the thread itself, or
+/// else from its listing's reply count) and the posts we have that are still there too.
+///
+/// Threads and posts found deleted (`deleted_detected_at`) are left out throughout: Enjin's totals
+/// don't count them any more, so counting them would cover up what's really missing.
+const THREAD_TOTALS: &str =
+    "SELECT t.thread_id AS thread_id, t.forum_id AS forum_id, s.preset_id AS preset_id, \
+    COALESCE(t.total_items, t.thread_replies + 1) AS expected, \
+    (SELECT COUNT(*) FROM forum_posts p \
+        WHERE p.thread_id = t.thread_id AND p.deleted_detected_at IS NULL) AS stored \
+    FROM forum_threads t LEFT JOIN subforums s ON t.forum_id = s.forum_id \
+    WHERE t.deleted_detected_at IS NULL ORDER BY t.thread_id";
+
+/// Every preset's reported totals and how many of its threads and posts we have.
+const PRESET_TOTALS: &str = "SELECT pr.preset_id AS preset_id, \
+    pr.total_threads AS expected_threads, pr.total_posts AS expected_posts, \
+    (SELECT COUNT(*) FROM forum_threads t JOIN subforums s ON t.forum_id = s.forum_id \
+        WHERE s.preset_id = pr.preset_id AND t.deleted_detected_at IS NULL) AS stored_threads, \
+    (SELECT COUNT(*) FROM forum_posts p JOIN forum_threads t ON p.thread_id = t.thread_id \
+        JOIN subforums s ON t.forum_id = s.forum_id WHERE s.preset_id = pr.preset_id \
+        AND t.deleted_detected_at IS NULL AND p.deleted_detected_at IS NULL) AS stored_posts \
+    FROM forum_presets pr ORDER BY pr.preset_id";
+
+/// Every fetched thread's page count with each page its stored posts came from, or a null page if
+/// it has none. Threads with posts stored before their page was recorded are left out.
+const SAVED_PAGES: &str = "SELECT t.thread_id AS thread_id, t.total_pages AS total_pages, \
+    p.thread_page AS thread_page \
+    FROM forum_threads t LEFT JOIN (SELECT DISTINCT thread_id, thread_page FROM forum_posts \
+        WHERE deleted_detected_at IS NULL) p ON p.thread_id = t.thread_id \
+    WHERE t.total_pages IS NOT NULL AND t.deleted_detected_at IS NULL \
+    AND NOT EXISTS (SELECT 1 FROM forum_posts q WHERE q.thread_id = t.thread_id \
+        AND q.thread_page IS NULL AND q.deleted_detected_at IS NULL)";
+
+impl Extractor {
+    async fn query_all(&self, sql: &str) -> anyhow::Result<Vec<QueryResult>> {
+        Ok(self
+            .conn
+            .query_all(Statement::from_string(DbBackend::Sqlite, sql.to_owned()))
+            .await?)
+    }
+
+    /// Thread pages that were queued but never saved, or that Enjin counted but none of the stored
+    /// posts came from, by thread.
+    async fn unsaved_thread_pages(&self) -> anyhow::Result<BTreeMap<String, BTreeSet<u32>>> {
+        let mut pages: BTreeMap<String, BTreeSet<u32>> = BTreeMap::new();
+        let queued = crawl_queue::Entity::find()
+            .filter(crawl_queue::Column::Kind.eq(UnitKind::ThreadPage.as_str()))
+            .filter(crawl_queue::Column::State.ne(UnitState::Done.as_str()))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|u| (u.item_id, u.page));
+        //The queue is cleared when a new extraction starts, but failures are kept until retried.
+        let failed = failed_items::Entity::find()
+            .filter(failed_items::Column::Kind.eq(UnitKind::ThreadPage.as_str()))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|f| (f.item_id, f.page));
+        for (thread_id, page) in queued.chain(failed) {
+            pages
+                .entry(thread_id)
+                .or_default()
+                .insert(page.unwrap_or(1) as u32);
+        }
+        let mut saved: BTreeMap<String, (i64, BTreeSet<i64>)> = BTreeMap::new();
+        for row in self.query_all(SAVED_PAGES).await? {
+            let thread_id: String = row.try_get("", "thread_id")?;
+            let total_pages: i64 = row.try_get("", "total_pages")?;
+            let page: Option<i64> = row.try_get("", "thread_page")?;
+            let (_, pages) = saved
+                .entry(thread_id)
+                .or_insert((total_pages, BTreeSet::new()));
+            pages.extend(page);
+        }
+        for (thread_id, (total_pages, stored)) in saved {
+            let missing: Vec<u32> = (1..=total_pages)
+                .filter(|page| !stored.contains(page))
+                .map(|page| page as u32)
+                .collect();
+            if !missing.is_empty() {
+                pages.entry(thread_id).or_default().extend(missing);
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Compares the stored threads and posts of every preset, subforum and thread with the totals
+    /// Enjin reported for them, and returns where we fall short.
+    ///
+    /// A preset's totals cover all of its subforums, so leaving some out with `subforum_ids` shows
+    /// up here as a gap. Enjin has no totals for a subforum, so a subforum's are those of all its
+    /// threads, and it's reported when they fall short; pages missing from a thread whose posts
+    /// add up are reported with the thread alone.
+    pub async fn completeness(&self) -> anyhow::Result<Vec<Gap>> {
+        let mut gaps = vec![];
+        for row in self.query_all(PRESET_TOTALS).await? {
+            let preset_id: String = row.try_get("", "preset_id")?;
+            for what in ["threads", "posts"] {
+                let expected: i64 = row.try_get("", &format!("expected_{}", what))?;
+                let stored: i64 = row.try_get("", &format!("stored_{}", what))?;
+                if stored < expected {
+                    gaps.push(Gap {
+                        level: "preset",
+                        item_id: preset_id.clone(),
+                        parent_id: None,
+                        what,
+                        expected: Some(expected),
+                        stored,
+                        missing_pages: BTreeSet::new(),
+                    });
+                }
+            }
+        }
+
+        let mut unsaved = self.unsaved_thread_pages().await?;
+        let mut subforums: BTreeMap<String, Gap> = BTreeMap::new();
+        let mut threads = vec![];
+        for row in self.query_all(THREAD_TOTALS).await? {
+            let thread_id: String = row.try_get("", "thread_id")?;
+            let forum_id: String = row.try_get("", "forum_id")?;
+            let expected: Option<i64> = row.try_get("", "expected")?;
+            let stored: i64 = row.try_get("", "stored")?;
+            let missing_pages = unsaved.remove(&thread_id).unwrap_or_default();
+            let short = match expected {
+                Some(expected) => stored < expected,
+                None => stored == 0,
+            };
+            let subforum = subforums.entry(forum_id.clone()).or_insert(Gap {
+                level: "subforum",
+                item_id: forum_id.clone(),
+                parent_id: row.try_get("", "preset_id")?,
+                what: "posts",
+                expected: Some(0),
+                stored: 0,
+                missing_pages: BTreeSet::new(),
+            });
+            subforum.expected = subforum.expected.map(|e| e + expected.unwrap_or(stored));
+            subforum.stored += stored;
+            if !short && missing_pages.is_empty() {
+                continue;
+            }
+            threads.push(Gap {
+                level: "thread",
+                item_id: thread_id,
+                parent_id: Some(forum_id),
+                what: "posts",
+                expected,
+                stored,
+                missing_pages,
+            });
+        }
+        gaps.extend(
+            subforums
+                .into_values()
+                .filter(|s| s.expected.is_some_and(|e| s.stored < e)),
+        );
+        gaps.extend(threads);
+        Ok(gaps)
+    }
+
+    /// Replaces the contents of the `completeness_gaps` table with `gaps`.
+    pub async fn save_gaps(&self, gaps: &[Gap]) -> anyhow::Result<()> {
+        let checked_at = now();
+        completeness_gaps::Entity::delete_many()
+            .exec(&self.conn)
+            .await?;
+        let models: Vec<completeness_gaps::ActiveModel> = gaps
+            .iter()
+            .map(|gap| completeness_gaps::ActiveModel {
+                checked_at: Set(checked_at),
+                level: Set(gap.level.to_string()),
+                item_id: Set(gap.item_id.clone()),
+                parent_id: Set(gap.parent_id.clone()),
+                what: Set(gap.what.to_string()),
+                expected: Set(gap.expected),
+                stored: Set(gap.stored),
+                missing_pages: Set((!gap.missing_pages.is_empty()).then(|| {
+                    let pages: Vec<String> = gap.missing_pages.iter().map(u32::to_string).collect();
+                    pages.join(",")
+                })),
+                ..Default::default()
+            })
+            .collect();
+        //SQLite caps how many values one statement can bind.
+        for chunk in models.chunks(100) {
+            completeness_gaps::Entity::insert_many(chunk.to_vec())
+                .exec(&self.conn)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestExtractor;
+    use sea_orm::ConnectionTrait;
+    use serde_json::{json, Value};
+
+    async fn gaps(ex: &TestExtractor) -> Vec<String> {
+        let gaps = ex.completeness().await.unwrap();
+        gaps.iter().map(|gap| gap.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn a_complete_archive_has_no_gaps() {
+        let ex = TestExtractor::new("complete").await;
+        ex.crawl_example(|_, _| {}).await;
+        assert_eq!(gaps(&ex).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn a_page_none_of_the_stored_posts_came_from_is_missing() {
+        let ex = TestExtractor::new("unsaved-page").await;
+        //Page 2 of thread 100 went missing without its unit failing, and Enjin undercounted the
+        //thread, so only the page shows it.
+        ex.crawl_example(|path, value| match path {
+            "threads/100/1.json" => value["total_items"] = json!("2"),
+            "threads/100/2.json" => *value = Value::Null,
+            _ => {}
+        })
+        .await;
+        assert_eq!(
+            gaps(&ex).await,
+            [
+                "preset 1: 4 of 5 posts stored",
+                "thread 100 (in 10): 2 of 2 posts stored; missing page 2"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn threads_and_posts_found_deleted_dont_count() {
+        let ex = TestExtractor::new("deleted-gaps").await;
+        ex.crawl_example(|_, _| {}).await;
+        ex.conn
+            .execute_unprepared(
+                "UPDATE forum_threads SET deleted_detected_at = '2023-06-01T00:00:00+00:00' WHERE thread_id = '101';
+                UPDATE forum_posts SET deleted_detected_at = '2023-06-01T00:00:00+00:00' WHERE post_id = '1004';",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            gaps(&ex).await,
+            [
+                "preset 1: 2 of 3 threads stored",
+                "preset 1: 3 of 5 posts stored",
+                "subforum 12 (in 1): 0 of 1 posts stored",
+                "thread 102 (in 12): 0 of 1 posts stored; missing page 1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_subforum_is_compared_on_all_its_threads() {
+        let ex = TestExtractor::new("subforum-gaps").await;
+        ex.crawl_example(|path, value| {
+            if path == "threads/101/1.json" {
+                *value = Value::Null;
+            }
+        })
+        .await;
+        //Thread 100 is whole, but still counts towards subforum 10.
+        assert_eq!(
+            gaps(&ex).await,
+            [
+                "preset 1: 4 of 5 posts stored",
+                "subforum 10 (in 1): 3 of 4 posts stored",
+                "thread 101 (in 10): 0 of 1 posts stored"
+            ]
+        );
+    }
+}
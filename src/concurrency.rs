@@ -0,0 +1,112 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::api::{EnjinApi, Params};
+use crate::error::EnjinError;
+use jsonrpsee::core::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// How many API calls may be in flight at once: `global` across all methods, and optionally fewer
+/// for some methods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConcurrencyConfig {
+    pub global: usize,
+    /// Keyed by method name, like `Forum.getThread`.
+    pub methods: HashMap<String, usize>,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        ConcurrencyConfig {
+            global: 4,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    /// The in-flight limit for `method`, which is never more than the global one.
+    pub fn for_method(&self, method: &str) -> usize {
+        self.methods
+            .get(method)
+            .map_or(self.global, |&n| n.min(self.global))
+    }
+}
+
+/// Holds every call to `inner` until there's room for it under a `ConcurrencyConfig`.
+///
+/// A call keeps its slot while it's being retried, so backing off after a 429 slows everything
+/// down instead of letting the next queued call straight in.
+pub struct Limited {
+    inner: Arc<dyn EnjinApi>,
+    global: Semaphore,
+    methods: HashMap<String, Semaphore>,
+}
+
+impl Limited {
+    pub fn new(inner: Arc<dyn EnjinApi>, config: &ConcurrencyConfig) -> Self {
+        Limited {
+            inner,
+            global: Semaphore::new(config.global),
+            methods: config
+                .methods
+                .keys()
+                .map(|m| (m.clone(), Semaphore::new(config.for_method(m))))
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl EnjinApi for Limited {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        //Take the narrower permit first, so a call waiting on its method doesn't hog a global slot.
+        let _method_permit = match self.methods.get(method) {
+            Some(s) => Some(s.acquire().await.expect("semaphore is never closed")),
+            None => None,
+        };
+        let _global_permit = self
+            .global
+            .acquire()
+            .await
+            .expect("semaphore is never closed");
+        self.inner.call(method, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeApi;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn calls_wait_for_room_under_the_global_cap_and_their_methods() {
+        let api = Arc::new(FakeApi::new(|_, _| Ok(Value::Null)).slow(Duration::from_millis(20)));
+        let config = ConcurrencyConfig {
+            global: 3,
+            methods: HashMap::from([
+                ("Forum.getThread".to_string(), 1),
+                ("Forum.getForum".to_string(), 10),
+            ]),
+        };
+        let limited = Limited::new(api.clone(), &config);
+        let methods = ["Forum.getThread", "Forum.getForum", "Applications.getList"];
+        let calls = methods
+            .iter()
+            .cycle()
+            .take(18)
+            .map(|m| limited.call(m, Params::new()));
+        futures::future::join_all(calls).await;
+
+        let most = api.most_in_flight.lock().unwrap();
+        assert_eq!(most["Forum.getThread"], 1);
+        //A method's own cap can't lift it over the global one.
+        assert_eq!(config.for_method("Forum.getForum"), 3);
+        assert_eq!(most[""], 3);
+        assert_eq!(api.calls.lock().unwrap().len(), 18);
+    }
+}
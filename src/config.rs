@@ -0,0 +1,946 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::concurrency::ConcurrencyConfig;
+use crate::retry::{RetryConfig, RetryPolicy};
+use secrecy::SecretString;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Config files we look for in the current directory when `--config`/`ENCUUM_CONFIG` aren't given.
+pub const DEFAULT_CONFIG_FILES: [&str; 3] = ["encuum.toml", "encuum.yaml", "encuum.yml"];
+
+pub const LOG_LEVELS: [&str; 5] = ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+/// A list of IDs, written either as a list or as a single comma-separated string.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum IdList {
+    List(Vec<String>),
+    Csv(String),
+}
+
+impl IdList {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            IdList::List(v) => v,
+            IdList::Csv(s) => split_ids(&s),
+        }
+    }
+}
+
+fn split_ids(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// Retry settings for one method, or for all of them. Anything left out comes from the layer below,
+/// or from `RetryPolicy::default()`.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RetryOverride {
+    pub max_attempts: Option<u32>,
+    pub base_delay_secs: Option<f64>,
+    pub max_delay_secs: Option<f64>,
+    pub jitter: Option<f64>,
+}
+
+/// The `[retry]` table: the settings for every method, plus `[retry.methods."Forum.getThread"]`-style overrides.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySettings {
+    pub max_attempts: Option<u32>,
+    pub base_delay_secs: Option<f64>,
+    pub max_delay_secs: Option<f64>,
+    pub jitter: Option<f64>,
+    #[serde(default)]
+    pub methods: BTreeMap<String, RetryOverride>,
+}
+
+/// The `[concurrency]` table: how many API calls may be in flight at once, overall and per method.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConcurrencySettings {
+    pub max_in_flight: Option<usize>,
+    /// Lower limits for some methods, like `"Forum.getThread" = 2`.
+    #[serde(default)]
+    pub methods: BTreeMap<String, usize>,
+}
+
+/// One layer of settings. Every layer (file defaults, profile, environment, command line) has this shape;
+/// later layers override the fields they set.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SiteSettings {
+    pub website: Option<String>,
+    pub endpoint: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<SecretString>,
+    pub session_id: Option<SecretString>,
+    pub database_file: Option<String>,
+    pub forum_ids: Option<IdList>,
+    pub subforum_ids: Option<IdList>,
+    pub keep_going: Option<bool>,
+    pub update: Option<bool>,
+    pub do_images: Option<bool>,
+    pub do_apps: Option<bool>,
+    pub proxy: Option<String>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub log_level: Option<String>,
+    pub log_file: Option<String>,
+    pub sanitize_log: Option<bool>,
+    pub requests_per_second: Option<f64>,
+    pub burst: Option<u32>,
+    pub retry: Option<RetrySettings>,
+    pub concurrency: Option<ConcurrencySettings>,
+}
+
+macro_rules! merge_fields {
+    ($dst:expr, $src:expr, $($field:ident),+) => {
+        $(
+            if $src.$field.is_some() {
+                $dst.$field = $src.$field;
+            }
+        )+
+    };
+}
+
+impl RetryOverride {
+    pub fn merge(&mut self, over: RetryOverride) {
+        merge_fields!(
+            self,
+            over,
+            max_attempts,
+            base_delay_secs,
+            max_delay_secs,
+            jitter
+        );
+    }
+}
+
+impl RetrySettings {
+    pub fn merge(&mut self, over: RetrySettings) {
+        merge_fields!(
+            self,
+            over,
+            max_attempts,
+            base_delay_secs,
+            max_delay_secs,
+            jitter
+        );
+        for (method, o) in over.methods {
+            self.methods.entry(method).or_default().merge(o);
+        }
+    }
+
+    fn defaults(&self) -> RetryOverride {
+        RetryOverride {
+            max_attempts: self.max_attempts,
+            base_delay_secs: self.base_delay_secs,
+            max_delay_secs: self.max_delay_secs,
+            jitter: self.jitter,
+        }
+    }
+}
+
+impl ConcurrencySettings {
+    pub fn merge(&mut self, over: ConcurrencySettings) {
+        merge_fields!(self, over, max_in_flight);
+        self.methods.extend(over.methods);
+    }
+}
+
+impl SiteSettings {
+    pub fn merge(&mut self, over: SiteSettings) {
+        merge_fields!(
+            self,
+            over,
+            website,
+            endpoint,
+            email,
+            password,
+            session_id,
+            database_file,
+            forum_ids,
+            subforum_ids,
+            keep_going,
+            update,
+            do_images,
+            do_apps,
+            proxy,
+            record,
+            replay,
+            log_level,
+            log_file,
+            sanitize_log,
+            requests_per_second,
+            burst
+        );
+        if let Some(over_retry) = over.retry {
+            self.retry
+                .get_or_insert_with(Default::default)
+                .merge(over_retry);
+        }
+        if let Some(over_concurrency) = over.concurrency {
+            self.concurrency
+                .get_or_insert_with(Default::default)
+                .merge(over_concurrency);
+        }
+    }
+
+    /// Reads the `ENCUUM_`-prefixed environment variables (including ones from `.env`).
+    /// Values that can't be parsed are reported in `problems` rather than aborting, and so are
+    /// settings only given under their old unprefixed names.
+    pub fn from_env(problems: &mut Vec<String>) -> SiteSettings {
+        check_unprefixed(|name| dotenvy::var(name).ok(), problems);
+        let string = |name: &str| dotenvy::var(format!("ENCUUM_{}", name)).ok();
+        let mut boolean = |name: &str| -> Option<bool> {
+            let raw = string(name)?;
+            match raw.trim().to_lowercase().as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => {
+                    problems.push(format!(
+                        "ENCUUM_{}: expected `true` or `false`, got `{}`",
+                        name, raw
+                    ));
+                    None
+                }
+            }
+        };
+        let mut settings = SiteSettings {
+            keep_going: boolean("KEEP_GOING"),
+            update: boolean("UPDATE"),
+            do_images: boolean("DO_IMAGES"),
+            do_apps: boolean("DO_APPS"),
+            sanitize_log: boolean("SANITIZE_LOG"),
+            website: string("WEBSITE"),
+            endpoint: string("ENDPOINT"),
+            email: string("EMAIL"),
+            password: string("PASSWORD").map(SecretString::new),
+            session_id: string("SESSION_ID").map(SecretString::new),
+            database_file: string("DATABASE_FILE"),
+            forum_ids: string("FORUM_IDS").map(IdList::Csv),
+            subforum_ids: string("SUBFORUM_IDS").map(IdList::Csv),
+            proxy: string("PROXY"),
+            record: string("RECORD").map(PathBuf::from),
+            replay: string("REPLAY").map(PathBuf::from),
+            log_level: string("LOG_LEVEL"),
+            log_file: string("LOG_FILE"),
+            requests_per_second: None,
+            burst: None,
+            retry: None,
+            concurrency: None,
+        };
+        settings.requests_per_second = number("REQUESTS_PER_SECOND", problems);
+        settings.burst = number("BURST", problems);
+        let retry = RetrySettings {
+            max_attempts: number("RETRY_MAX_ATTEMPTS", problems),
+            base_delay_secs: number("RETRY_BASE_DELAY_SECS", problems),
+            max_delay_secs: number("RETRY_MAX_DELAY_SECS", problems),
+            jitter: number("RETRY_JITTER", problems),
+            methods: BTreeMap::new(),
+        };
+        if retry.defaults() != RetryOverride::default() {
+            settings.retry = Some(retry);
+        }
+        if let Some(n) = number("CONCURRENCY_MAX_IN_FLIGHT", problems) {
+            settings.concurrency = Some(ConcurrencySettings {
+                max_in_flight: Some(n),
+                methods: BTreeMap::new(),
+            });
+        }
+        settings
+    }
+}
+
+/// The settings encuum read from the environment (or `.env`) before they took the `ENCUUM_` prefix.
+const UNPREFIXED: [&str; 14] = [
+    "website",
+    "proxy",
+    "email",
+    "password",
+    "session_id",
+    "database_file",
+    "forum_ids",
+    "subforum_ids",
+    "keep_going",
+    "do_images",
+    "do_apps",
+    "sanitize_log",
+    "log_file",
+    "log_level",
+];
+
+/// Reports each setting that `var` only has under its old name, which isn't read anymore.
+fn check_unprefixed(var: impl Fn(&str) -> Option<String>, problems: &mut Vec<String>) {
+    for name in UNPREFIXED {
+        let prefixed = format!("ENCUUM_{}", name.to_uppercase());
+        if var(name).is_some() && var(&prefixed).is_none() {
+            problems.push(format!(
+                "`{}` is now `{}`; rename it in your environment or .env file",
+                name, prefixed
+            ));
+        }
+    }
+}
+
+fn number<T: FromStr>(name: &str, problems: &mut Vec<String>) -> Option<T> {
+    let raw = dotenvy::var(format!("ENCUUM_{}", name)).ok()?;
+    match raw.trim().parse() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            problems.push(format!("ENCUUM_{}: expected a number, got `{}`", name, raw));
+            None
+        }
+    }
+}
+
+/// The on-disk config file: shared `defaults` plus any number of named site `profiles`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub defaults: SiteSettings,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, SiteSettings>,
+}
+
+impl ConfigFile {
+    pub fn read(path: &Path) -> Result<ConfigFile, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            ConfigError::single(format!("Can't read config file {}: {}", path.display(), e))
+        })?;
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let parsed = if ext == "yaml" || ext == "yml" {
+            serde_yaml::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| ConfigError::single(format!("{}: {}", path.display(), e)))
+    }
+}
+
+/// Where to find the config file and which profile to use, plus the command-line override layer.
+#[derive(Debug, Default)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub overrides: SiteSettings,
+    /// Whether the command talks to Enjin, which makes `website` mandatory unless `endpoint` is set.
+    pub needs_site: bool,
+    /// Whether the command logs in, which makes the credentials mandatory.
+    pub needs_login: bool,
+    /// Whether to go by `path`, `profile` and `overrides` alone, without looking for a config file or
+    /// reading `ENCUUM_` variables and `.env`. Tests use this so a developer's own setup can't leak in.
+    pub isolated: bool,
+}
+
+/// Every problem found while loading the configuration, so they can all be fixed in one go.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl ConfigError {
+    fn single(problem: String) -> Self {
+        ConfigError {
+            problems: vec![problem],
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for p in &self.problems {
+            writeln!(f, "  - {}", p)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The fully merged and validated settings for one site.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub profile: String,
+    pub website: String,
+    /// The JSON-RPC URL to call. Defaults to the site's own API; set it to use something like the mock server instead.
+    pub endpoint: String,
+    pub email: String,
+    pub password: SecretString,
+    pub session_id: Option<SecretString>,
+    pub database_file: String,
+    pub forum_ids: Option<Vec<String>>,
+    pub subforum_ids: Option<Vec<String>>,
+    pub keep_going: bool,
+    /// Only fetch threads whose reply count or last post changed since they were stored.
+    pub update: bool,
+    pub do_images: bool,
+    pub do_apps: bool,
+    pub proxy: Option<String>,
+    /// Cassette file to append every API call to.
+    pub record: Option<PathBuf>,
+    /// Cassette file to answer API calls from, instead of the network.
+    pub replay: Option<PathBuf>,
+    pub log_level: String,
+    pub log_file: Option<String>,
+    pub sanitize_log: bool,
+    /// The most requests (API calls and image downloads together) to send per second; 0 means no limit.
+    pub requests_per_second: f64,
+    /// How many requests can go out at once after a quiet spell, before `requests_per_second` kicks in.
+    pub burst: u32,
+    pub retry: RetryConfig,
+    pub concurrency: ConcurrencyConfig,
+}
+
+fn check_ids(name: &str, ids: &Option<Vec<String>>, problems: &mut Vec<String>) {
+    for id in ids.iter().flatten() {
+        if id.parse::<u64>().is_err() {
+            problems.push(format!("{}: `{}` is not a numeric ID", name, id));
+        }
+    }
+}
+
+fn retry_policy(
+    name: &str,
+    base: &RetryPolicy,
+    o: &RetryOverride,
+    problems: &mut Vec<String>,
+) -> RetryPolicy {
+    let secs = |field: &str, value: Option<f64>, default: Duration, problems: &mut Vec<String>| {
+        match value {
+            Some(v) if v.is_finite() && v >= 0.0 => Duration::from_secs_f64(v),
+            Some(v) => {
+                problems.push(format!(
+                    "{}.{}: must be zero or more, got {}",
+                    name, field, v
+                ));
+                default
+            }
+            None => default,
+        }
+    };
+    let policy = RetryPolicy {
+        max_attempts: o.max_attempts.unwrap_or(base.max_attempts),
+        base_delay: secs(
+            "base_delay_secs",
+            o.base_delay_secs,
+            base.base_delay,
+            problems,
+        ),
+        max_delay: secs("max_delay_secs", o.max_delay_secs, base.max_delay, problems),
+        jitter: o.jitter.unwrap_or(base.jitter),
+    };
+    if policy.max_attempts == 0 {
+        problems.push(format!("{}.max_attempts: must be at least 1", name));
+    }
+    if !(0.0..=1.0).contains(&policy.jitter) {
+        problems.push(format!(
+            "{}.jitter: must be between 0 and 1, got {}",
+            name, policy.jitter
+        ));
+    }
+    if policy.base_delay > policy.max_delay {
+        problems.push(format!(
+            "{}: base_delay_secs can't be more than max_delay_secs",
+            name
+        ));
+    }
+    policy
+}
+
+fn concurrency_config(
+    settings: ConcurrencySettings,
+    problems: &mut Vec<String>,
+) -> ConcurrencyConfig {
+    let global = settings
+        .max_in_flight
+        .unwrap_or(ConcurrencyConfig::default().global);
+    if global == 0 {
+        problems.push("concurrency.max_in_flight: must be at least 1".to_string());
+    }
+    for (method, n) in &settings.methods {
+        if *n == 0 {
+            problems.push(format!(
+                "concurrency.methods.\"{}\": must be at least 1",
+                method
+            ));
+        }
+    }
+    ConcurrencyConfig {
+        global,
+        methods: settings.methods.into_iter().collect(),
+    }
+}
+
+fn retry_config(settings: RetrySettings, problems: &mut Vec<String>) -> RetryConfig {
+    let default = retry_policy(
+        "retry",
+        &RetryPolicy::default(),
+        &settings.defaults(),
+        problems,
+    );
+    let methods = settings
+        .methods
+        .iter()
+        .map(|(method, o)| {
+            let name = format!("retry.methods.\"{}\"", method);
+            (method.clone(), retry_policy(&name, &default, o, problems))
+        })
+        .collect();
+    RetryConfig { default, methods }
+}
+
+impl Config {
+    pub fn load(source: ConfigSource) -> Result<Config, ConfigError> {
+        let mut problems = vec![];
+
+        let env = |name: &str| match source.isolated {
+            true => None,
+            false => dotenvy::var(name).ok(),
+        };
+        let path = source
+            .path
+            .or_else(|| env("ENCUUM_CONFIG").map(PathBuf::from))
+            .or_else(|| {
+                DEFAULT_CONFIG_FILES
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|p| !source.isolated && p.exists())
+            });
+        let file = match &path {
+            Some(p) => ConfigFile::read(p)?,
+            None => ConfigFile::default(),
+        };
+
+        let profile = source
+            .profile
+            .or_else(|| env("ENCUUM_PROFILE"))
+            .or(file.default_profile.clone())
+            .or_else(|| match file.profiles.len() {
+                1 => file.profiles.keys().next().cloned(),
+                _ => None,
+            });
+
+        let mut settings = file.defaults.clone();
+        let profile_name = match profile {
+            Some(name) => {
+                match file.profiles.get(&name) {
+                    Some(p) => settings.merge(p.clone()),
+                    None => problems.push(format!(
+                        "Profile `{}` not found; available profiles: {}",
+                        name,
+                        file.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                    )),
+                }
+                name
+            }
+            None => {
+                if !file.profiles.is_empty() {
+                    problems.push(format!(
+                        "Several profiles are defined ({}); pick one with --profile, ENCUUM_PROFILE or default_profile",
+                        file.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                    ));
+                }
+                "default".to_string()
+            }
+        };
+        if !source.isolated {
+            settings.merge(SiteSettings::from_env(&mut problems));
+        }
+        settings.merge(source.overrides);
+
+        let website = settings.website.unwrap_or_default();
+        if let Some(endpoint) = &settings.endpoint {
+            if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+                problems.push(format!(
+                    "endpoint must be a URL like `http://127.0.0.1:8088`, got `{}`",
+                    endpoint
+                ));
+            }
+        }
+        //Replaying needs neither a site nor a login.
+        let replaying = settings.replay.is_some();
+        if replaying && settings.record.is_some() {
+            problems.push("record and replay can't be used together".to_string());
+        }
+        if source.needs_site && settings.endpoint.is_none() && !replaying {
+            if website.is_empty() {
+                problems.push("website is required".to_string());
+            } else if website.contains("://") || website.contains('/') {
+                problems.push(format!(
+                    "website must be just a domain like `www.enjin.com`, got `{}`",
+                    website
+                ));
+            }
+        }
+        if source.needs_login && settings.session_id.is_none() && !replaying {
+            if settings.email.is_none() {
+                problems.push("email is required unless session_id is set".to_string());
+            }
+            if settings.password.is_none() {
+                problems.push("password is required unless session_id is set".to_string());
+            }
+        }
+
+        let database_file = settings.database_file.unwrap_or_default();
+        if database_file.is_empty() {
+            problems.push("database_file is required".to_string());
+        }
+
+        let forum_ids = settings.forum_ids.map(IdList::into_vec);
+        let subforum_ids = settings.subforum_ids.map(IdList::into_vec);
+        check_ids("forum_ids", &forum_ids, &mut problems);
+        check_ids("subforum_ids", &subforum_ids, &mut problems);
+
+        let log_level = settings
+            .log_level
+            .unwrap_or("INFO".to_string())
+            .to_uppercase();
+        if !LOG_LEVELS.contains(&log_level.as_str()) {
+            problems.push(format!(
+                "log_level must be one of {}, got `{}`",
+                LOG_LEVELS.join(", "),
+                log_level
+            ));
+        }
+
+        if let Some(proxy) = &settings.proxy {
+            if !(proxy.starts_with("http://")
+                || proxy.starts_with("https://")
+                || proxy.starts_with("socks5://"))
+            {
+                problems.push(format!(
+                    "proxy must be a URL like `http://127.0.0.1:9091`, got `{}`",
+                    proxy
+                ));
+            }
+        }
+
+        let requests_per_second = settings.requests_per_second.unwrap_or(5.0);
+        if !requests_per_second.is_finite() || requests_per_second < 0.0 {
+            problems.push(format!(
+                "requests_per_second must be zero (no limit) or more, got {}",
+                requests_per_second
+            ));
+        }
+        let burst = settings.burst.unwrap_or(10);
+        if burst == 0 {
+            problems.push("burst must be at least 1".to_string());
+        }
+
+        let retry = retry_config(settings.retry.unwrap_or_default(), &mut problems);
+        let concurrency =
+            concurrency_config(settings.concurrency.unwrap_or_default(), &mut problems);
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
+        let endpoint = settings
+            .endpoint
+            .unwrap_or(format!("https://{}:443/api/v1/api.php", website));
+
+        Ok(Config {
+            profile: profile_name,
+            website,
+            endpoint,
+            email: settings.email.unwrap_or_default(),
+            password: settings
+                .password
+                .unwrap_or(SecretString::new(String::new())),
+            session_id: settings.session_id,
+            database_file,
+            forum_ids,
+            subforum_ids,
+            keep_going: settings.keep_going.unwrap_or(false),
+            update: settings.update.unwrap_or(false),
+            do_images: settings.do_images.unwrap_or(true),
+            do_apps: settings.do_apps.unwrap_or(true),
+            proxy: settings.proxy,
+            record: settings.record,
+            replay: settings.replay,
+            log_level,
+            log_file: settings.log_file,
+            sanitize_log: settings.sanitize_log.unwrap_or(false),
+            requests_per_second,
+            burst,
+            retry,
+            concurrency,
+        })
+    }
+
+    /// The settings that decide what gets extracted and how, as JSON for the `runs` table. The
+    /// password, session ID and email are left out, and so is the proxy, which may embed credentials.
+    pub fn settings(&self) -> serde_json::Value {
+        fn policy(p: &RetryPolicy) -> serde_json::Value {
+            json!({
+                "max_attempts": p.max_attempts,
+                "base_delay_secs": p.base_delay.as_secs_f64(),
+                "max_delay_secs": p.max_delay.as_secs_f64(),
+                "jitter": p.jitter,
+            })
+        }
+        let retry_methods: BTreeMap<&String, serde_json::Value> = self
+            .retry
+            .methods
+            .iter()
+            .map(|(m, p)| (m, policy(p)))
+            .collect();
+        let concurrency_methods: BTreeMap<&String, &usize> =
+            self.concurrency.methods.iter().collect();
+        json!({
+            "profile": self.profile,
+            "website": self.website,
+            "endpoint": self.endpoint,
+            "database_file": self.database_file,
+            "forum_ids": self.forum_ids,
+            "subforum_ids": self.subforum_ids,
+            "keep_going": self.keep_going,
+            "update": self.update,
+            "do_images": self.do_images,
+            "do_apps": self.do_apps,
+            "proxy": self.proxy.is_some(),
+            "record": self.record,
+            "replay": self.replay,
+            "requests_per_second": self.requests_per_second,
+            "burst": self.burst,
+            "retry": { "default": policy(&self.retry.default), "methods": retry_methods },
+            "concurrency": {
+                "max_in_flight": self.concurrency.global,
+                "methods": concurrency_methods,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(overrides: SiteSettings) -> Result<Config, ConfigError> {
+        Config::load(ConfigSource {
+            overrides,
+            needs_site: true,
+            needs_login: true,
+            isolated: true,
+            ..Default::default()
+        })
+    }
+
+    fn valid() -> SiteSettings {
+        SiteSettings {
+            website: Some("www.example.com".to_string()),
+            email: Some("someone@example.com".to_string()),
+            password: Some(SecretString::new("hunter2".to_string())),
+            database_file: Some("example.db".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn a_valid_configuration_gets_the_defaults() {
+        let config = load(SiteSettings {
+            forum_ids: Some(IdList::Csv(" 1, 2,,3 ".to_string())),
+            ..valid()
+        })
+        .unwrap();
+        assert_eq!(config.profile, "default");
+        assert_eq!(
+            config.endpoint,
+            "https://www.example.com:443/api/v1/api.php"
+        );
+        assert_eq!(
+            config.forum_ids,
+            Some(vec!["1".into(), "2".into(), "3".into()])
+        );
+        assert!(!config.keep_going);
+        assert!(config.do_images && config.do_apps);
+        assert_eq!(config.log_level, "INFO");
+        assert_eq!(config.requests_per_second, 5.0);
+        assert_eq!(config.burst, 10);
+        assert_eq!(config.retry.default, RetryPolicy::default());
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let problems = load(SiteSettings {
+            website: Some("https://www.example.com/".to_string()),
+            email: None,
+            database_file: None,
+            forum_ids: Some(IdList::List(vec!["1".into(), "general".into()])),
+            log_level: Some("loud".to_string()),
+            proxy: Some("127.0.0.1:9091".to_string()),
+            requests_per_second: Some(-1.0),
+            burst: Some(0),
+            retry: Some(RetrySettings {
+                max_attempts: Some(0),
+                jitter: Some(2.0),
+                ..Default::default()
+            }),
+            concurrency: Some(ConcurrencySettings {
+                max_in_flight: Some(0),
+                ..Default::default()
+            }),
+            ..valid()
+        })
+        .unwrap_err()
+        .problems;
+        assert_eq!(
+            problems,
+            [
+                "website must be just a domain like `www.enjin.com`, got `https://www.example.com/`",
+                "email is required unless session_id is set",
+                "database_file is required",
+                "forum_ids: `general` is not a numeric ID",
+                "log_level must be one of ERROR, WARN, INFO, DEBUG, TRACE, got `LOUD`",
+                "proxy must be a URL like `http://127.0.0.1:9091`, got `127.0.0.1:9091`",
+                "requests_per_second must be zero (no limit) or more, got -1",
+                "burst must be at least 1",
+                "retry.max_attempts: must be at least 1",
+                "retry.jitter: must be between 0 and 1, got 2",
+                "concurrency.max_in_flight: must be at least 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn method_retry_settings_fall_back_to_the_general_ones() {
+        let config = load(SiteSettings {
+            retry: Some(RetrySettings {
+                max_delay_secs: Some(60.0),
+                methods: BTreeMap::from([(
+                    "Forum.getThread".to_string(),
+                    RetryOverride {
+                        base_delay_secs: Some(120.0),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            }),
+            ..valid()
+        });
+        //The thread override's base delay is now above the general maximum.
+        assert_eq!(
+            config.unwrap_err().problems,
+            ["retry.methods.\"Forum.getThread\": base_delay_secs can't be more than max_delay_secs"]
+        );
+
+        let config = load(SiteSettings {
+            retry: Some(RetrySettings {
+                max_attempts: Some(7),
+                methods: BTreeMap::from([(
+                    "Forum.getThread".to_string(),
+                    RetryOverride {
+                        max_attempts: Some(2),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            }),
+            ..valid()
+        })
+        .unwrap();
+        assert_eq!(config.retry.for_method("Forum.getForum").max_attempts, 7);
+        let thread = config.retry.for_method("Forum.getThread");
+        assert_eq!(thread.max_attempts, 2);
+        assert_eq!(thread.max_delay, RetryPolicy::default().max_delay);
+    }
+
+    #[test]
+    fn settings_under_their_old_unprefixed_names_are_reported() {
+        let env: BTreeMap<&str, &str> = [
+            ("email", "alice@example.com"),
+            ("keep_going", "true"),
+            ("database_file", "old.db"),
+            ("ENCUUM_DATABASE_FILE", "new.db"),
+        ]
+        .into();
+        let mut problems = vec![];
+        check_unprefixed(|name| env.get(name).map(|v| v.to_string()), &mut problems);
+        assert_eq!(
+            problems,
+            [
+                "`email` is now `ENCUUM_EMAIL`; rename it in your environment or .env file",
+                "`keep_going` is now `ENCUUM_KEEP_GOING`; rename it in your environment or .env file"
+            ]
+        );
+    }
+
+    #[test]
+    fn the_endpoint_or_a_replay_stands_in_for_the_site_and_login() {
+        let endpoint = SiteSettings {
+            endpoint: Some("http://127.0.0.1:8088".to_string()),
+            website: None,
+            ..valid()
+        };
+        assert_eq!(load(endpoint).unwrap().endpoint, "http://127.0.0.1:8088");
+        let replay = SiteSettings {
+            replay: Some(PathBuf::from("calls.jsonl")),
+            database_file: Some("example.db".to_string()),
+            ..Default::default()
+        };
+        assert!(load(replay).is_ok());
+        let both = SiteSettings {
+            record: Some(PathBuf::from("calls.jsonl")),
+            replay: Some(PathBuf::from("calls.jsonl")),
+            ..valid()
+        };
+        assert_eq!(
+            load(both).unwrap_err().problems,
+            ["record and replay can't be used together"]
+        );
+    }
+
+    #[test]
+    fn profiles_are_picked_and_layered_over_the_defaults() {
+        let path = std::env::temp_dir().join(format!("encuum-test-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[defaults]\nwebsite = \"www.example.com\"\nkeep_going = true\n\
+            [profiles.a]\ndatabase_file = \"a.db\"\n\
+            [profiles.b]\ndatabase_file = \"b.db\"\nkeep_going = false\n",
+        )
+        .unwrap();
+        let source = |profile: Option<&str>| ConfigSource {
+            path: Some(path.clone()),
+            profile: profile.map(String::from),
+            needs_site: true,
+            isolated: true,
+            ..Default::default()
+        };
+        let a = Config::load(source(Some("a"))).unwrap();
+        assert_eq!((a.database_file.as_str(), a.keep_going), ("a.db", true));
+        let b = Config::load(source(Some("b"))).unwrap();
+        assert_eq!((b.database_file.as_str(), b.keep_going), ("b.db", false));
+        let none = Config::load(source(None)).unwrap_err().problems;
+        assert!(
+            none[0].starts_with("Several profiles are defined (a, b)"),
+            "{:?}",
+            none
+        );
+        let missing = Config::load(source(Some("c"))).unwrap_err().problems;
+        assert!(
+            missing[0].starts_with("Profile `c` not found"),
+            "{:?}",
+            missing
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::retry::ResponseStatus;
+use hyper::StatusCode;
+use jsonrpsee::core::Error;
+use jsonrpsee::types::error::CallError;
+use serde::{Deserialize, Serialize};
+
+/// What went wrong with an Enjin API call, as far as deciding what to do next is concerned.
+///
+/// Every error from the transport is classified here, once, by `from_response` and
+/// `From<jsonrpsee::core::Error>`.
+/// New kinds of Enjin errors should be added here rather than matched on at the call sites.
+#[derive(thiserror::Error, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnjinError {
+    /// HTTP 429: we're going too fast. `retry_after` is how many seconds Enjin asked us to wait, if it said.
+    #[error("Enjin rate-limited us (HTTP 429)")]
+    RateLimited { retry_after: Option<u64> },
+    /// We aren't allowed to see this item.
+    #[error("No access: {0}")]
+    NoAccess(String),
+    /// The thread was moved somewhere else.
+    #[error("Moved: {0}")]
+    Moved(String),
+    /// Enjin has nothing for this item (or page).
+    #[error("The result is empty")]
+    Empty,
+    /// Our session ID isn't valid anymore; nothing will work until we log in again.
+    #[error("Session expired or invalid: {0}")]
+    SessionExpired(String),
+    /// We haven't logged in, so there's no session ID to send.
+    #[error("Not logged in")]
+    NotLoggedIn,
+    #[error("Request timed out")]
+    Timeout,
+    /// Networking trouble, or an HTTP error other than 429.
+    #[error("Transport error: {0}")]
+    Transport(String),
+    /// The response wasn't the JSON we expected.
+    #[error("Malformed response: {0}")]
+    Malformed(String),
+    /// Any other error Enjin reported.
+    #[error("Enjin error: {0}")]
+    Other(String),
+    /// We were asked to stop before the call finished; see `shutdown::Interruptible`.
+    #[error("Interrupted")]
+    Interrupted,
+}
+
+impl EnjinError {
+    /// Classifies an error message from Enjin itself (a JSON-RPC error object).
+    fn from_message(message: &str) -> Self {
+        let lower = message.to_lowercase();
+        if lower.contains("noaccess") {
+            EnjinError::NoAccess(message.to_string())
+        } else if lower.contains("thread has been moved") {
+            EnjinError::Moved(message.to_string())
+        } else if lower.contains("the result is empty") {
+            EnjinError::Empty
+        } else if (lower.contains("session")
+            && (lower.contains("invalid") || lower.contains("expired")))
+            || lower.contains("not logged in")
+        {
+            EnjinError::SessionExpired(message.to_string())
+        } else {
+            EnjinError::Other(message.to_string())
+        }
+    }
+
+    /// The item isn't there for us, so there's no point retrying; skip it and carry on.
+    pub fn is_skippable(&self) -> bool {
+        matches!(
+            self,
+            EnjinError::NoAccess(_) | EnjinError::Moved(_) | EnjinError::Empty
+        )
+    }
+
+    /// Every further call will fail the same way, so the whole extraction should stop.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            EnjinError::SessionExpired(_) | EnjinError::NotLoggedIn
+        )
+    }
+
+    /// Trying the same call again might work.
+    pub fn is_retryable(&self) -> bool {
+        !self.is_skippable() && !self.is_fatal() && !matches!(self, EnjinError::Interrupted)
+    }
+
+    /// A short name for the kind of error, as stored in `failed_items.error_class`.
+    pub fn class(&self) -> &'static str {
+        match self {
+            EnjinError::RateLimited { .. } => "rate_limited",
+            EnjinError::NoAccess(_) => "no_access",
+            EnjinError::Moved(_) => "moved",
+            EnjinError::Empty => "empty",
+            EnjinError::SessionExpired(_) => "session_expired",
+            EnjinError::NotLoggedIn => "not_logged_in",
+            EnjinError::Timeout => "timeout",
+            EnjinError::Transport(_) => "transport",
+            EnjinError::Malformed(_) => "malformed",
+            EnjinError::Other(_) => "other",
+            EnjinError::Interrupted => "interrupted",
+        }
+    }
+
+    /// Classifies a failed call, given the HTTP response it got if it got one: a 429 comes back
+    /// from the transport as an error that doesn't say so.
+    pub fn from_response(e: Error, response: Option<ResponseStatus>) -> Self {
+        match response {
+            Some(r) if r.status == StatusCode::TOO_MANY_REQUESTS => EnjinError::RateLimited {
+                retry_after: r.retry_after,
+            },
+            _ => e.into(),
+        }
+    }
+}
+
+impl From<Error> for EnjinError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Call(CallError::Custom(obj)) => EnjinError::from_message(obj.message()),
+            Error::Call(other) => EnjinError::from_message(&other.to_string()),
+            Error::RequestTimeout => EnjinError::Timeout,
+            Error::ParseError(pe) => EnjinError::Malformed(pe.to_string()),
+            Error::Transport(te) => EnjinError::Transport(te.to_string()),
+            other => EnjinError::Transport(other.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for EnjinError {
+    fn from(e: serde_json::Error) -> Self {
+        EnjinError::Malformed(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enjin_messages_are_classified() {
+        let classes: Vec<&str> = [
+            "NoAccess",
+            "This thread has been moved",
+            "The result is empty",
+            "Session expired",
+            "Invalid session ID",
+            "You are not logged in",
+            "Something else",
+        ]
+        .into_iter()
+        .map(|m| EnjinError::from_message(m).class())
+        .collect();
+        assert_eq!(
+            classes,
+            [
+                "no_access",
+                "moved",
+                "empty",
+                "session_expired",
+                "session_expired",
+                "session_expired",
+                "other"
+            ]
+        );
+    }
+
+    #[test]
+    fn each_kind_is_skipped_retried_or_fatal() {
+        let kinds = [
+            EnjinError::RateLimited { retry_after: None },
+            EnjinError::NoAccess(String::new()),
+            EnjinError::Moved(String::new()),
+            EnjinError::Empty,
+            EnjinError::SessionExpired(String::new()),
+            EnjinError::NotLoggedIn,
+            EnjinError::Timeout,
+            EnjinError::Transport(String::new()),
+            EnjinError::Malformed(String::new()),
+            EnjinError::Other(String::new()),
+            EnjinError::Interrupted,
+        ];
+        let of = |pick: fn(&EnjinError) -> bool| -> Vec<&str> {
+            kinds
+                .iter()
+                .filter(|e| pick(e))
+                .map(|e| e.class())
+                .collect()
+        };
+        assert_eq!(
+            of(EnjinError::is_skippable),
+            ["no_access", "moved", "empty"]
+        );
+        assert_eq!(
+            of(EnjinError::is_fatal),
+            ["session_expired", "not_logged_in"]
+        );
+        assert_eq!(
+            of(EnjinError::is_retryable),
+            ["rate_limited", "timeout", "transport", "malformed", "other"]
+        );
+    }
+
+    #[test]
+    fn transport_errors_are_classified() {
+        let e = EnjinError::from(Error::RequestTimeout);
+        assert!(matches!(e, EnjinError::Timeout));
+        let e = EnjinError::from(Error::Transport(anyhow::anyhow!("connection refused")));
+        assert!(matches!(e, EnjinError::Transport(_)));
+    }
+
+    #[test]
+    fn a_429_is_told_by_its_status_not_the_error_text() {
+        let rejected = || Error::Transport(anyhow::anyhow!("Request rejected"));
+        let response = |status, retry_after| {
+            Some(ResponseStatus {
+                status,
+                retry_after,
+            })
+        };
+        let e = EnjinError::from_response(
+            rejected(),
+            response(StatusCode::TOO_MANY_REQUESTS, Some(30)),
+        );
+        assert!(matches!(
+            e,
+            EnjinError::RateLimited {
+                retry_after: Some(30)
+            }
+        ));
+        let e = EnjinError::from_response(rejected(), response(StatusCode::BAD_GATEWAY, None));
+        assert!(matches!(e, EnjinError::Transport(_)));
+        let text = Error::Transport(anyhow::anyhow!("Request rejected `429` status code: 429"));
+        assert!(matches!(
+            EnjinError::from_response(text, None),
+            EnjinError::Transport(_)
+        ));
+    }
+}
@@ -0,0 +1,160 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::api::EnjinApi;
+use crate::cassette::{Recorder, Replayer};
+use crate::concurrency::Limited;
+use crate::config::Config;
+use crate::error::EnjinError;
+use crate::ratelimit::RateLimiter;
+use crate::retry::Retrying;
+use crate::runs::RunStats;
+use crate::shutdown::{Interruptible, Shutdown};
+use crate::structures::*;
+use crate::upgrade;
+use anyhow::Context;
+use sea_orm::prelude::DateTimeUtc;
+use sea_orm::DatabaseConnection;
+use secrecy::{ExposeSecret, SecretString};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// One extraction of one Enjin site. It owns its database connection and API clients,
+/// so any number of them can be used side by side in the same process.
+pub struct Extractor {
+    pub config: Config,
+    pub conn: DatabaseConnection,
+    pub api: Arc<dyn EnjinApi>,
+    pub req_client: ImageClient,
+    /// Shared by the API client and `req_client`, so everything we send stays under `config.requests_per_second`.
+    pub limiter: RateLimiter,
+    /// Raised to stop the crawl cleanly; see `crawl`.
+    pub shutdown: Shutdown,
+    pub stats: RunStats,
+    /// The row in `runs` for this run, once `start_run` has added it.
+    pub run_id: Option<i32>,
+    /// When the extraction that `start_crawl` started or resumed began, if that's known.
+    pub crawl_started_at: Option<DateTimeUtc>,
+    session_id: Option<SecretString>,
+}
+
+impl Extractor {
+    /// Opens the database (see `upgrade::open`) and builds the HTTP API client, recording to or replaying
+    /// from a cassette if configured. This doesn't log in; call `login` before anything that talks to Enjin.
+    pub async fn new(config: Config) -> anyhow::Result<Self> {
+        let conn = upgrade::open(&config.database_file).await?;
+        Self::with_connection(config, conn)
+    }
+
+    /// Like `new`, but leaves the database alone, not even creating it, for what only talks to
+    /// Enjin, like `plan`. Anything that uses the database fails.
+    pub fn without_database(config: Config) -> anyhow::Result<Self> {
+        Self::with_connection(config, DatabaseConnection::Disconnected)
+    }
+
+    fn with_connection(config: Config, conn: DatabaseConnection) -> anyhow::Result<Self> {
+        let limiter = RateLimiter::new(config.requests_per_second, config.burst);
+        let api: Arc<dyn EnjinApi> = match (&config.replay, &config.record) {
+            (Some(path), _) => Arc::new(Replayer::open(path)?),
+            (None, Some(path)) => Arc::new(Recorder::create(
+                path,
+                Arc::new(build_client(&config, &limiter)?),
+            )?),
+            (None, None) => Arc::new(build_client(&config, &limiter)?),
+        };
+        Ok(Self::assemble(config, api, limiter, conn))
+    }
+
+    /// Like `new`, but talks to Enjin through `api` instead of over HTTP. Calls are retried
+    /// according to `config.retry` and held to `config.concurrency` either way, but only image
+    /// downloads are rate limited; `api` is expected to do its own.
+    pub async fn with_api(config: Config, api: Arc<dyn EnjinApi>) -> anyhow::Result<Self> {
+        let limiter = RateLimiter::new(config.requests_per_second, config.burst);
+        let conn = upgrade::open(&config.database_file).await?;
+        Ok(Self::assemble(config, api, limiter, conn))
+    }
+
+    fn assemble(
+        config: Config,
+        api: Arc<dyn EnjinApi>,
+        limiter: RateLimiter,
+        conn: DatabaseConnection,
+    ) -> Self {
+        let mut retrying = Retrying::new(api, config.retry.clone());
+        if config.replay.is_some() {
+            retrying = retrying.without_delays();
+        }
+        let shutdown = Shutdown::default();
+        let limited = Limited::new(Arc::new(retrying), &config.concurrency);
+        Extractor {
+            api: Arc::new(Interruptible::new(Arc::new(limited), shutdown.clone())),
+            req_client: build_image_client(&limiter),
+            limiter,
+            shutdown,
+            stats: RunStats::default(),
+            run_id: None,
+            crawl_started_at: None,
+            session_id: config.session_id.clone(),
+            config,
+            conn,
+        }
+    }
+
+    /// Logs in with the configured email and password, unless a session ID was configured.
+    pub async fn login(&mut self) -> anyhow::Result<()> {
+        if self.session_id.is_some() {
+            return Ok(());
+        }
+        let resp = self
+            .api
+            .login(&self.config.email, self.config.password.expose_secret())
+            .await
+            .context("FATAL ERROR: Login failed")?;
+        if self.config.sanitize_log {
+            info!("Your session ID is: {}", resp.session_id);
+        }
+        self.session_id = Some(SecretString::new(resp.session_id));
+        Ok(())
+    }
+
+    /// The session ID to send with API calls, or `NotLoggedIn` if we haven't logged in.
+    pub fn session(&self) -> Result<&String, EnjinError> {
+        self.session_id
+            .as_ref()
+            .map(|s| s.expose_secret())
+            .ok_or(EnjinError::NotLoggedIn)
+    }
+
+    /// Deals with a call that failed for good (retries included): carries on without `what` if that's
+    /// harmless or `keep_going` is set, and otherwise returns the error, which stops the extraction.
+    pub fn give_up(&self, what: &str, e: &EnjinError) -> anyhow::Result<()> {
+        if e.is_skippable() {
+            warn!(
+                "{}: {}. Continuing anyway because this is not fatal. Your extraction may be incomplete.",
+                what, e
+            );
+        } else if self.config.keep_going && !e.is_fatal() {
+            warn!("{}: {}. Skipping it because keep_going is set.", what, e);
+        } else {
+            return Err(anyhow::Error::new(e.clone()).context(what.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestExtractor;
+
+    #[tokio::test]
+    async fn calls_before_logging_in_fail_instead_of_panicking() {
+        let ex = TestExtractor::new("not-logged-in").await;
+        assert!(matches!(ex.session(), Err(EnjinError::NotLoggedIn)));
+        let not_logged_in = |e: anyhow::Error| matches!(e.downcast(), Ok(EnjinError::NotLoggedIn));
+        assert!(not_logged_in(
+            ex.call("Forum.getForum", "{}", true).await.unwrap_err()
+        ));
+        assert!(not_logged_in(ex.get_apps().await.unwrap_err()));
+    }
+}
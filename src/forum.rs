@@ -1,433 +1,516 @@
-use crate::dumbsert;
-use crate::exposed_session;
-use crate::helpers::*;
 /// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
 /// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
 /// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
-use crate::state;
+use crate::dumbsert;
+use crate::error::EnjinError;
+use crate::extractor::Extractor;
+use crate::helpers::*;
+use crate::queue::{Skipped, Unit, UnitKind};
+use crate::runs::now;
 use crate::structures::*;
 use entity::*;
-use futures::{stream::FuturesUnordered, StreamExt};
-use jsonrpsee::proc_macros::rpc;
 use lazy_static::lazy_static;
 use regex::Regex;
-use sea_orm::{sea_query::OnConflict, EntityTrait, Set};
-use secrecy::ExposeSecret;
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, EntityTrait, NotSet, QueryFilter, QuerySelect, Set, TransactionTrait,
+};
 use std::collections::{HashMap, HashSet};
 use std::iter::*;
-use tracing::{debug, info, warn};
-
-#[rpc(client)]
-trait ForumApi {
-    #[method(name="User.login", param_kind=map)]
-    async fn login(&self, email: &String, password: &String) -> Result<LoginResponse, Error>;
-
-    #[method(name="Forum.getCategoriesAndForums", param_kind=map)]
-    async fn get_categories_and_forums(
-        &self,
-        session_id: &String,
-        preset_id: &String,
-    ) -> Result<GetCafResult, Error>;
-
-    #[method(name="Forum.getForum", param_kind=map)]
-    async fn get_forum(
-        &self,
-        session_id: &String,
-        forum_id: &String,
-        page: Option<&String>,
-    ) -> Result<GetForumResult, Error>;
-
-    #[method(name="Forum.getThread", param_kind=map)]
-    async fn get_thread(
-        &self,
-        session_id: &String,
-        thread_id: &String,
-        page: Option<&String>,
-    ) -> Result<GetThreadResult, Error>;
-}
+use tower::ServiceExt;
+use tracing::{debug, info};
 
 lazy_static! {
     pub static ref IMG_RX: Regex = Regex::new(r"(?i)\[img]\s*(https?://.+?)\s*\[/img]").unwrap();
 }
 
-/// Since image downloads are unreliable anyway, we just print out errors and keep going
-pub async fn download_image(url: String) {
-    debug!("download_image({:?})", url);
-
-    let db_find = images::Entity::find_by_id(url.to_string())
-        .one(&state!().conn)
-        .await;
-
-    if db_find.is_err() {
-        warn!("{:#?}", db_find.unwrap_err());
-        return;
-    }
-
-    let db_result = db_find.unwrap();
-    if db_result.is_some() {
-        info!("Already have image; not downloading again: {}", url);
-        return;
-    }
-    let req = state!().req_client.get(&url).send().await;
-    if req.is_err() {
-        warn!("{:#?}", req.unwrap_err());
-        return;
-    }
-    let maybe_bytes = req.unwrap().bytes().await;
-    if maybe_bytes.is_err() {
-        warn!("{:#?}", maybe_bytes.unwrap_err());
-        return;
-    }
-    let bytes = maybe_bytes.unwrap();
-    let maybe_exec = images::Entity::insert(images::ActiveModel {
-        image_url: Set(url.clone()),
-        image_content: Set(Some(bytes.to_vec())),
-    })
-    .exec(&state!().conn)
-    .await;
-    if maybe_exec.is_err() {
-        warn!("{:#?}", maybe_exec.unwrap_err());
-    }
+/// A thread or post count of a whole preset, which `forum_presets` can't be without.
+fn preset_total(val: &serde_json::Value) -> Result<i32, EnjinError> {
+    parse_number(val)?
+        .and_then(|n| i32::try_from(n).ok())
+        .ok_or_else(|| EnjinError::Malformed(format!("expected a preset total, got {}", val)))
 }
 
-pub async fn get_images(post_id: String, post_content: String) {
-    debug!("get_images({:?})", post_id);
-    let matches = IMG_RX.captures_iter(&post_content);
+impl Extractor {
+    /// Downloads an image into the `images` table, unless we already have it.
+    pub async fn download_image(&self, url: &str) -> anyhow::Result<()> {
+        debug!("download_image({:?})", url);
+
+        if images::Entity::find_by_id(url.to_string())
+            .one(&self.conn)
+            .await?
+            .is_some()
+        {
+            info!("Already have image; not downloading again: {}", url);
+            return Ok(());
+        }
+        let req = reqwest::Request::new(reqwest::Method::GET, reqwest::Url::parse(url)?);
+        let bytes = self
+            .req_client
+            .clone()
+            .oneshot(req)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        images::Entity::insert(images::ActiveModel {
+            image_url: Set(url.to_string()),
+            image_content: Set(Some(bytes.to_vec())),
+        })
+        .exec(&self.conn)
+        .await?;
+        self.stats.images.add(1);
+        Ok(())
+    }
 
-    for mmatch in matches {
-        let url = &mmatch[1];
-        download_image(url.to_owned()).await;
+    async fn crawl_preset(&self, unit: Unit) -> anyhow::Result<()> {
+        let preset_id = &unit.item_id;
+        let caf = self
+            .api
+            .get_categories_and_forums(self.session()?, preset_id)
+            .await?;
+        info!(
+            "got a site forum instance (aka prefix or caf) {} called {}",
+            preset_id, &caf.settings.title_welcome
+        );
+        self.save_preset(preset_id, &caf).await?;
+        self.stats.presets.add(1);
+        let allowed_subforums = self.allowed_subforums(&caf);
+        info!(
+            "*** Total number of forums and subforums to scan: {}",
+            allowed_subforums.len()
+        );
+        self.enqueue(
+            allowed_subforums
+                .iter()
+                .map(|forum_id| Unit::forum_page(forum_id, 1, preset_id)),
+        )
+        .await
     }
-}
 
-pub async fn get_preset_retry(preset_id: &String) -> Option<GetCafResult> {
-    debug!("get_preset_retry({:?})", preset_id);
-    let mut tries = 1;
+    /// Every forum and subforum of a preset, less those `subforum_ids` leaves out.
+    pub fn allowed_subforums(&self, caf: &GetCafResult) -> Vec<String> {
+        let maybe_sfis = &self.config.subforum_ids;
+        let mut all_subforums: Vec<String> = vec![];
 
-    loop {
-        let maybe_caf = SEE
-            .get_categories_and_forums(exposed_session!(), preset_id)
-            .await;
-        match maybe_caf {
-            Err(e) => {
-                let f = format!("Preset: {}, Try #{}: {}", preset_id, tries, e);
-                if tries >= 5 {
-                    if state!().keep_going {
-                        return None;
-                    } else {
-                        panic!("{}", f);
+        //Add all the subforums.
+        match &caf.subforums {
+            SubforumType::MapSubforum(m) => {
+                all_subforums.extend(m.keys().cloned());
+                for sfs in m.values() {
+                    for sf in sfs {
+                        all_subforums.push(sf.forum_id.clone());
                     }
                 }
-                warn!("{:#?}", f);
-                tries += 1;
-                calculate_and_sleep(&Thing::Preset, preset_id, &e, &tries).await;
-            }
-            Ok(caf) => {
-                info!(
-                    "got a site forum instance (aka prefix or caf) {} called {}",
-                    preset_id, &caf.settings.title_welcome
-                );
-                return Some(caf);
-            }
-        }
-    }
-}
-
-pub async fn get_forum_index_retry(
-    forum_id: &String,
-    page: Option<String>,
-) -> Option<GetForumResult> {
-    debug!("get_forum_index_retry({:?}, {:?})", forum_id, page);
-    let mut tries = 1;
-    loop {
-        let maybe_gfr = SEE
-            .get_forum(exposed_session!(), &forum_id, page.as_ref())
-            .await;
-        if maybe_gfr.is_err() {
-            let e = maybe_gfr.unwrap_err();
-            let f = format!("Subforum: {}, Try #{}: {}", forum_id, tries, e);
-            warn!("{:#?}", &f);
-            //The user doesn't have access to the forum. This isn't fatal for extracting what we can; log it and keep going
-            if e.to_string().contains("noaccess")
-                || e.to_string().contains("thread has been moved")
-                || e.to_string().contains("The result is empty")
-            {
-                info!("Continuing anyway because this is not fatal. Your extraction may be incomplete.");
-                return None;
             }
-            if tries >= 5 {
-                if state!().keep_going {
-                    return None;
-                } else {
-                    panic!("{}", f);
+            SubforumType::SeqSubforum(s) => {
+                for x in s {
+                    all_subforums.push(x.clone());
                 }
             }
-
-            tries += 1;
-            calculate_and_sleep(&Thing::ForumIndex, forum_id, &e, &tries).await;
-        } else {
-            return maybe_gfr.ok();
         }
-    }
-}
 
-pub async fn get_thread_index_retry(
-    thread_id: &String,
-    page: Option<String>,
-) -> Option<GetThreadResult> {
-    debug!("get_thread_index_retry({}, {:?})", thread_id, page);
-    let mut tries = 1;
-    loop {
-        let maybe_gtr = SEE
-            .get_thread(exposed_session!(), thread_id, page.as_ref())
-            .await;
-        match maybe_gtr {
-            Err(e) => {
-                let f = format!("Thread: {}, Try #{}: {}", thread_id, tries, e);
-                warn!(f);
-                //The user doesn't have access to the forum. This isn't fatal for extracting what we can; log it and keep going
-                if e.to_string().contains("noaccess")
-                    || e.to_string().contains("thread has been moved")
-                    || e.to_string().contains("The result is empty")
-                {
-                    warn!("Continuing anyway because this is not fatal. Your extraction may be incomplete.");
-                    return None;
-                }
-                if tries >= 5 {
-                    if state!().keep_going {
-                        return None;
-                    } else {
-                        panic!("{}", f);
-                    }
-                }
-
-                tries += 1;
-                calculate_and_sleep(&Thing::Thread, thread_id, &e, &tries).await;
-            }
-            Ok(gtr) => {
-                return Some(gtr);
+        //Add the "categories" top-level forums.
+        for foru in caf.categories.values() {
+            for (fid, _) in foru {
+                all_subforums.push(fid.clone());
             }
         }
-    }
-}
 
-pub async fn get_subforums(subforum_ids: Vec<&String>) -> Vec<GetForumResult> {
-    debug!("get_subforums({:#?})", subforum_ids);
-    let mut retval: Vec<GetForumResult> = vec![];
-    let mut page_map: HashMap<String, u32> = HashMap::new();
-    let mut futs = FuturesUnordered::new();
+        all_subforums.sort();
+        all_subforums.dedup();
 
-    //Queue request for first page for every subforum index list.
-    for forum_id in subforum_ids {
-        futs.push(get_forum_index_retry(forum_id, None));
+        //Call Forum.getForum for every CAF (only for allowed subforums).
+        Vec::from_iter(
+            all_subforums
+                .into_iter()
+                .filter(|subforum_id| match maybe_sfis {
+                    Some(sfis) => {
+                        if sfis.len() == 0 {
+                            debug!("subforum_ids.len() == 0");
+                            return true;
+                        } else {
+                            debug!("subforum_ids contains check on {}", subforum_id);
+                            return sfis.contains(subforum_id);
+                        }
+                    }
+                    None => {
+                        return true;
+                    }
+                }),
+        )
     }
 
-    let mut arl: u32 = 1;
-    while let Some(x) = futs.next().await {
-        //This is true when we have keep_going on and the forum index couldn't be retrieved.
-        if x.is_none() {
-            continue;
+    async fn crawl_forum_page(&self, unit: Unit) -> anyhow::Result<()> {
+        let gfr = self
+            .api
+            .get_forum(self.session()?, &unit.item_id, unit.page_param().as_deref())
+            .await?;
+        let pages = parse_number(&gfr.pages).map_err(Skipped::from)?;
+        info!(
+            "Got Page {:?}/{:?} for Forum {} from Preset {}",
+            gfr.page, gfr.pages, gfr.forum.forum_id, gfr.forum.preset_id
+        );
+        debug!(
+            "Sticky thread count for GFR page {} for forum {}: {}",
+            gfr.page,
+            gfr.forum.forum_id,
+            gfr.sticky.len()
+        );
+        let listed: Vec<&forum_threads::ForumThread> = gfr
+            .listings()
+            .into_iter()
+            .flat_map(|(_, threads)| threads)
+            .map(|t| &t.thread)
+            .collect();
+        //This has to be worked out before the new listing overwrites what we stored.
+        let unchanged = if self.config.update {
+            self.unchanged_threads(&listed).await?
+        } else {
+            HashSet::new()
+        };
+        if !unchanged.is_empty() {
+            info!(
+                "Skipping {} unchanged threads on page {} of Forum {}",
+                unchanged.len(),
+                unit.page.unwrap_or(1),
+                unit.item_id
+            );
         }
-        let y = x.unwrap(); //Guaranteed to succeed.
-        let page_num = parse_number(&y.pages);
-        if let Some(pn) = page_num {
-            page_map.insert(y.forum.forum_id.clone(), pn);
+        self.save_subforum(&gfr).await?;
+        //Global announcements show up in every forum; the queue only keeps the first.
+        self.enqueue(
+            listed
+                .iter()
+                .filter(|t| !unchanged.contains(&t.thread_id))
+                .map(|t| Unit::thread_page(&t.thread_id, 1, &gfr.forum.forum_id)),
+        )
+        .await?;
+        if unit.page == Some(1) {
+            self.stats.subforums.add(1);
+            let pages = pages.unwrap_or(1);
+            let preset_id = unit.parent_id.as_deref().unwrap_or(&gfr.forum.preset_id);
+            self.enqueue((2..=pages).map(|page| Unit::forum_page(&unit.item_id, page, preset_id)))
+                .await?;
         }
-        info!("Got Page 1 of the Forum Index for {}", y.forum.forum_id);
-        whoa(&mut arl).await;
-        retval.push(y);
+        Ok(())
     }
 
-    //Queue request for every remaining page for every subforum index list.
-    for (forum_id, pages) in page_map.iter_mut() {
-        let mut page = 2;
-        while page <= *pages {
-            futs.push(get_forum_index_retry(forum_id, Some(page.to_string())));
-            page += 1;
+    /// Fetches a thread page and queues up the rest of the thread's pages; `save_thread_page` stores it.
+    async fn fetch_thread_page(&self, unit: Unit) -> anyhow::Result<GetThreadResult> {
+        let gtr = self
+            .api
+            .get_thread(self.session()?, &unit.item_id, unit.page_param().as_deref())
+            .await?;
+        let pages = parse_number(&gtr.pages).map_err(Skipped::from)?;
+        info!(
+            "Got Page {:?} of Thread {} from Forum {}",
+            unit.page, gtr.thread.thread_id, gtr.thread.forum_id
+        );
+        if unit.page == Some(1) {
+            //When updating, the pages before the one our stored posts run out on are already saved.
+            let first_new_page = match self.config.update {
+                true => self.first_new_page(&unit.item_id).await?,
+                false => 2,
+            };
+            let pages = pages.unwrap_or(1);
+            self.enqueue(
+                (first_new_page.max(2)..=pages)
+                    .map(|page| Unit::thread_page(&unit.item_id, page, &gtr.thread.forum_id)),
+            )
+            .await?;
         }
+        Ok(gtr)
     }
 
-    //Fill out the list of forum indexes from all the pages.
-    arl = 1;
-    while let Some(x) = futs.next().await {
-        if x.is_none() {
-            continue;
+    pub(crate) async fn save_thread_page(
+        &self,
+        unit: Unit,
+        gtr: GetThreadResult,
+    ) -> anyhow::Result<()> {
+        let total_items = parse_number(&gtr.total_items).map_err(Skipped::from)?;
+        let pages = parse_number(&gtr.pages).map_err(Skipped::from)?;
+        //Worked out before the posts are overwritten, so an edit can be told apart, but saved after,
+        //since a revision needs its post.
+        let revisions = self.new_revisions(&gtr.posts).await?;
+        self.save_thread(&gtr, unit.page.unwrap_or(1)).await?;
+        self.save_revisions(revisions).await?;
+        forum_threads::Entity::update_many()
+            .col_expr(
+                forum_threads::Column::TotalItems,
+                Expr::value(total_items.map(i64::from)),
+            )
+            .col_expr(
+                forum_threads::Column::TotalPages,
+                Expr::value(pages.map(i64::from)),
+            )
+            .filter(forum_threads::Column::ThreadId.eq(gtr.thread.thread_id.clone()))
+            .exec(&self.conn)
+            .await?;
+        if unit.page == Some(1) {
+            self.stats.threads.add(1);
         }
-        let xu = x.unwrap();
-        info!(
-            "Got Page {:?}/{:?} for Forum {} from Preset {}",
-            xu.page, xu.pages, xu.forum.forum_id, xu.forum.preset_id
-        );
-        retval.push(xu);
-        whoa(&mut arl).await;
+        self.stats.posts.add(gtr.posts.len() as u64);
+        if self.config.do_images {
+            self.enqueue(gtr.posts.iter().flat_map(|post| {
+                IMG_RX
+                    .captures_iter(&post.post_content)
+                    .map(|m| Unit::image(&m[1], &post.post_id))
+                    .collect::<Vec<_>>()
+            }))
+            .await?;
+        }
+        Ok(())
     }
 
-    return retval;
-}
-
-pub async fn get_threads(mut thread_ids: Vec<String>) -> Vec<GetThreadResult> {
-    debug!("get_threads({:#?})", thread_ids);
-    let mut retval = vec![];
-    let mut page_map: HashMap<String, u32> = HashMap::new();
-    let mut futs = FuturesUnordered::new();
-
-    //Queue request for first page for every thread index list.
-    for thread_id in thread_ids.iter_mut() {
-        futs.push(get_thread_index_retry(thread_id, None));
+    /// The page of a thread the last of our stored posts came from, where an update has to pick the
+    /// thread up again, since new posts start there. Posts found deleted don't count: Enjin doesn't
+    /// have them any more, so they're on no page. Page 1, so everything, if we have no posts, or
+    /// some were stored before their page was recorded.
+    async fn first_new_page(&self, thread_id: &str) -> anyhow::Result<u32> {
+        let pages: Vec<Option<i64>> = forum_posts::Entity::find()
+            .select_only()
+            .column(forum_posts::Column::ThreadPage)
+            .distinct()
+            .filter(forum_posts::Column::ThreadId.eq(thread_id))
+            .filter(forum_posts::Column::DeletedDetectedAt.is_null())
+            .into_tuple()
+            .all(&self.conn)
+            .await?;
+        Ok(match pages.contains(&None) {
+            true => 1,
+            false => pages.into_iter().flatten().max().map_or(1, |p| p as u32),
+        })
     }
 
-    let mut arl: u32 = 1;
-    while let Some(x) = futs.next().await {
-        //This is true when we have keep_going on and the thread index couldn't be retrieved.
-        if x.is_none() {
-            continue;
-        }
-        let y = x.unwrap(); //Guaranteed to succeed.
-        let page_num = parse_number(&y.pages);
-        if let Some(pn) = page_num {
-            page_map.insert(y.thread.thread_id.clone(), pn);
-        }
-        whoa(&mut arl).await;
-        info!(
-            "Got Page 1 of Thread {} from Forum {}",
-            y.thread.thread_id, &y.thread.forum_id
-        );
-        retval.push(y);
+    /// How many posts of each of `thread_ids` we have stored, leaving out those found deleted.
+    async fn stored_post_counts(
+        &self,
+        thread_ids: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, u64>> {
+        let counts: Vec<(Option<String>, i64)> = forum_posts::Entity::find()
+            .select_only()
+            .column(forum_posts::Column::ThreadId)
+            .column_as(forum_posts::Column::PostId.count(), "n")
+            .filter(forum_posts::Column::ThreadId.is_in(thread_ids))
+            .filter(forum_posts::Column::DeletedDetectedAt.is_null())
+            .group_by(forum_posts::Column::ThreadId)
+            .into_tuple()
+            .all(&self.conn)
+            .await?;
+        Ok(counts
+            .into_iter()
+            .filter_map(|(thread_id, n)| Some((thread_id?, n as u64)))
+            .collect())
     }
 
-    //Queue request for every remaining page for every thread index list.
-    for (thread_id, pages) in page_map.iter_mut() {
-        let mut page = 2;
-        while page <= *pages {
-            futs.push(get_thread_index_retry(thread_id, Some(page.to_string())));
-            page += 1;
-        }
+    /// The threads in a forum listing that haven't changed since we stored them: we have one post
+    /// more than the listed reply count, and the same last post time. A listing without a reply
+    /// count is always taken to have changed.
+    async fn unchanged_threads(
+        &self,
+        listed: &[&forum_threads::ForumThread],
+    ) -> anyhow::Result<HashSet<String>> {
+        let ids: Vec<String> = listed.iter().map(|t| t.thread_id.clone()).collect();
+        let stored: HashMap<String, forum_threads::ForumThread> = forum_threads::Entity::find()
+            .filter(forum_threads::Column::ThreadId.is_in(ids.clone()))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|t| (t.thread_id.clone(), t))
+            .collect();
+        let counts = self.stored_post_counts(ids).await?;
+        Ok(listed
+            .iter()
+            .filter(|t| {
+                let replies = t.thread_replies.and_then(|r| u64::try_from(r).ok());
+                let same_count = replies.is_some()
+                    && counts.get(&t.thread_id).copied() == replies.map(|r| r + 1);
+                let same_last_post = stored
+                    .get(&t.thread_id)
+                    .is_some_and(|s| s.thread_lastpost_time == t.thread_lastpost_time);
+                same_count && same_last_post
+            })
+            .map(|t| t.thread_id.clone())
+            .collect())
     }
 
-    //Fill out the list of thread indexes from all the pages.
-    arl = 1;
-    while let Some(x) = futs.next().await {
-        if x.is_none() {
-            continue;
-        }
-        let xu = x.unwrap();
-        info!(
-            "Got Thread {} from Forum {}",
-            xu.thread.thread_id, xu.thread.forum_id
-        );
-        retval.push(xu);
-        whoa(&mut arl).await;
+    /// Since image downloads are unreliable anyway, we just print out errors and keep going
+    async fn crawl_image(&self, unit: Unit) -> anyhow::Result<()> {
+        self.download_image(&unit.item_id)
+            .await
+            .map_err(|e| Skipped(e).into())
     }
 
-    return retval;
-}
+    pub async fn save_preset(&self, preset_id: &String, caf: &GetCafResult) -> anyhow::Result<()> {
+        debug!("save_preset({})", preset_id);
+        let total_threads = preset_total(&caf.total_threads).map_err(Skipped::from)?;
+        let total_posts = preset_total(&caf.total_posts).map_err(Skipped::from)?;
+        let categories = &caf.category_names;
 
-pub async fn save_preset(preset_id: &String, caf: &GetCafResult) {
-    debug!("save_preset({})", preset_id);
-    let categories = &caf.category_names;
+        for (cid, cn) in categories {
+            let am = category_names::ActiveModel {
+                category_id: Set(cid.to_string()),
+                category_name: Set(cn.to_string()),
+            };
+            dumbsert!(
+                &self.conn,
+                category_names::Entity,
+                &am,
+                category_names::Column::CategoryId,
+                "Error saving category to database",
+                true
+            );
+        }
 
-    for (cid, cn) in categories {
-        let am = category_names::ActiveModel {
-            category_id: Set(cid.to_string()),
-            category_name: Set(cn.to_string()),
+        let am = forum_presets::ActiveModel {
+            preset_id: Set(preset_id.to_string()),
+            title_welcome: Set(caf.settings.title_welcome.clone()),
+            total_threads: Set(total_threads),
+            total_posts: Set(total_posts),
         };
         dumbsert!(
-            category_names::Entity,
+            &self.conn,
+            forum_presets::Entity,
             &am,
-            category_names::Column::CategoryId,
-            "Error saving category to database",
+            forum_presets::Column::PresetId,
+            "Error saving preset to database",
             true
         );
+        Ok(())
     }
 
-    let am = forum_presets::ActiveModel {
-        preset_id: Set(preset_id.to_string()),
-        title_welcome: Set(caf.settings.title_welcome.clone()),
-        total_threads: Set(parse_number(&caf.total_threads)
-            .unwrap()
-            .try_into()
-            .unwrap()),
-        total_posts: Set(parse_number(&caf.total_posts).unwrap().try_into().unwrap()),
-    };
-    dumbsert!(
-        forum_presets::Entity,
-        &am,
-        forum_presets::Column::PresetId,
-        "Error saving preset to database",
-        true
-    );
-}
+    pub async fn save_subforum(&self, gfr: &GetForumResult) -> anyhow::Result<()> {
+        debug!("save_subforum({})", gfr.forum.forum_id);
+        let page = parse_number(&gfr.page)
+            .map_err(Skipped::from)?
+            .map(i64::from);
 
-pub async fn save_subforum(gfr: &GetForumResult) {
-    debug!("save_subforum({})", gfr.forum.forum_id);
-
-    let am = subforums::ActiveModel {
-        title_welcome: Set(gfr.forum.title_welcome.clone()),
-        preset_id: Set(gfr.forum.preset_id.clone()),
-        category_id: Set(gfr.forum.category_id.clone()),
-        category_name: Set(gfr.forum.category_name.clone()),
-        forum_id: Set(gfr.forum.forum_id.clone()),
-        forum_name: Set(gfr.forum.forum_name.clone()),
-        forum_description: Set(gfr.forum.forum_description.clone()),
-        parent_id: Set(gfr.forum.parent_id.clone()),
-        forum_type: Set(gfr.forum.forum_type.clone()),
-    };
-    dumbsert!(
-        subforums::Entity,
-        &am,
-        subforums::Column::ForumId,
-        "Error saving subforum to database",
-        true
-    );
-
-    let all_threads = gfr
-        .threads
-        .to_owned()
-        .into_iter()
-        .chain(gfr.announcement_global.to_owned().into_iter())
-        .chain(gfr.announcement_local.to_owned().into_iter())
-        .chain(gfr.sticky.to_owned().into_iter())
-        .chain(gfr.notices.to_owned().into_iter());
-
-    for thread in all_threads {
-        let am = forum_threads::ActiveModel {
-            thread_id: Set(thread.thread_id.clone()),
-            thread_subject: Set(thread.thread_subject.clone()),
-            thread_views: Set(thread.thread_views.clone()),
-            thread_type: Set(thread.thread_type.clone()),
-            thread_status: Set(thread.thread_status.clone()),
-            forum_id: Set(gfr.forum.forum_id.clone()),
-            username: Set(thread.username.clone()),
+        let am = subforums::ActiveModel {
+            title_welcome: Set(gfr.forum.title_welcome.clone()),
+            preset_id: Set(gfr.forum.preset_id.clone()),
             category_id: Set(gfr.forum.category_id.clone()),
+            category_name: Set(gfr.forum.category_name.clone()),
+            forum_id: Set(gfr.forum.forum_id.clone()),
+            forum_name: Set(gfr.forum.forum_name.clone()),
+            forum_description: Set(gfr.forum.forum_description.clone()),
+            parent_id: Set(gfr.forum.parent_id.clone()),
+            forum_type: Set(gfr.forum.forum_type.clone()),
         };
         dumbsert!(
-            forum_threads::Entity,
+            &self.conn,
+            subforums::Entity,
             &am,
-            forum_threads::Column::ThreadId,
-            "Error saving forum thread to database",
+            subforums::Column::ForumId,
+            "Error saving subforum to database",
             true
         );
+
+        //What was on this page of the listing before is superseded by what's on it now, all at once,
+        //so the page is never left with only some of its threads placed.
+        let forum_id = &gfr.forum.forum_id;
+        let page = page.unwrap_or(1);
+        let txn = self.conn.begin().await?;
+        thread_placements::Entity::delete_many()
+            .filter(thread_placements::Column::ForumId.eq(forum_id.as_str()))
+            .filter(thread_placements::Column::ListingPage.eq(page))
+            .exec(&txn)
+            .await?;
+        //A thread listed by a subforum other than its own, like a global announcement, keeps the
+        //subforum it has; this one is only a stand-in until its own is crawled.
+        let elsewhere: Vec<&String> = gfr
+            .listings()
+            .into_iter()
+            .flat_map(|(_, threads)| threads)
+            .filter(|listed| listed.thread.forum_id != *forum_id)
+            .map(|listed| &listed.thread.thread_id)
+            .collect();
+        let stored_elsewhere: HashSet<String> = forum_threads::Entity::find()
+            .filter(forum_threads::Column::ThreadId.is_in(elsewhere))
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|t| t.thread_id)
+            .collect();
+        let seen_at = now();
+        for (listing, threads) in gfr.listings() {
+            for (position, listed) in threads.iter().enumerate() {
+                let thread = &listed.thread;
+                let mut am = forum_threads::ActiveModel {
+                    thread_id: Set(thread.thread_id.clone()),
+                    thread_subject: Set(thread.thread_subject.clone()),
+                    thread_views: Set(thread.thread_views),
+                    thread_type: Set(thread.thread_type.clone()),
+                    thread_status: Set(thread.thread_status.clone()),
+                    forum_id: Set(forum_id.clone()),
+                    username: Set(thread.username.clone()),
+                    category_id: Set(gfr.forum.category_id.clone()),
+                    thread_replies: Set(thread.thread_replies),
+                    thread_lastpost_time: Set(thread.thread_lastpost_time),
+                    last_seen_at: Set(Some(seen_at)),
+                    deleted_detected_at: Set(None),
+                    thread_user_id: Set(thread.thread_user_id.clone()),
+                    thread_lastpost_user_id: Set(thread.thread_lastpost_user_id.clone()),
+                    thread_lastpost_username: Set(thread.thread_lastpost_username.clone()),
+                    thread_moved_id: Set(thread.thread_moved_id.clone()),
+                    thread_hidden: Set(thread.thread_hidden),
+                    listing_json: Set(Some(listed.json.to_string())),
+                    ..Default::default()
+                };
+                //It's known to be there, and an INSERT without its subforum would be refused.
+                if stored_elsewhere.contains(&thread.thread_id) {
+                    am.forum_id = NotSet;
+                    am.category_id = NotSet;
+                    forum_threads::Entity::update(am).exec(&txn).await?;
+                } else {
+                    dumbsert!(
+                        &txn,
+                        forum_threads::Entity,
+                        &am,
+                        forum_threads::Column::ThreadId,
+                        "Error saving forum thread to database",
+                        true
+                    );
+                }
+                thread_placements::Entity::insert(thread_placements::ActiveModel {
+                    forum_id: Set(forum_id.clone()),
+                    listing: Set(listing.to_string()),
+                    listing_page: Set(page),
+                    listing_position: Set(position as i64 + 1),
+                    thread_id: Set(thread.thread_id.clone()),
+                })
+                .exec(&txn)
+                .await?;
+            }
+        }
+        txn.commit().await?;
+        Ok(())
     }
-}
 
-pub async fn save_threads(gtrs: &Vec<GetThreadResult>) {
-    debug!("save_threads()");
-    for gtr in gtrs {
-        for post in &gtr.posts {
+    /// Stores the posts of page `page` of a thread.
+    pub async fn save_thread(&self, gtr: &GetThreadResult, page: u32) -> anyhow::Result<()> {
+        debug!("save_thread({}, {})", gtr.thread.thread_id, page);
+        let seen_at = now();
+        for (position, post) in gtr.posts.iter().enumerate() {
             let am = forum_posts::ActiveModel {
                 post_id: Set(post.post_id.clone()),
-                post_time: Set(post.post_time.clone()),
+                post_time: Set(post.post_time),
                 post_content: Set(post.post_content.clone()),
                 post_user_id: Set(post.post_user_id.clone()),
-                last_edit_time: Set(post.last_edit_time.clone()),
-                post_unhidden: Set(post.post_unhidden.clone()),
-                post_admin_hidden: Set(post.post_admin_hidden.clone()),
-                post_locked: Set(post.post_locked.clone()),
+                last_edit_time: Set(post.last_edit_time),
+                post_unhidden: Set(post.post_unhidden),
+                post_admin_hidden: Set(post.post_admin_hidden),
+                post_locked: Set(post.post_locked),
                 last_edit_user: Set(post.last_edit_user.clone()),
                 post_username: Set(post.post_username.clone()),
                 thread_id: Set(Some(gtr.thread.thread_id.clone())),
+                last_seen_at: Set(Some(seen_at)),
+                deleted_detected_at: Set(None),
+                thread_page: Set(Some(page.into())),
+                page_position: Set(Some(position as i64 + 1)),
             };
             dumbsert!(
+                &self.conn,
                 forum_posts::Entity,
                 &am,
                 forum_posts::Column::PostId,
@@ -435,111 +518,269 @@ pub async fn save_threads(gtrs: &Vec<GetThreadResult>) {
                 true
             );
         }
+        Ok(())
+    }
+
+    /// Crawls every configured preset down to the last page of every thread (and its images),
+    /// by way of the crawl queue, so an interrupted run carries on where it stopped. Then marks
+    /// what it should have come across but didn't as deleted; see `mark_deleted`.
+    pub async fn get_forums(&self) -> anyhow::Result<()> {
+        self.enqueue(
+            self.config
+                .forum_ids
+                .iter()
+                .flatten()
+                .map(|preset_id| Unit::preset(preset_id)),
+        )
+        .await?;
+        self.crawl_forums().await?;
+        self.mark_deleted().await?;
+        info!("*** Done extracting forums.");
+        Ok(())
+    }
+
+    /// Works through whatever forum units are pending in the queue.
+    pub async fn crawl_forums(&self) -> anyhow::Result<()> {
+        //Each kind of unit only queues up more of its own kind or of the kinds after it.
+        self.crawl(UnitKind::Preset, |u| self.crawl_preset(u))
+            .await?;
+        self.crawl(UnitKind::ForumPage, |u| self.crawl_forum_page(u))
+            .await?;
+        //Thread pages are the bulk of the work, so saving one doesn't hold up fetching the next, and
+        //the images in them are downloaded meanwhile.
+        let threads = self.crawl_pipelined(
+            UnitKind::ThreadPage,
+            |u| self.fetch_thread_page(u),
+            |u, gtr| self.save_thread_page(u, gtr),
+        );
+        self.crawl_alongside(UnitKind::Image, |u| self.crawl_image(u), threads)
+            .await
     }
 }
 
-pub async fn get_forums() -> anyhow::Result<()> {
-    let mut dones: HashSet<String> = HashSet::new();
-    for caf_id in state!().forum_ids.as_ref().unwrap() {
-        let subforum_results;
-        let maybe_caf = get_preset_retry(caf_id).await;
-        if maybe_caf.is_none() {
-            continue;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestExtractor;
+    use sea_orm::{ConnectionTrait, PaginatorTrait, QueryOrder};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn a_page_of_a_thread_that_isnt_stored_is_recorded_as_failed() {
+        let ex = TestExtractor::new("unstored-thread").await;
+        let page = include_str!("../fixtures/example/threads/100/1.json");
+        ex.enqueue([Unit::thread_page("100", 1, "10")])
+            .await
+            .unwrap();
+        ex.crawl_pipelined(
+            UnitKind::ThreadPage,
+            |_| async { Ok(serde_json::from_str::<GetThreadResult>(page)?) },
+            |u, gtr| ex.save_thread_page(u, gtr),
+        )
+        .await
+        .unwrap();
+
+        let posts = forum_posts::Entity::find().count(&ex.conn).await.unwrap();
+        assert_eq!(posts, 0);
+        let failed = failed_items::Entity::find().all(&ex.conn).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].unit_id, "thread_page/100/1");
+        assert_eq!(failed[0].error_class, "missing_parent");
+    }
 
-        let caf = maybe_caf.unwrap(); //Guaranteed to succeed
-        save_preset(caf_id, &caf).await;
-        let maybe_sfis = &state!().subforum_ids;
-        let mut all_subforums: Vec<String> = vec![];
+    #[tokio::test]
+    async fn a_listing_page_of_a_preset_that_isnt_stored_is_recorded_as_failed() {
+        let ex = TestExtractor::new("unstored-preset").await;
+        let page = include_str!("../fixtures/example/forums/10/1.json");
+        ex.enqueue([Unit::forum_page("10", 1, "1")]).await.unwrap();
+        ex.crawl(UnitKind::ForumPage, |_| async {
+            ex.save_subforum(&serde_json::from_str(page)?).await
+        })
+        .await
+        .unwrap();
+
+        let subforums = subforums::Entity::find().count(&ex.conn).await.unwrap();
+        assert_eq!(subforums, 0);
+        let failed = failed_items::Entity::find().all(&ex.conn).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].unit_id, "forum_page/10/1");
+        assert_eq!(failed[0].error_class, "missing_parent");
+    }
 
-        //Add all the subforums.
-        match caf.subforums {
-            SubforumType::MapSubforum(m) => {
-                all_subforums.extend(m.keys().cloned());
-                for sfs in m.values() {
-                    for sf in sfs {
-                        all_subforums.push(sf.forum_id.clone());
-                    }
-                }
-            }
-            SubforumType::SeqSubforum(s) => {
-                for x in s {
-                    all_subforums.push(x.clone());
-                }
-            }
-        }
+    #[tokio::test]
+    async fn a_page_enjin_sent_nonsense_for_is_recorded_as_failed() {
+        let ex = TestExtractor::new("malformed-page").await;
+        let page = include_str!("../fixtures/example/threads/101/1.json");
+        ex.enqueue([
+            Unit::thread_page("101", 1, "10"),
+            Unit::thread_page("102", 1, "10"),
+        ])
+        .await
+        .unwrap();
+        let crawled = ex
+            .crawl_pipelined(
+                UnitKind::ThreadPage,
+                |_| async {
+                    let mut gtr = serde_json::from_str::<GetThreadResult>(page)?;
+                    gtr.pages = serde_json::json!({"of": "many"});
+                    Ok(gtr)
+                },
+                |u, gtr| ex.save_thread_page(u, gtr),
+            )
+            .await;
+        assert!(crawled.is_ok(), "{:?}", crawled);
+
+        let failed = failed_items::Entity::find().all(&ex.conn).await.unwrap();
+        let failed: Vec<(&str, &str)> = failed
+            .iter()
+            .map(|f| (f.unit_id.as_str(), f.error_class.as_str()))
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                ("thread_page/101/1", "malformed"),
+                ("thread_page/102/1", "malformed")
+            ]
+        );
+    }
 
-        //Add the "categories" top-level forums.
-        for foru in caf.categories.values() {
-            for (fid, _) in foru {
-                all_subforums.push(fid.clone());
-            }
-        }
+    #[tokio::test]
+    async fn a_thread_listed_by_several_subforums_keeps_its_own_and_has_a_place_in_each() {
+        let ex = TestExtractor::new("placements").await;
+        let listing = |path| serde_json::from_str::<serde_json::Value>(path).unwrap();
+        let ten = listing(include_str!("../fixtures/example/forums/10/1.json"));
+        let twelve = listing(include_str!("../fixtures/example/forums/12/1.json"));
+        //Subforum 10 is crawled first, announcing 12's thread 102 before 12 is; 12 announces 100.
+        ex.crawl_example(|path, value| match path {
+            "forums/10/1.json" => value["announcement_global"] = json!([twelve["sticky"][0]]),
+            "forums/12/1.json" => value["announcement_global"] = json!([ten["threads"][0]]),
+            _ => {}
+        })
+        .await;
 
-        all_subforums.sort();
-        all_subforums.dedup();
+        let threads = forum_threads::Entity::find()
+            .order_by_asc(forum_threads::Column::ThreadId)
+            .all(&ex.conn)
+            .await
+            .unwrap();
+        let forums: Vec<(&str, &str)> = threads
+            .iter()
+            .map(|t| (t.thread_id.as_str(), t.forum_id.as_str()))
+            .collect();
+        assert_eq!(forums, [("100", "10"), ("101", "10"), ("102", "12")]);
+        let placements = || async {
+            thread_placements::Entity::find()
+                .order_by_asc(thread_placements::Column::ForumId)
+                .order_by_asc(thread_placements::Column::Listing)
+                .order_by_asc(thread_placements::Column::ListingPage)
+                .all(&ex.conn)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|p| {
+                    let place = (p.listing_page, p.listing_position);
+                    (p.forum_id, p.listing, place, p.thread_id)
+                })
+                .collect::<Vec<_>>()
+        };
+        let s = String::from;
+        assert_eq!(
+            placements().await,
+            [
+                (s("10"), s("announcement_global"), (1, 1), s("102")),
+                (s("10"), s("threads"), (1, 1), s("100")),
+                (s("10"), s("threads"), (2, 1), s("101")),
+                (s("12"), s("announcement_global"), (1, 1), s("100")),
+                (s("12"), s("sticky"), (1, 1), s("102"))
+            ]
+        );
 
-        //Call Forum.getForum for every CAF (only for allowed subforums).
-        let allowed_subforums =
-            Vec::from_iter(all_subforums.iter().filter(|subforum_id| match maybe_sfis {
-                Some(sfis) => {
-                    if sfis.len() == 0 {
-                        debug!("subforum_ids.len() == 0");
-                        return true;
-                    } else {
-                        debug!("subforum_ids contains check on {}", subforum_id);
-                        return sfis.contains(subforum_id);
-                    }
-                }
-                None => {
-                    return true;
-                }
-            }));
-        info!(
-            "*** Total number of forums and subforums to scan: {}",
-            allowed_subforums.len()
+        //A page listed again replaces what it listed before.
+        let mut gfr: GetForumResult = serde_json::from_value(twelve.clone()).unwrap();
+        ex.save_subforum(&gfr).await.unwrap();
+        let placed = placements().await;
+        assert_eq!(placed.len(), 4);
+        assert_eq!(placed[3], (s("12"), s("sticky"), (1, 1), s("102")));
+
+        //Announcing a thread that's stored already updates it without moving it.
+        let mut announced = ten["threads"][0].clone();
+        announced["thread_views"] = json!("99");
+        gfr.announcement_global = serde_json::from_value(json!([announced])).unwrap();
+        ex.save_subforum(&gfr).await.unwrap();
+        let thread = forum_threads::Entity::find_by_id("100".to_string())
+            .one(&ex.conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((thread.forum_id.as_str(), thread.thread_views), ("10", 99));
+        assert_eq!(placements().await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn an_update_picks_a_thread_up_on_the_page_its_live_posts_run_out_on() {
+        let ex = TestExtractor::new("first-new-page").await;
+        ex.crawl_example(|_, _| {}).await;
+        assert_eq!(ex.first_new_page("100").await.unwrap(), 2);
+        assert_eq!(ex.first_new_page("102").await.unwrap(), 1);
+
+        //Thread 100 lost its first two posts, and the one left moved up to page 1. The deleted ones
+        //are kept, but an update has to go on from page 1, not from page 3 as counting them would.
+        ex.conn
+            .execute_unprepared(
+                "UPDATE forum_posts SET deleted_detected_at = '2023-06-01T00:00:00+00:00' WHERE post_id IN ('1000', '1001');
+                UPDATE forum_posts SET thread_page = 1, page_position = 1 WHERE post_id = '1002';
+                INSERT INTO forum_posts (post_id, post_time, post_content, post_user_id, post_unhidden, post_admin_hidden, post_locked, last_edit_user, post_username, thread_id, deleted_detected_at, thread_page, page_position)
+                VALUES ('990', '2022-01-01T00:00:00+00:00', 'Long gone', '1', 1, 0, 0, '', 'alice', '100', '2022-06-01T00:00:00+00:00', 3, 1);
+                UPDATE forum_posts SET thread_page = 4 WHERE post_id = '1004';",
+            )
+            .await
+            .unwrap();
+        assert_eq!(ex.first_new_page("100").await.unwrap(), 1);
+        assert_eq!(ex.first_new_page("102").await.unwrap(), 4);
+        let counts = ex.stored_post_counts(vec!["100".into()]).await.unwrap();
+        assert_eq!(counts["100"], 1);
+
+        //Without the pages of some posts, there's no telling.
+        ex.conn
+            .execute_unprepared("UPDATE forum_posts SET thread_page = NULL WHERE post_id = '1004'")
+            .await
+            .unwrap();
+        assert_eq!(ex.first_new_page("102").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn threads_are_unchanged_if_their_reply_count_and_last_post_are() {
+        let ex = TestExtractor::new("unchanged-threads").await;
+        ex.crawl_example(|_, _| {}).await;
+        let stored: HashMap<String, forum_threads::ForumThread> = forum_threads::Entity::find()
+            .all(&ex.conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.thread_id.clone(), t))
+            .collect();
+        let all: Vec<&forum_threads::ForumThread> = stored.values().collect();
+        let unchanged = ex.unchanged_threads(&all).await.unwrap();
+        assert_eq!(
+            unchanged,
+            HashSet::from(["100".into(), "101".into(), "102".into()])
         );
-        subforum_results = get_subforums(allowed_subforums).await;
-        //Call Forum.getThread for every GFR.
-        for gfr in &subforum_results {
-            save_subforum(&gfr).await;
-            let mut inval = vec![];
-            for inv in &gfr.threads {
-                inval.push(inv.thread_id.clone());
-            }
-            debug!(
-                "Sticky thread count for GFR page {} for forum {}: {}",
-                gfr.page,
-                gfr.forum.forum_id,
-                gfr.sticky.len()
-            );
-            for inv in &gfr.sticky {
-                inval.push(inv.thread_id.clone());
-            }
-            for inv in &gfr.notices {
-                inval.push(inv.thread_id.clone());
-            }
-            for inv in &gfr.announcement_local {
-                inval.push(inv.thread_id.clone());
-            }
-            for inv in &gfr.announcement_global {
-                if !dones.contains(&inv.thread_id) {
-                    inval.push(inv.thread_id.clone());
-                    dones.insert(inv.thread_id.clone());
-                }
-            }
-            let threads = get_threads(inval).await;
-            save_threads(&threads).await;
 
-            if state!().do_images {
-                for thread in threads {
-                    for post in thread.posts {
-                        get_images(post.post_id.clone(), post.post_content.clone()).await;
-                    }
-                }
-            }
-        }
+        let mut replied = stored["100"].clone();
+        replied.thread_replies = replied.thread_replies.map(|r| r + 1);
+        let mut bumped = stored["101"].clone();
+        bumped.thread_lastpost_time = bumped
+            .thread_lastpost_time
+            .map(|t| t + Duration::from_secs(60));
+        let mut uncounted = stored["102"].clone();
+        uncounted.thread_replies = None;
+        let mut unstored = stored["102"].clone();
+        unstored.thread_id = "999".to_string();
+        let changed = ex
+            .unchanged_threads(&[&replied, &bumped, &uncounted, &unstored])
+            .await
+            .unwrap();
+        assert!(changed.is_empty(), "{:?}", changed);
     }
-    info!("*** Done extracting forums.");
-    Ok(())
 }
@@ -1,79 +1,134 @@
 /// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
 /// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
 /// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
-use jsonrpsee::core::Error;
-use std::time::Duration;
-use tracing::warn;
+use crate::error::EnjinError;
+use sea_orm::{DbErr, RuntimeErr};
+use serde_json::Value;
 
-#[derive(Debug)]
-pub enum Thing {
-    Preset,
-    ForumIndex,
-    Thread,
-    Application,
-    ApplicationList,
+/// A count or page number as Enjin sends it: a number, a string of one, or null (or an empty
+/// string) for none. Anything else, including a number too big for a `u32`, is `Malformed`.
+pub fn parse_number(val: &Value) -> Result<Option<u32>, EnjinError> {
+    let n = match val {
+        Value::Null => return Ok(None),
+        Value::String(s) if s.trim().is_empty() => return Ok(None),
+        Value::String(s) => s.trim().parse::<u64>().ok(),
+        Value::Number(n) => n.as_u64(),
+        _ => None,
+    };
+    n.and_then(|n| u32::try_from(n).ok())
+        .map(Some)
+        .ok_or_else(|| EnjinError::Malformed(format!("expected a number, got {}", val)))
 }
 
-pub fn parse_number(val: &serde_json::Value) -> Option<u32> {
-    match val {
-        sea_orm::JsonValue::Null => None,
-        sea_orm::JsonValue::Bool(_) => panic!("Expected number, got bool"),
-        sea_orm::JsonValue::Number(n) => Some(n.as_u64().unwrap().try_into().unwrap()),
-        sea_orm::JsonValue::String(s) => Some(s.parse::<u32>().unwrap()),
-        sea_orm::JsonValue::Array(_) => panic!("Expected number, got array"),
-        sea_orm::JsonValue::Object(_) => panic!("Expected number, got object"),
-    }
-}
+/// SQLite's extended result code for a row whose foreign key has nothing to refer to.
+const SQLITE_CONSTRAINT_FOREIGNKEY: &str = "787";
 
-//Slow down the calls ever so slightly to reduce the chance of being rate-limited
-pub async fn whoa(arl: &mut u32) {
-    tokio::time::sleep(Duration::from_millis((100 * *arl).into())).await;
-    if *arl < 5 {
-        *arl += 1;
+/// Whether `e` is SQLite refusing a row because what it belongs to (a subforum's preset, a
+/// thread's subforum or a post's thread) isn't stored.
+pub fn is_missing_parent(e: &migration::DbErr) -> bool {
+    match e {
+        DbErr::Exec(RuntimeErr::SqlxError(e)) | DbErr::Query(RuntimeErr::SqlxError(e)) => {
+            e.as_database_error().and_then(|e| e.code()).as_deref()
+                == Some(SQLITE_CONSTRAINT_FOREIGNKEY)
+        }
+        _ => false,
     }
 }
 
-pub async fn calculate_and_sleep(thing: &Thing, thing_id: &String, e: &Error, tries: &u32) {
-    let mut dur: u32 = 30;
-    if e.to_string().contains("status code: 429") {
-        dur = 30 + (60 * tries * tries); // 30 + 60x^2 quadratic backoff
-        warn!("For {:?} {}: HTTP response code 429 means Enjin rate-limited us for going too fast! Waiting {} seconds.",
-        thing, thing_id, dur);
-    } else {
-        warn!("For {:?} {}: Error {:?}", thing, thing_id, e);
-    }
-    tokio::time::sleep(Duration::from_secs(dur.into())).await;
-}
-
+/// Inserts `$model`, or updates the row if it's already there. A row whose parent isn't stored
+/// makes the enclosing function return `Skipped(MissingParent)`, and any other error is returned
+/// too, unless it's a failed UPDATE and `$fail` is false.
 #[macro_export]
 macro_rules! dumbsert {
-    ($ntt:ty, $model:expr, $column:expr, $error_msg:expr, $do_panic:expr) => {
+    ($conn:expr, $ntt:ty, $model:expr, $column:expr, $error_msg:expr, $fail:expr) => {
         let dumbsert_rslt = <$ntt>::insert($model.to_owned())
             .on_conflict(
                 // on conflict do nothing
                 OnConflict::column($column).do_nothing().to_owned(),
             )
-            .exec(&state!().conn)
+            .exec($conn)
             .await;
         if let Err(dr) = dumbsert_rslt {
             if let migration::DbErr::RecordNotInserted = dr {
                 tracing::debug!("Skipping RecordNotInserted.");
-                let dumbsert_update_rslt =
-                    <$ntt>::update($model.to_owned()).exec(&state!().conn).await;
+                let dumbsert_update_rslt = <$ntt>::update($model.to_owned()).exec($conn).await;
                 if let Err(ddr) = dumbsert_update_rslt {
                     if let migration::DbErr::RecordNotUpdated = ddr {
                         tracing::debug!("Skipping RecordNotUpdated.");
                     } else {
-                        if $do_panic {
-                            panic!("UPDATE Code-up error: {}", ddr);
+                        if $crate::helpers::is_missing_parent(&ddr) {
+                            return Err($crate::queue::Skipped(
+                                $crate::queue::MissingParent($error_msg.to_string()).into(),
+                            )
+                            .into());
+                        } else if $fail {
+                            return Err(anyhow::Error::from(ddr).context($error_msg));
                         } else {
                             tracing::info!("Couldn't update record but continuing: {}", $error_msg);
                         }
                     }
                 }
+            } else if $crate::helpers::is_missing_parent(&dr) {
+                return Err($crate::queue::Skipped(
+                    $crate::queue::MissingParent($error_msg.to_string()).into(),
+                )
+                .into());
             } else {
-                panic!("INSERT Code-up error: {}", dr);
+                return Err(anyhow::Error::from(dr).context($error_msg));
             }
         };
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestExtractor;
+    use sea_orm::ConnectionTrait;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn only_a_foreign_key_failing_is_a_missing_parent() {
+        let ex = TestExtractor::new("missing-parent").await;
+        let e = ex
+            .conn
+            .execute_unprepared(
+                "INSERT INTO forum_threads (thread_id, thread_subject, thread_views, thread_type, thread_status, forum_id, category_id) \
+                VALUES ('100', 'Hello', 1, 'normal', 'open', '10', '5')",
+            )
+            .await
+            .unwrap_err();
+        assert!(is_missing_parent(&e), "{}", e);
+        let e = ex
+            .conn
+            .execute_unprepared("INSERT INTO forum_threads (thread_id) VALUES ('100')")
+            .await
+            .unwrap_err();
+        assert!(!is_missing_parent(&e), "{}", e);
+        //The message alone doesn't make it one.
+        let e = DbErr::Custom("FOREIGN KEY constraint failed".to_string());
+        assert!(!is_missing_parent(&e));
+    }
+
+    #[test]
+    fn numbers_and_strings_of_them_are_read_and_anything_else_is_malformed() {
+        assert_eq!(parse_number(&json!(3)).unwrap(), Some(3));
+        assert_eq!(parse_number(&json!(" 12 ")).unwrap(), Some(12));
+        assert_eq!(parse_number(&json!(null)).unwrap(), None);
+        assert_eq!(parse_number(&json!("")).unwrap(), None);
+        for bad in [
+            json!(true),
+            json!({"pages": 2}),
+            json!([1]),
+            json!("two"),
+            json!(-1),
+            json!(1.5),
+            json!(u64::from(u32::MAX) + 1),
+        ] {
+            match parse_number(&bad) {
+                Err(EnjinError::Malformed(_)) => {}
+                other => panic!("{} gave {:?}", bad, other),
+            }
+        }
+    }
+}
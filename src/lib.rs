@@ -0,0 +1,33 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+pub mod api;
+pub mod applications;
+pub mod cassette;
+pub mod commands;
+pub mod completeness;
+pub mod concurrency;
+pub mod config;
+pub mod error;
+pub mod extractor;
+pub mod forum;
+pub mod helpers;
+pub mod mock;
+pub mod plan;
+pub mod queue;
+pub mod ratelimit;
+pub mod retry;
+pub mod revisions;
+pub mod runs;
+pub mod shutdown;
+//pub mod wiki;
+pub mod structures;
+#[cfg(test)]
+mod testing;
+pub mod tombstones;
+pub mod upgrade;
+
+pub use api::EnjinApi;
+pub use config::Config;
+pub use error::EnjinError;
+pub use extractor::Extractor;
@@ -1,114 +1,30 @@
 /// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
 /// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
 /// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
-pub mod forum;
-pub mod helpers;
-pub mod applications;
-//pub mod wiki;
-pub mod structures;
+mod cli;
 
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering;
+use std::path::Path;
 use std::sync::Mutex;
-use std::time::Duration;
-
-use crate::applications::get_apps;
-use crate::forum::*;
-use crate::structures::*;
-use dotenvy::var;
-use lazy_static::lazy_static;
-use migration::Migrator;
-use migration::MigratorTrait;
-
-use sea_orm::Database;
-use secrecy::SecretString;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cli::*;
+use clap::Parser;
+use encuum::commands::{snippet, EXPORT_TABLES};
+use encuum::error::EnjinError;
+use encuum::shutdown::Shutdown;
+use encuum::{Config, Extractor};
+
 use tokio_cron_scheduler::Job;
 use tokio_cron_scheduler::JobScheduler;
+use tracing::error;
 use tracing::info;
+use tracing::level_filters::LevelFilter;
 use tracing::warn;
-use tracing::Level;
 use tracing_subscriber::EnvFilter;
 
-impl State {
-    pub async fn new() -> Self {
-        let forum_preset_ids = var("forum_ids").ok();
-        let forum_ids: Option<Vec<String>> = match forum_preset_ids {
-            Some(fpis) => Some(fpis.split(",").map(|s| s.to_string()).collect()),
-            None => None,
-        };
-
-        let subforum_ids_opt = var("subforum_ids").ok();
-        let subforum_ids: Option<Vec<String>> = match subforum_ids_opt {
-            Some(fpis) => Some(fpis.split(",").map(|s| s.to_string()).collect()),
-            None => None,
-        };
-
-        let filename = var("database_file").expect("database_file must be set");
-        let conn = Database::connect(format!("sqlite://./{}?mode=rwc", filename))
-            .await
-            .expect(format!("Can't open DB {}", filename).as_str());
-        Migrator::up(&conn, None)
-            .await
-            .expect("Failed to bring DB schema up");
-
-        let email = var("email").expect("Required .env variable missing: email");
-        let password = var("password").expect("Required .env variable missing: password");
-        let sanitize_log = var("sanitize_log")
-            .unwrap_or("false".to_string())
-            .parse()
-            .unwrap();
-
-        let mut session_id = match var("session_id").ok() {
-            Some(s) => Some(SecretString::new(s)),
-            None => None,
-        };
-
-        if session_id.is_none() {
-            let resp = SEE
-                .login(&email, &password)
-                .await
-                .expect("FATAL ERROR: Login failed");
-            if sanitize_log {
-                info!("Your session ID is: {}", resp.session_id);
-            }
-            session_id = Some(SecretString::new(resp.session_id));
-            if session_id.is_none() {
-                panic!("FATAL ERROR: Login failed");
-            }
-        }
-
-        State {
-            email: email,
-            password: SecretString::new(password),
-            session_id: session_id,
-            forum_ids: forum_ids,
-            subforum_ids: subforum_ids,
-            keep_going: var("keep_going")
-                .unwrap_or("false".to_string())
-                .parse()
-                .unwrap(),
-            do_images: var("do_images")
-                .unwrap_or("true".to_string())
-                .parse()
-                .unwrap(),
-            do_apps: var("do_apps")
-                .unwrap_or("true".to_string())
-                .parse()
-                .unwrap(),
-            sanitize_log: sanitize_log,
-            req_client: reqwest::Client::new(),
-            conn: conn,
-        }
-    }
-}
-
-lazy_static! {
-    static ref STOPPIT: AtomicBool = AtomicBool::new(false);
-}
-
 struct MultiWriter {
     writers: Vec<Box<dyn Write + Send + Sync>>,
 }
@@ -129,37 +45,37 @@ impl Write for MultiWriter {
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    STATE
-        .set(State::new().await)
-        .expect("State couldn't be set");
-
+fn init_logging(log_level: &str, log_file: Option<&String>) {
     let mut writers: Vec<Box<dyn Write + Send + Sync>> = vec![(Box::new(std::io::stderr()))];
-    if let Some(log_file) = var("log_file").ok() {
+    if let Some(log_file) = log_file {
         writers.push(Box::new(BufWriter::new(File::create(log_file).unwrap())));
     }
     let mw = Mutex::new(MultiWriter { writers });
 
-    let tsb = tracing_subscriber::FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::from_default_env().add_directive("sqlx=warn".parse().unwrap()))
+    let level = match log_level.to_uppercase().as_str() {
+        "TRACE" => LevelFilter::TRACE,
+        "DEBUG" => LevelFilter::DEBUG,
+        "WARN" => LevelFilter::WARN,
+        "ERROR" => LevelFilter::ERROR,
+        _ => LevelFilter::INFO,
+    };
+
+    tracing_subscriber::FmtSubscriber::builder()
+        .with_env_filter(
+            EnvFilter::from_default_env()
+                .add_directive(level.into())
+                .add_directive("sqlx=warn".parse().unwrap()),
+        )
         .with_ansi(false)
-        .with_writer(mw);
-
-    if let Ok(log_level) = var("log_level") {
-        match log_level.to_uppercase().as_str() {
-            "TRACE" => tsb.with_max_level(Level::TRACE),
-            "DEBUG" => tsb.with_max_level(Level::DEBUG),
-            "INFO" => tsb.with_max_level(Level::INFO),
-            "WARN" => tsb.with_max_level(Level::WARN),
-            "ERROR" => tsb.with_max_level(Level::ERROR),
-            _ => tsb.with_max_level(Level::INFO),
-        }
+        .with_writer(mw)
         .try_init()
         .expect("setting default subscriber failed");
-    }
+}
 
-    let sched = JobScheduler::new().await?;
+async fn extract(extractor: &mut Extractor, args: ExtractArgs) -> anyhow::Result<i32> {
+    extractor.login().await?;
+
+    let mut sched = JobScheduler::new().await?;
 
     let stats_job =
         Job::new_repeated(
@@ -179,41 +95,302 @@ async fn main() -> anyhow::Result<()> {
         )?;
 
     sched.add(stats_job).await?;
+    sched.start().await?;
 
-    let oneshot = Job::new_one_shot_at_instant_async(
-        std::time::Instant::now(),
-        |_a, mut schedd| {
-            Box::pin(async move {
-                if state!().forum_ids.is_some() {
-                    get_forums().await.unwrap();
-                } else {
-                    warn!("You didn't specify the environment variable `forum_ids`, so the tool is not going to extract anything from the forums. If this isn't what you intended, modify your .env file (or environment variable) for forum_ids according to the instructions in README.md.");
-                }
+    extractor.start_crawl().await?;
 
-                if state!().do_apps {
-                    info!("Doing apps");
-                    get_apps().await.unwrap();
-                } else {
-                    warn!("`do_apps` variable set to false, so the tool is not going to extract anything from the apps. If this isn't what you intended, modify your .env file (or environment variable) for do_apps according to the instructions in README.md.");
-                }
+    if args.skip_forums {
+        info!("--skip-forums given, so the tool is not going to extract anything from the forums.");
+    } else if extractor.config.forum_ids.is_some() {
+        extractor.get_forums().await?;
+    } else {
+        warn!("You didn't specify `forum_ids`, so the tool is not going to extract anything from the forums. If this isn't what you intended, set forum_ids in your config file (or ENCUUM_FORUM_IDS) according to the instructions in README.md.");
+    }
 
-                STOPPIT.store(true, Ordering::Relaxed);
-                info!("*** Stopping tasks...");
-                schedd.shutdown().await.unwrap();
-            })
-        },
-    )?;
-    sched.add(oneshot).await?;
+    if extractor.config.do_apps {
+        info!("Doing apps");
+        extractor.get_apps().await?;
+    } else {
+        warn!("`do_apps` set to false, so the tool is not going to extract anything from the apps. If this isn't what you intended, set do_apps in your config file (or ENCUUM_DO_APPS) according to the instructions in README.md.");
+    }
 
-    sched.start().await?;
+    info!("*** Stopping tasks...");
+    sched.shutdown().await?;
+    info!("Encuum exited normally.");
+    Ok(EXIT_SUCCESS)
+}
+
+async fn retry_failed(extractor: &mut Extractor) -> anyhow::Result<i32> {
+    extractor.login().await?;
+    extractor.hold_unfinished().await?;
+    let result = retry(extractor).await;
+    extractor.release_held().await?;
+    result
+}
+
+async fn retry(extractor: &mut Extractor) -> anyhow::Result<i32> {
+    let requeued = extractor.requeue_failed().await?;
+    if requeued == 0 {
+        info!("Nothing has failed, so there is nothing to retry.");
+        return Ok(EXIT_SUCCESS);
+    }
+    info!("*** Retrying {} failed items.", requeued);
+    //Anything they queue up, like the rest of a thread's pages, is crawled too.
+    extractor.crawl_forums().await?;
+    extractor.crawl_apps().await?;
+    let still_failing = extractor.failed_count().await?;
+    if still_failing > 0 {
+        warn!(
+            "{} of {} items failed again; run retry-failed later to try them once more.",
+            still_failing, requeued
+        );
+    } else {
+        info!("All {} failed items were extracted this time.", requeued);
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+async fn mock_server(args: &MockServerArgs) -> anyhow::Result<i32> {
+    let (addr, handle) = encuum::mock::serve(&args.fixtures, args.listen).await?;
+    info!(
+        "Mock Enjin API serving {} at http://{}/ - set `endpoint` to this URL to use it. Press Ctrl+C to stop.",
+        args.fixtures.display(),
+        addr
+    );
+    tokio::signal::ctrl_c().await?;
+    handle.stop()?;
+    Ok(EXIT_SUCCESS)
+}
+
+/// Waits for Ctrl+C, or SIGTERM where there is such a thing.
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("can't listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
 
-    // Wait a while so that the jobs actually run
-    loop {
-        tokio::time::sleep(core::time::Duration::from_secs(10)).await;
-        if STOPPIT.load(Ordering::Relaxed) {
-            info!("Encuum exited normally.");
-            break;
+/// On the first signal, asks the crawl to stop once what's in progress is saved; on a second one,
+/// quits right away. Either way, the next run resumes from the `crawl_queue` table.
+fn stop_on_signal(shutdown: Shutdown) {
+    tokio::spawn(async move {
+        stop_signal().await;
+        warn!(
+            "*** Stopping: finishing the work in progress. Press Ctrl+C again to quit right away."
+        );
+        shutdown.request();
+        stop_signal().await;
+        warn!("*** Quitting without waiting. Nothing saved so far is lost.");
+        std::process::exit(EXIT_INTERRUPTED);
+    });
+}
+
+/// Turns a crawl that `stop_on_signal` stopped into a summary of where it got to and `EXIT_INTERRUPTED`.
+async fn interrupted(extractor: &Extractor, result: anyhow::Result<i32>) -> anyhow::Result<i32> {
+    match result {
+        Err(e) if matches!(e.downcast_ref(), Some(EnjinError::Interrupted)) => {
+            let summary: Vec<String> = extractor
+                .checkpoint()
+                .await?
+                .iter()
+                .map(|(state, count)| format!("{} {}", count, state))
+                .collect();
+            warn!(
+                "*** Stopped before finishing. Units of work: {}. Run the same command again to carry on from here.",
+                summary.join(", ")
+            );
+            Ok(EXIT_INTERRUPTED)
+        }
+        other => other,
+    }
+}
+
+/// Records how run `run_id` ended in the `runs` table, then passes its result on.
+async fn record_run(
+    extractor: &Extractor,
+    run_id: i32,
+    result: anyhow::Result<i32>,
+) -> anyhow::Result<i32> {
+    let exit_code = match &result {
+        Ok(code) => *code,
+        Err(_) => EXIT_FAILURE,
+    };
+    if let Err(e) = extractor.finish_run(run_id, exit_code).await {
+        warn!("Couldn't record the end of run {}: {:#}", run_id, e);
+    }
+    result
+}
+
+/// Upgrades the database without opening an `Extractor`, which refuses one that needs upgrading.
+async fn upgrade(config: &Config, args: &UpgradeArgs) -> anyhow::Result<i32> {
+    let filename = &config.database_file;
+    if !Path::new(filename).exists() {
+        anyhow::bail!("There is no database {} to upgrade", filename);
+    }
+    let conn = encuum::upgrade::connect(filename).await?;
+    if encuum::upgrade::pending(&conn).await?.is_empty() {
+        info!("{} is already up to date.", filename);
+        return Ok(EXIT_SUCCESS);
+    }
+    if !args.no_backup {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let backup = format!("{}.before-upgrade-{}", filename, now);
+        encuum::upgrade::backup(&conn, Path::new(&backup)).await?;
+        info!("Backed {} up to {}", filename, backup);
+    }
+    let applied = encuum::upgrade::upgrade(&conn).await?;
+    for (name, summary) in &applied {
+        println!("{}: {}", name, summary);
+    }
+    info!("Upgraded {} with {} migrations.", filename, applied.len());
+    Ok(EXIT_SUCCESS)
+}
+
+async fn run(cli: Cli, config: Config) -> anyhow::Result<i32> {
+    if let Command::Upgrade(args) = &cli.command {
+        return upgrade(&config, args).await;
+    }
+    if let Command::Extract(args) = &cli.command {
+        if args.dry_run {
+            // Only Enjin is asked, so the database isn't created or even opened.
+            let mut extractor = Extractor::without_database(config)?;
+            extractor.login().await?;
+            print!("{}", extractor.plan().await?);
+            return Ok(EXIT_SUCCESS);
+        }
+    }
+    let mut extractor = Extractor::new(config).await?;
+    match cli.command {
+        Command::Extract(args) => {
+            let run_id = extractor.start_run("extract").await?;
+            stop_on_signal(extractor.shutdown.clone());
+            let result = extract(&mut extractor, args).await;
+            let result = interrupted(&extractor, result).await;
+            record_run(&extractor, run_id, result).await
         }
+        Command::RetryFailed(_) => {
+            let run_id = extractor.start_run("retry-failed").await?;
+            stop_on_signal(extractor.shutdown.clone());
+            let result = retry_failed(&mut extractor).await;
+            let result = interrupted(&extractor, result).await;
+            record_run(&extractor, run_id, result).await
+        }
+        Command::Status => {
+            for (table, count) in extractor.status().await? {
+                println!("{:<16}{}", format!("{}:", table), count);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        Command::Verify(args) => {
+            let mut problems = 0;
+            for (what, count) in extractor.verify().await? {
+                println!("{}: {}", what, count);
+                problems += count;
+            }
+            let gaps = extractor.completeness().await?;
+            println!(
+                "presets, subforums and threads missing threads or posts: {}",
+                gaps.len()
+            );
+            for gap in &gaps {
+                println!("  {}", gap);
+            }
+            if args.save_gaps {
+                extractor.save_gaps(&gaps).await?;
+                info!("Saved {} gaps to the completeness_gaps table", gaps.len());
+            }
+            problems += gaps.len() as i64;
+            if problems > 0 {
+                warn!("verify found {} problems", problems);
+                return Ok(EXIT_VERIFY_FAILED);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        Command::Export(args) => {
+            let tables = args
+                .tables
+                .unwrap_or(EXPORT_TABLES.iter().map(|t| t.to_string()).collect());
+            extractor
+                .export(&args.output, &tables, !args.exclude_deleted)
+                .await?;
+            Ok(EXIT_SUCCESS)
+        }
+        Command::Search(args) => {
+            let found = extractor
+                .search(
+                    &args.pattern,
+                    args.thread_id.as_ref(),
+                    args.username.as_ref(),
+                    !args.exclude_deleted,
+                    args.limit,
+                )
+                .await?;
+            for post in &found {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    post.post_id,
+                    post.thread_id.as_deref().unwrap_or("?"),
+                    post.post_username,
+                    snippet(&post.post_content, &args.pattern)
+                );
+            }
+            info!("{} matching posts", found.len());
+            Ok(EXIT_SUCCESS)
+        }
+        Command::Call(args) => {
+            if !args.no_session {
+                extractor.login().await?;
+            }
+            let result = extractor
+                .call(&args.method, &args.params, !args.no_session)
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&result)?);
+            Ok(EXIT_SUCCESS)
+        }
+        Command::Upgrade(_) => unreachable!("handled before opening the database"),
+        Command::MockServer(_) => unreachable!("handled before loading the config"),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    //The mock server stands in for Enjin, so it doesn't need a site, credentials or a database.
+    if let Command::MockServer(args) = &cli.command {
+        init_logging(cli.log_level.as_deref().unwrap_or("INFO"), None);
+        let code = match mock_server(args).await {
+            Ok(code) => code,
+            Err(e) => {
+                error!("{:#}", e);
+                EXIT_FAILURE
+            }
+        };
+        std::process::exit(code);
     }
-    Ok(())
+    let config = match Config::load(cli.config_source()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprint!("{}", e);
+            std::process::exit(EXIT_BAD_CONFIG);
+        }
+    };
+    init_logging(&config.log_level, config.log_file.as_ref());
+    info!("Using profile `{}`", config.profile);
+    let code = match run(cli, config).await {
+        Ok(code) => code,
+        Err(e) => {
+            error!("{:#}", e);
+            EXIT_FAILURE
+        }
+    };
+    std::process::exit(code);
 }
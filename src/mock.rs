@@ -0,0 +1,334 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use hyper::{Body, Request, Response, StatusCode};
+use jsonrpsee::server::{ServerBuilder, ServerHandle};
+use jsonrpsee::types::error::{CallError, ErrorObject};
+use jsonrpsee::RpcModule;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{debug, info, warn};
+
+pub const MOCK_SESSION_ID: &str = "mock-session";
+
+/// Enjin's generic application error code.
+const ENJIN_ERROR_CODE: i32 = -32000;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+    /// A JSON-RPC error saying we aren't allowed to see the item.
+    Noaccess,
+    /// A JSON-RPC error with `message`.
+    Error,
+    /// HTTP 429 Too Many Requests, with a `Retry-After` header if `retry_after` is set.
+    RateLimit,
+    /// HTTP 200 with a body that isn't valid JSON.
+    Malformed,
+}
+
+/// One injected failure, read from `faults.json`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Fault {
+    pub method: String,
+    /// Only calls whose parameters include all of these match. Values are compared as strings,
+    /// so `"page": 2` matches both `2` and `"2"`.
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    pub kind: FaultKind,
+    pub message: Option<String>,
+    pub retry_after: Option<u64>,
+    /// How many matching calls fail before the fault stops applying. Forever if not set.
+    pub times: Option<u32>,
+}
+
+fn as_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Null => "".to_string(),
+        other => other.to_string(),
+    }
+}
+
+impl Fault {
+    fn matches(&self, method: &str, params: &Map<String, Value>) -> bool {
+        self.method == method
+            && self.params.iter().all(|(k, want)| {
+                params
+                    .get(k)
+                    .map(|got| as_string(got) == as_string(want))
+                    .unwrap_or(false)
+            })
+    }
+}
+
+/// The faults still to be injected, shared by every connection.
+#[derive(Default)]
+pub struct Faults {
+    faults: Mutex<Vec<Fault>>,
+}
+
+impl Faults {
+    pub fn load(fixtures: &Path) -> anyhow::Result<Self> {
+        let path = fixtures.join("faults.json");
+        let faults = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(Faults {
+            faults: Mutex::new(faults),
+        })
+    }
+
+    /// The fault to inject for this call, if any, using up one of its `times`.
+    fn take(&self, method: &str, params: &Map<String, Value>) -> Option<Fault> {
+        let mut faults = self.faults.lock().unwrap();
+        let fault = faults
+            .iter_mut()
+            .find(|f| f.matches(method, params) && f.times != Some(0))?;
+        if let Some(times) = fault.times.as_mut() {
+            *times -= 1;
+        }
+        Some(fault.clone())
+    }
+}
+
+fn enjin_error(message: &str) -> jsonrpsee::core::Error {
+    CallError::Custom(ErrorObject::owned(
+        ENJIN_ERROR_CODE,
+        message.to_string(),
+        None::<()>,
+    ))
+    .into()
+}
+
+fn param(params: &Map<String, Value>, name: &str) -> Option<String> {
+    params.get(name).map(as_string).filter(|s| !s.is_empty())
+}
+
+fn page(params: &Map<String, Value>) -> String {
+    param(params, "page").unwrap_or("1".to_string())
+}
+
+struct Fixtures {
+    root: PathBuf,
+}
+
+impl Fixtures {
+    fn read(&self, relative: PathBuf) -> Result<Value, jsonrpsee::core::Error> {
+        let path = self.root.join(&relative);
+        let text = std::fs::read_to_string(&path).map_err(|_| {
+            debug!("No fixture {}", path.display());
+            enjin_error("The result is empty")
+        })?;
+        serde_json::from_str(&text).map_err(|e| {
+            warn!("Fixture {} isn't valid JSON: {}", path.display(), e);
+            enjin_error("Invalid fixture")
+        })
+    }
+
+    fn respond(
+        &self,
+        method: &str,
+        params: &Map<String, Value>,
+    ) -> Result<Value, jsonrpsee::core::Error> {
+        if method == "User.login" {
+            return match self.read(PathBuf::from("login.json")) {
+                Ok(v) => Ok(v),
+                Err(_) => Ok(json!({ "session_id": MOCK_SESSION_ID })),
+            };
+        }
+        if param(params, "session_id").is_none() {
+            return Err(enjin_error("Session ID is missing"));
+        }
+        let required = |name: &str| {
+            param(params, name).ok_or_else(|| enjin_error(&format!("Missing parameter {}", name)))
+        };
+        let relative = match method {
+            "Forum.getCategoriesAndForums" => {
+                PathBuf::from("categories").join(format!("{}.json", required("preset_id")?))
+            }
+            "Forum.getForum" => PathBuf::from("forums")
+                .join(required("forum_id")?)
+                .join(format!("{}.json", page(params))),
+            "Forum.getThread" => PathBuf::from("threads")
+                .join(required("thread_id")?)
+                .join(format!("{}.json", page(params))),
+            "Applications.getTypes" => PathBuf::from("applications/types.json"),
+            "Applications.getList" => PathBuf::from("applications/list")
+                .join(required("type")?)
+                .join(format!("{}.json", page(params))),
+            "Applications.getApplication" => {
+                PathBuf::from("applications").join(format!("{}.json", required("application_id")?))
+            }
+            _ => unreachable!("only registered methods get here"),
+        };
+        self.read(relative)
+    }
+}
+
+pub const MOCK_METHODS: [&str; 7] = [
+    "User.login",
+    "Forum.getCategoriesAndForums",
+    "Forum.getForum",
+    "Forum.getThread",
+    "Applications.getTypes",
+    "Applications.getList",
+    "Applications.getApplication",
+];
+
+/// A stand-in for the Enjin API, serving JSON-RPC over HTTP from a directory of fixtures.
+///
+/// Fixture layout, relative to the fixture directory:
+///
+/// - `login.json`: the `User.login` result (default: a fixed session ID)
+/// - `categories/<preset_id>.json`: `Forum.getCategoriesAndForums`
+/// - `forums/<forum_id>/<page>.json`: `Forum.getForum`
+/// - `threads/<thread_id>/<page>.json`: `Forum.getThread`
+/// - `applications/types.json`: `Applications.getTypes`
+/// - `applications/list/<type>/<page>.json`: `Applications.getList`
+/// - `applications/<application_id>.json`: `Applications.getApplication`
+/// - `faults.json`: a list of `Fault`s to inject
+///
+/// `page` defaults to 1 when the client doesn't send one. A call with no matching fixture
+/// gets the same "The result is empty" error Enjin gives.
+///
+/// Starts the server on `addr` and returns the address it actually bound (useful with port 0).
+pub async fn serve(
+    fixtures: &Path,
+    addr: SocketAddr,
+) -> anyhow::Result<(SocketAddr, ServerHandle)> {
+    let faults = Arc::new(Faults::load(fixtures)?);
+    let mut module = RpcModule::new(Fixtures {
+        root: fixtures.to_path_buf(),
+    });
+    for method in MOCK_METHODS {
+        module.register_method(method, move |params, fixtures| {
+            let params: Map<String, Value> = params.parse().unwrap_or_default();
+            info!("{} {}", method, crate::api::describe(&params));
+            fixtures.respond(method, &params)
+        })?;
+    }
+    let server = ServerBuilder::default()
+        .http_only()
+        .set_middleware(tower::ServiceBuilder::new().layer(FaultLayer { faults }))
+        .build(addr)
+        .await?;
+    let local_addr = server.local_addr()?;
+    Ok((local_addr, server.start(module)?))
+}
+
+/// Injects `Fault`s in front of the JSON-RPC service. It works on the raw HTTP exchange so it can
+/// produce things a well-behaved JSON-RPC server never would, like 429s and broken JSON.
+#[derive(Clone)]
+struct FaultLayer {
+    faults: Arc<Faults>,
+}
+
+impl<S> Layer<S> for FaultLayer {
+    type Service = FaultService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FaultService {
+            inner,
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct FaultService<S> {
+    inner: S,
+    faults: Arc<Faults>,
+}
+
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+#[derive(Deserialize)]
+struct RawCall {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Option<Map<String, Value>>,
+}
+
+fn fault_response(fault: &Fault, id: Value) -> Result<Response<Body>, hyper::http::Error> {
+    match fault.kind {
+        FaultKind::RateLimit => {
+            let mut resp = Response::builder().status(StatusCode::TOO_MANY_REQUESTS);
+            if let Some(secs) = fault.retry_after {
+                resp = resp.header("Retry-After", secs.to_string());
+            }
+            resp.body(Body::from("Too Many Requests"))
+        }
+        FaultKind::Malformed => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(format!(
+                "{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{{\"posts\":[",
+                id
+            ))),
+        FaultKind::Noaccess | FaultKind::Error => {
+            let message = match fault.kind {
+                FaultKind::Noaccess => "noaccess".to_string(),
+                _ => fault.message.clone().unwrap_or("Mock error".to_string()),
+            };
+            let body = json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": ENJIN_ERROR_CODE, "message": message },
+            });
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for FaultService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        //The clone isn't necessarily ready; keep the one that is and leave the clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let faults = self.faults.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let bytes = hyper::body::to_bytes(body).await?;
+            if let Ok(call) = serde_json::from_slice::<RawCall>(&bytes) {
+                let params = call.params.unwrap_or_default();
+                if let Some(fault) = faults.take(&call.method, &params) {
+                    info!(
+                        "Injecting {:?} into {} {:?}",
+                        fault.kind, call.method, params
+                    );
+                    return Ok(fault_response(&fault, call.id.unwrap_or(Value::Null))?);
+                }
+            }
+            inner
+                .call(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        })
+    }
+}
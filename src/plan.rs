@@ -0,0 +1,329 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::extractor::Extractor;
+use crate::helpers::parse_number;
+use crate::structures::{GetCafResult, SubforumType};
+use futures::{stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+use tracing::info;
+
+/// How many posts a thread page is taken to hold. The real number is a forum setting that we only
+/// see once a thread is fetched, which a plan doesn't do.
+pub const ASSUMED_POSTS_PER_PAGE: u64 = 20;
+
+/// What extracting one subforum would take, going by the first page of its thread listing.
+pub struct SubforumPlan {
+    pub forum_id: String,
+    pub forum_name: String,
+    pub parent_id: Option<String>,
+    /// Pages of the thread listing.
+    pub pages: u64,
+    /// An estimate: the last listing page may not be full.
+    pub threads: u64,
+    /// An estimate, from the reply counts on the first listing page.
+    pub thread_pages: u64,
+    /// Why the first page couldn't be fetched, if it couldn't.
+    pub error: Option<String>,
+}
+
+pub struct PresetPlan {
+    pub preset_id: String,
+    pub title: String,
+    /// What Enjin says the preset holds, subforums we'd leave out included.
+    pub total_threads: Option<u32>,
+    pub total_posts: Option<u32>,
+    pub subforums: Vec<SubforumPlan>,
+}
+
+/// What `extract` would fetch from the forums, worked out without fetching any posts or images.
+pub struct Plan {
+    pub presets: Vec<PresetPlan>,
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+impl Plan {
+    /// API calls for the forums: logging in, each preset, every listing page and every thread page.
+    pub fn requests(&self) -> u64 {
+        let subforums = self.presets.iter().flat_map(|p| &p.subforums);
+        1 + self.presets.len() as u64 + subforums.map(|sf| sf.pages + sf.thread_pages).sum::<u64>()
+    }
+
+    /// How long `requests` take under the rate limit, or `None` if there isn't one.
+    pub fn duration(&self) -> Option<Duration> {
+        if self.requests_per_second <= 0.0 {
+            return None;
+        }
+        let limited = self.requests().saturating_sub(self.burst as u64);
+        Some(Duration::from_secs_f64(
+            limited as f64 / self.requests_per_second,
+        ))
+    }
+
+    fn write_tree(
+        f: &mut fmt::Formatter<'_>,
+        subforums: &[SubforumPlan],
+        parent: Option<&str>,
+        depth: usize,
+    ) -> fmt::Result {
+        let ids: HashSet<&str> = subforums.iter().map(|sf| sf.forum_id.as_str()).collect();
+        for sf in subforums {
+            //A subforum whose parent isn't being crawled is shown at the top.
+            let shown_under = sf.parent_id.as_deref().filter(|p| ids.contains(p));
+            if shown_under != parent {
+                continue;
+            }
+            write!(
+                f,
+                "{:indent$}Forum {} {}: ",
+                "",
+                sf.forum_id,
+                sf.forum_name,
+                indent = 2 * depth + 2
+            )?;
+            match &sf.error {
+                Some(e) => writeln!(f, "can't be read ({})", e)?,
+                None => writeln!(
+                    f,
+                    "{} listing pages, ~{} threads, ~{} thread pages",
+                    sf.pages, sf.threads, sf.thread_pages
+                )?,
+            }
+            Plan::write_tree(f, subforums, Some(&sf.forum_id), depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for preset in &self.presets {
+            writeln!(
+                f,
+                "Preset {} {}: {} subforums to crawl ({} threads and {} posts in the whole preset)",
+                preset.preset_id,
+                preset.title,
+                preset.subforums.len(),
+                preset
+                    .total_threads
+                    .map_or("?".to_string(), |n| n.to_string()),
+                preset
+                    .total_posts
+                    .map_or("?".to_string(), |n| n.to_string()),
+            )?;
+            Plan::write_tree(f, &preset.subforums, None, 0)?;
+        }
+        let subforums = self.presets.iter().flat_map(|p| &p.subforums);
+        let (pages, threads, thread_pages) = subforums.fold((0, 0, 0), |(p, t, tp), sf| {
+            (p + sf.pages, t + sf.threads, tp + sf.thread_pages)
+        });
+        writeln!(f)?;
+        writeln!(f, "Listing pages:  {}", pages)?;
+        writeln!(f, "Threads:        ~{}", threads)?;
+        writeln!(
+            f,
+            "Thread pages:   ~{} (assuming {} posts per page)",
+            thread_pages, ASSUMED_POSTS_PER_PAGE
+        )?;
+        writeln!(
+            f,
+            "API requests:   ~{} (plus one per image and the applications, if enabled)",
+            self.requests()
+        )?;
+        match self.duration() {
+            Some(d) => {
+                let secs = d.as_secs();
+                writeln!(
+                    f,
+                    "Estimated time: ~{}h {:02}m {:02}s at {} requests per second",
+                    secs / 3600,
+                    secs % 3600 / 60,
+                    secs % 60,
+                    self.requests_per_second
+                )
+            }
+            None => writeln!(
+                f,
+                "Estimated time: unknown, since requests_per_second is 0 (no limit)"
+            ),
+        }
+    }
+}
+
+/// How many pages a thread with `replies` replies takes up.
+fn thread_pages(replies: Option<i64>) -> u64 {
+    let posts = replies.and_then(|r| u64::try_from(r).ok()).unwrap_or(0) + 1;
+    posts.div_ceil(ASSUMED_POSTS_PER_PAGE)
+}
+
+impl Extractor {
+    /// Works out what extracting the configured forums would take, by calling only
+    /// `Forum.getCategoriesAndForums` for each preset and `Forum.getForum` for the first page of
+    /// each subforum that would be crawled. Nothing is saved.
+    pub async fn plan(&self) -> anyhow::Result<Plan> {
+        let mut presets = vec![];
+        for preset_id in self.config.forum_ids.iter().flatten() {
+            let caf = self
+                .api
+                .get_categories_and_forums(self.session()?, preset_id)
+                .await?;
+            info!(
+                "Planning preset {} ({})",
+                preset_id, caf.settings.title_welcome
+            );
+            presets.push(self.plan_preset(preset_id, &caf).await?);
+        }
+        Ok(Plan {
+            presets,
+            requests_per_second: self.config.requests_per_second,
+            burst: self.config.burst,
+        })
+    }
+
+    async fn plan_preset(&self, preset_id: &str, caf: &GetCafResult) -> anyhow::Result<PresetPlan> {
+        //Names for the subforums we can't read, which getForum would otherwise tell us.
+        let mut names: HashMap<&str, &str> = HashMap::new();
+        if let SubforumType::MapSubforum(m) = &caf.subforums {
+            for sf in m.values().flatten() {
+                names.insert(&sf.forum_id, &sf.forum_name);
+            }
+        }
+        for sf in caf.categories.values().flat_map(|c| c.values()) {
+            names.insert(&sf.forum_id, &sf.forum_name);
+        }
+
+        let session = self.session()?;
+        let in_flight = self.config.concurrency.for_method("Forum.getForum");
+        let first_pages: Vec<_> = stream::iter(self.allowed_subforums(caf))
+            .map(|forum_id| async move {
+                let gfr = self.api.get_forum(session, &forum_id, None).await;
+                (forum_id, gfr)
+            })
+            .buffered(in_flight)
+            .collect()
+            .await;
+
+        //Global announcements are listed in every forum, but only fetched once.
+        let mut global_seen = HashSet::new();
+        let mut subforums = vec![];
+        for (forum_id, gfr) in first_pages {
+            let listing = gfr.and_then(|gfr| Ok((parse_number(&gfr.pages)?, gfr)));
+            let (pages, gfr) = match listing {
+                Ok((pages, gfr)) => (pages.unwrap_or(1) as u64, gfr),
+                Err(e) if e.is_fatal() => return Err(e.into()),
+                Err(e) => {
+                    subforums.push(SubforumPlan {
+                        forum_name: names.get(forum_id.as_str()).unwrap_or(&"?").to_string(),
+                        forum_id,
+                        parent_id: None,
+                        pages: 0,
+                        threads: 0,
+                        thread_pages: 0,
+                        error: Some(e.to_string()),
+                    });
+                    continue;
+                }
+            };
+            let globals: Vec<_> = gfr
+                .announcement_global
+                .iter()
+                .filter(|t| global_seen.insert(t.thread.thread_id.clone()))
+                .collect();
+            let pinned: Vec<_> = gfr
+                .sticky
+                .iter()
+                .chain(&gfr.notices)
+                .chain(&gfr.announcement_local)
+                .chain(globals)
+                .collect();
+            let threads = pinned.len() as u64 + gfr.threads.len() as u64 * pages;
+            //Pinned threads are on every listing page; the regular ones are a sample of the rest.
+            let pinned_pages: u64 = pinned
+                .iter()
+                .map(|t| thread_pages(t.thread.thread_replies))
+                .sum();
+            let sample_pages: u64 = gfr
+                .threads
+                .iter()
+                .map(|t| thread_pages(t.thread.thread_replies))
+                .sum();
+            subforums.push(SubforumPlan {
+                forum_id,
+                forum_name: gfr.forum.forum_name.clone(),
+                parent_id: gfr.forum.parent_id.clone(),
+                pages,
+                threads,
+                thread_pages: pinned_pages + sample_pages * pages,
+                error: None,
+            });
+        }
+        Ok(PresetPlan {
+            preset_id: preset_id.to_string(),
+            title: caf.settings.title_welcome.clone(),
+            //Shown as unknown if Enjin sent something we can't read.
+            total_threads: parse_number(&caf.total_threads).ok().flatten(),
+            total_posts: parse_number(&caf.total_posts).ok().flatten(),
+            subforums,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigSource, IdList, SiteSettings};
+    use crate::error::EnjinError;
+    use crate::testing::FakeApi;
+    use crate::Config;
+    use secrecy::SecretString;
+    use serde_json::Value;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn a_dry_run_doesnt_create_the_database() {
+        let database_file = format!("encuum-test-dry-run-{}.db", std::process::id());
+        let config = Config::load(ConfigSource {
+            overrides: SiteSettings {
+                database_file: Some(database_file.clone()),
+                endpoint: Some("http://127.0.0.1:9".to_string()),
+                session_id: Some(SecretString::new("session".to_string())),
+                forum_ids: Some(IdList::Csv("1".to_string())),
+                ..Default::default()
+            },
+            isolated: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut ex = Extractor::without_database(config).unwrap();
+        //Forum 11 is in the example's forum list, but none of its pages are.
+        ex.api = Arc::new(FakeApi::new(|method, params| {
+            let path = match (method, params.get("forum_id").and_then(Value::as_str)) {
+                ("Forum.getCategoriesAndForums", _) => "categories/1.json".to_string(),
+                ("Forum.getForum", Some("11")) => return Err(EnjinError::NoAccess("11".into())),
+                ("Forum.getForum", Some(id)) => format!("forums/{}/1.json", id),
+                _ => unreachable!("{}", method),
+            };
+            let file = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("fixtures/example")
+                .join(path);
+            Ok(serde_json::from_str(&std::fs::read_to_string(file).unwrap()).unwrap())
+        }));
+        ex.login().await.unwrap();
+        let plan = ex.plan().await.unwrap();
+
+        assert!(!Path::new(&database_file).exists());
+        let subforums: Vec<_> = plan.presets[0]
+            .subforums
+            .iter()
+            .map(|sf| (sf.forum_id.as_str(), sf.pages, sf.error.is_some()))
+            .collect();
+        assert_eq!(
+            subforums,
+            [("10", 2, false), ("11", 0, true), ("12", 1, false)]
+        );
+    }
+}
@@ -0,0 +1,774 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::error::EnjinError;
+use crate::extractor::Extractor;
+use crate::runs::now;
+use entity::{crawl_queue, failed_items};
+use futures::{stream, Future, StreamExt};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QuerySelect, Set,
+};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tracing::{debug, info, warn};
+
+/// How many pending units to take off the queue at a time.
+const BATCH_SIZE: u64 = 100;
+
+/// How many fetched units `crawl_pipelined` lets wait to be saved before it stops fetching more.
+const SAVE_BACKLOG: usize = 16;
+
+/// How long `crawl_alongside` waits for more units to be queued once it has run out.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitKind {
+    Preset,
+    ForumPage,
+    ThreadPage,
+    ApplicationList,
+    Application,
+    Image,
+}
+
+impl UnitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnitKind::Preset => "preset",
+            UnitKind::ForumPage => "forum_page",
+            UnitKind::ThreadPage => "thread_page",
+            UnitKind::ApplicationList => "application_list",
+            UnitKind::Application => "application",
+            UnitKind::Image => "image",
+        }
+    }
+
+    /// The API method a unit of this kind calls, if any.
+    pub fn method(&self) -> Option<&'static str> {
+        match self {
+            UnitKind::Preset => Some("Forum.getCategoriesAndForums"),
+            UnitKind::ForumPage => Some("Forum.getForum"),
+            UnitKind::ThreadPage => Some("Forum.getThread"),
+            UnitKind::ApplicationList => Some("Applications.getList"),
+            UnitKind::Application => Some("Applications.getApplication"),
+            UnitKind::Image => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitState {
+    Pending,
+    InProgress,
+    Done,
+    Failed,
+    /// Left by an interrupted extraction and set aside while `retry-failed` runs.
+    Held,
+}
+
+impl UnitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnitState::Pending => "pending",
+            UnitState::InProgress => "in_progress",
+            UnitState::Done => "done",
+            UnitState::Failed => "failed",
+            UnitState::Held => "held",
+        }
+    }
+}
+
+/// What a crawl handler returns for a unit that failed in a way we expect now and then, like a dead
+/// image link: the unit is marked failed and the crawl carries on.
+#[derive(thiserror::Error, Debug)]
+#[error("{0:#}")]
+pub struct Skipped(pub anyhow::Error);
+
+impl Skipped {
+    /// The `failed_items.error_class` it's recorded with.
+    fn class(&self) -> &'static str {
+        if let Some(e) = self.0.downcast_ref::<EnjinError>() {
+            e.class()
+        } else if self.0.is::<MissingParent>() {
+            "missing_parent"
+        } else {
+            "download"
+        }
+    }
+}
+
+/// A response that couldn't be made sense of, like a page count that isn't a number, is skipped
+/// rather than given up on, since the calls for other units may well be fine.
+impl From<EnjinError> for Skipped {
+    fn from(e: EnjinError) -> Self {
+        Skipped(e.into())
+    }
+}
+
+/// What a row that couldn't be saved belongs to (a subforum's preset, a thread's subforum or a
+/// post's thread) isn't stored, so the unit it came from is `Skipped`.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}: what it belongs to isn't stored")]
+pub struct MissingParent(pub String);
+
+/// One unit of crawl work, as kept in the `crawl_queue` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub kind: UnitKind,
+    pub item_id: String,
+    pub page: Option<u32>,
+    pub parent_id: Option<String>,
+}
+
+impl Unit {
+    fn new(kind: UnitKind, item_id: &str, page: Option<u32>, parent_id: Option<&str>) -> Self {
+        Unit {
+            kind,
+            item_id: item_id.to_string(),
+            page,
+            parent_id: parent_id.map(str::to_string),
+        }
+    }
+
+    pub fn preset(preset_id: &str) -> Self {
+        Unit::new(UnitKind::Preset, preset_id, None, None)
+    }
+
+    pub fn forum_page(forum_id: &str, page: u32, preset_id: &str) -> Self {
+        Unit::new(UnitKind::ForumPage, forum_id, Some(page), Some(preset_id))
+    }
+
+    pub fn thread_page(thread_id: &str, page: u32, forum_id: &str) -> Self {
+        Unit::new(UnitKind::ThreadPage, thread_id, Some(page), Some(forum_id))
+    }
+
+    pub fn application_list(app_type: &str, page: u32) -> Self {
+        Unit::new(UnitKind::ApplicationList, app_type, Some(page), None)
+    }
+
+    pub fn application(application_id: &str, app_type: &str) -> Self {
+        Unit::new(UnitKind::Application, application_id, None, Some(app_type))
+    }
+
+    pub fn image(url: &str, post_id: &str) -> Self {
+        Unit::new(UnitKind::Image, url, None, Some(post_id))
+    }
+
+    pub fn id(&self) -> String {
+        match self.page {
+            Some(page) => format!("{}/{}/{}", self.kind.as_str(), self.item_id, page),
+            None => format!("{}/{}", self.kind.as_str(), self.item_id),
+        }
+    }
+
+    /// The `page` parameter to send: Enjin's first page is asked for without one.
+    pub fn page_param(&self) -> Option<String> {
+        self.page.filter(|p| *p > 1).map(|p| p.to_string())
+    }
+
+    fn from_model(m: crawl_queue::Model) -> Option<Self> {
+        Unit::from_parts(&m.kind, m.item_id, m.page, m.parent_id)
+    }
+
+    fn from_parts(
+        kind: &str,
+        item_id: String,
+        page: Option<i32>,
+        parent_id: Option<String>,
+    ) -> Option<Self> {
+        let kind = match kind {
+            "preset" => UnitKind::Preset,
+            "forum_page" => UnitKind::ForumPage,
+            "thread_page" => UnitKind::ThreadPage,
+            "application_list" => UnitKind::ApplicationList,
+            "application" => UnitKind::Application,
+            "image" => UnitKind::Image,
+            _ => return None,
+        };
+        Some(Unit {
+            kind,
+            item_id,
+            page: page.map(|p| p as u32),
+            parent_id,
+        })
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            UnitKind::Preset => "Preset",
+            UnitKind::ForumPage => "Subforum",
+            UnitKind::ThreadPage => "Thread",
+            UnitKind::ApplicationList => "Applications of type",
+            UnitKind::Application => "Application",
+            UnitKind::Image => "Image",
+        };
+        match self.page {
+            Some(page) => write!(f, "{} {} page {}", what, self.item_id, page),
+            None => write!(f, "{} {}", what, self.item_id),
+        }
+    }
+}
+
+impl Extractor {
+    /// Picks up where an interrupted extraction left off, if there is one; otherwise clears the
+    /// queue so everything is crawled afresh.
+    pub async fn start_crawl(&mut self) -> anyhow::Result<()> {
+        let unfinished = crawl_queue::Entity::find()
+            .filter(crawl_queue::Column::State.is_in([
+                UnitState::Pending.as_str(),
+                UnitState::InProgress.as_str(),
+                UnitState::Held.as_str(),
+            ]))
+            .count(&self.conn)
+            .await?;
+        if unfinished > 0 {
+            //Whatever was in progress when we stopped didn't get saved, and a retry-failed that
+            //stopped may not have put back what it held.
+            self.move_units(UnitState::InProgress, UnitState::Pending)
+                .await?;
+            self.move_units(UnitState::Held, UnitState::Pending).await?;
+            info!(
+                "*** Resuming the previous extraction: {} units of work left.",
+                unfinished
+            );
+        } else {
+            crawl_queue::Entity::delete_many().exec(&self.conn).await?;
+            info!("*** Starting a new extraction.");
+        }
+        self.set_crawl_start(unfinished > 0).await
+    }
+
+    /// Sets aside whatever an interrupted extraction left to do, so that `retry-failed` crawls only
+    /// the failed items and what they lead to, and the next `extract` still carries on with the rest.
+    /// Returns how many units were set aside; `release_held` puts them back.
+    pub async fn hold_unfinished(&self) -> anyhow::Result<u64> {
+        //A retry-failed that stopped may not have put back what it held.
+        self.move_units(UnitState::InProgress, UnitState::Pending)
+            .await?;
+        self.move_units(UnitState::Held, UnitState::Pending).await?;
+        let held = self.move_units(UnitState::Pending, UnitState::Held).await?;
+        if held > 0 {
+            info!(
+                "*** Setting aside the {} units of work an interrupted extraction left; run `extract` to carry on with them.",
+                held
+            );
+        }
+        Ok(held)
+    }
+
+    /// Puts the units `hold_unfinished` set aside back to pending.
+    pub async fn release_held(&self) -> anyhow::Result<()> {
+        self.move_units(UnitState::Held, UnitState::Pending).await?;
+        Ok(())
+    }
+
+    /// Changes every unit in state `from` to `to`, returning how many there were.
+    async fn move_units(&self, from: UnitState, to: UnitState) -> anyhow::Result<u64> {
+        let result = crawl_queue::Entity::update_many()
+            .col_expr(crawl_queue::Column::State, Expr::value(to.as_str()))
+            .filter(crawl_queue::Column::State.eq(from.as_str()))
+            .exec(&self.conn)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    /// Adds units to the queue as pending. Units that are already there are left alone.
+    pub async fn enqueue(&self, units: impl IntoIterator<Item = Unit>) -> anyhow::Result<()> {
+        let models: Vec<crawl_queue::ActiveModel> = units
+            .into_iter()
+            .map(|u| crawl_queue::ActiveModel {
+                unit_id: Set(u.id()),
+                kind: Set(u.kind.as_str().to_string()),
+                item_id: Set(u.item_id),
+                page: Set(u.page.map(|p| p as i32)),
+                parent_id: Set(u.parent_id),
+                state: Set(UnitState::Pending.as_str().to_string()),
+                attempts: Set(0),
+                last_error: Set(None),
+            })
+            .collect();
+        //SQLite caps how many values one statement can bind.
+        for chunk in models.chunks(100) {
+            let inserted = crawl_queue::Entity::insert_many(chunk.to_vec())
+                .on_conflict(
+                    OnConflict::column(crawl_queue::Column::UnitId)
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .exec(&self.conn)
+                .await;
+            match inserted {
+                Ok(_) | Err(migration::DbErr::RecordNotInserted) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes up to `limit` pending units of `kind` off the queue, marking them in progress.
+    async fn next_units(&self, kind: UnitKind, limit: u64) -> anyhow::Result<Vec<Unit>> {
+        let models = crawl_queue::Entity::find()
+            .filter(crawl_queue::Column::Kind.eq(kind.as_str()))
+            .filter(crawl_queue::Column::State.eq(UnitState::Pending.as_str()))
+            .limit(limit)
+            .all(&self.conn)
+            .await?;
+        if models.is_empty() {
+            return Ok(vec![]);
+        }
+        crawl_queue::Entity::update_many()
+            .col_expr(
+                crawl_queue::Column::State,
+                Expr::value(UnitState::InProgress.as_str()),
+            )
+            .col_expr(
+                crawl_queue::Column::Attempts,
+                Expr::col(crawl_queue::Column::Attempts).add(1),
+            )
+            .filter(crawl_queue::Column::UnitId.is_in(models.iter().map(|m| m.unit_id.clone())))
+            .exec(&self.conn)
+            .await?;
+        Ok(models.into_iter().filter_map(Unit::from_model).collect())
+    }
+
+    async fn finish(&self, unit: &Unit, error: Option<String>) -> anyhow::Result<()> {
+        let state = match error {
+            None => UnitState::Done,
+            Some(_) => UnitState::Failed,
+        };
+        crawl_queue::Entity::update_many()
+            .col_expr(crawl_queue::Column::State, Expr::value(state.as_str()))
+            .col_expr(crawl_queue::Column::LastError, Expr::value(error))
+            .filter(crawl_queue::Column::UnitId.eq(unit.id()))
+            .exec(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Puts `unit` back to pending, because we stopped before handling it.
+    async fn release(&self, unit: &Unit) -> anyhow::Result<()> {
+        crawl_queue::Entity::update_many()
+            .col_expr(
+                crawl_queue::Column::State,
+                Expr::value(UnitState::Pending.as_str()),
+            )
+            .filter(crawl_queue::Column::UnitId.eq(unit.id()))
+            .exec(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Puts `unit` in the `failed_items` ledger, or bumps its failure count if it's already there.
+    async fn record_failure(&self, unit: &Unit, class: &str, error: &str) -> anyhow::Result<()> {
+        let failed_at = now();
+        failed_items::Entity::insert(failed_items::ActiveModel {
+            unit_id: Set(unit.id()),
+            kind: Set(unit.kind.as_str().to_string()),
+            item_id: Set(unit.item_id.clone()),
+            page: Set(unit.page.map(|p| p as i32)),
+            parent_id: Set(unit.parent_id.clone()),
+            error_class: Set(class.to_string()),
+            last_error: Set(error.to_string()),
+            failed_at: Set(failed_at),
+            failures: Set(1),
+        })
+        .on_conflict(
+            OnConflict::column(failed_items::Column::UnitId)
+                .values([
+                    (failed_items::Column::ErrorClass, class.into()),
+                    (failed_items::Column::LastError, error.into()),
+                    (failed_items::Column::FailedAt, failed_at.into()),
+                    (
+                        failed_items::Column::Failures,
+                        Expr::col((failed_items::Entity, failed_items::Column::Failures)).add(1),
+                    ),
+                ])
+                .to_owned(),
+        )
+        .exec(&self.conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn settle(&self, unit: &Unit, result: anyhow::Result<()>) -> anyhow::Result<()> {
+        match result {
+            Ok(()) => {
+                //It may have failed in an earlier run.
+                failed_items::Entity::delete_by_id(unit.id())
+                    .exec(&self.conn)
+                    .await?;
+                self.finish(unit, None).await
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(EnjinError::Interrupted)) => {
+                self.release(unit).await
+            }
+            Err(e) => {
+                let class = if let Some(ee) = e.downcast_ref::<EnjinError>() {
+                    self.give_up(&unit.to_string(), ee)?;
+                    ee.class()
+                } else if let Some(skipped) = e.downcast_ref::<Skipped>() {
+                    warn!("{}: {}", unit, e);
+                    skipped.class()
+                } else {
+                    return Err(e);
+                };
+                self.record_failure(unit, class, &e.to_string()).await?;
+                self.stats.error(class);
+                self.finish(unit, Some(e.to_string())).await
+            }
+        }
+    }
+
+    /// Puts every unit in the `failed_items` ledger back on the queue as pending, returning how many.
+    pub async fn requeue_failed(&self) -> anyhow::Result<usize> {
+        let failed = failed_items::Entity::find().all(&self.conn).await?;
+        let units: Vec<Unit> = failed
+            .into_iter()
+            .filter_map(|f| Unit::from_parts(&f.kind, f.item_id, f.page, f.parent_id))
+            .collect();
+        let count = units.len();
+        //Some of them may still be in the queue from the run they failed in.
+        for chunk in units.chunks(100) {
+            crawl_queue::Entity::update_many()
+                .col_expr(
+                    crawl_queue::Column::State,
+                    Expr::value(UnitState::Pending.as_str()),
+                )
+                .filter(crawl_queue::Column::UnitId.is_in(chunk.iter().map(Unit::id)))
+                .exec(&self.conn)
+                .await?;
+        }
+        self.enqueue(units).await?;
+        Ok(count)
+    }
+
+    /// Makes sure nothing is left marked in progress (or held) after a stop, and counts the units in
+    /// each state, for the summary printed on the way out.
+    pub async fn checkpoint(&self) -> anyhow::Result<Vec<(&'static str, u64)>> {
+        self.move_units(UnitState::InProgress, UnitState::Pending)
+            .await?;
+        self.release_held().await?;
+        let mut counts = vec![];
+        for state in [UnitState::Done, UnitState::Failed, UnitState::Pending] {
+            let count = crawl_queue::Entity::find()
+                .filter(crawl_queue::Column::State.eq(state.as_str()))
+                .count(&self.conn)
+                .await?;
+            counts.push((state.as_str(), count));
+        }
+        Ok(counts)
+    }
+
+    /// How many units are in the `failed_items` ledger.
+    pub async fn failed_count(&self) -> anyhow::Result<u64> {
+        Ok(failed_items::Entity::find().count(&self.conn).await?)
+    }
+
+    fn in_flight(&self, kind: UnitKind) -> usize {
+        match kind.method() {
+            Some(method) => self.config.concurrency.for_method(method),
+            None => self.config.concurrency.global,
+        }
+    }
+
+    /// Works through every pending unit of `kind`, including ones `handler` queues up along the way,
+    /// a few at a time. Each unit is marked done (or failed) as soon as it's handled, so an interrupted
+    /// run never has to fetch it again.
+    ///
+    /// An `EnjinError` from `handler` goes to `give_up`; if that lets us carry on, the unit is marked
+    /// failed and recorded in `failed_items`, as is one that was `Skipped`. Any other error stops the
+    /// crawl.
+    ///
+    /// Once `shutdown` is raised, no more units are started; the ones already started are finished
+    /// (or, if their API call was cut short, put back to pending) and then this returns
+    /// `EnjinError::Interrupted`.
+    pub async fn crawl<F, Fut>(&self, kind: UnitKind, handler: F) -> anyhow::Result<()>
+    where
+        F: Fn(Unit) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let in_flight = self.in_flight(kind);
+        loop {
+            if self.shutdown.is_requested() {
+                return Err(EnjinError::Interrupted.into());
+            }
+            let units = self.next_units(kind, BATCH_SIZE).await?;
+            if units.is_empty() {
+                return Ok(());
+            }
+            debug!("Crawling {} {} units", units.len(), kind.as_str());
+            //Units are settled inside the buffered futures rather than in this loop, so none of
+            //them is left unpolled halfway through a database statement while we write to it.
+            let mut results = stream::iter(units)
+                .map(|unit| {
+                    let handled = handler(unit.clone());
+                    async move {
+                        if self.shutdown.is_requested() {
+                            return self.release(&unit).await;
+                        }
+                        self.settle(&unit, handled.await).await
+                    }
+                })
+                .buffer_unordered(in_flight);
+            while let Some(result) = results.next().await {
+                result?;
+            }
+        }
+    }
+
+    /// Like `crawl`, but each unit is fetched and saved in two steps that run side by side: a few
+    /// `fetch`es at a time hand what they got to one task that `save`s it and settles the unit.
+    ///
+    /// The hand-off holds at most `SAVE_BACKLOG` units, so when saving falls behind, fetching waits
+    /// for it instead of piling up pages in memory. Every unit `fetch` queues up of this same kind
+    /// must be queued by `fetch`, not `save`, or it may be missed.
+    ///
+    /// A shutdown stops it the same way as `crawl`; whatever was fetched is still saved.
+    pub async fn crawl_pipelined<F, FFut, S, SFut, T>(
+        &self,
+        kind: UnitKind,
+        fetch: F,
+        save: S,
+    ) -> anyhow::Result<()>
+    where
+        F: Fn(Unit) -> FFut,
+        FFut: Future<Output = anyhow::Result<T>>,
+        S: Fn(Unit, T) -> SFut,
+        SFut: Future<Output = anyhow::Result<()>>,
+    {
+        let in_flight = self.in_flight(kind);
+        let (tx, mut rx) = mpsc::channel(SAVE_BACKLOG);
+        let produce = async move {
+            loop {
+                let units = if self.shutdown.is_requested() {
+                    vec![]
+                } else {
+                    self.next_units(kind, BATCH_SIZE).await?
+                };
+                if units.is_empty() {
+                    //Dropping `tx` lets the saver finish what it has been given.
+                    return anyhow::Ok(());
+                }
+                debug!("Fetching {} {} units", units.len(), kind.as_str());
+                //Each fetch waits for room in the channel itself once it's done with the database,
+                //so a full channel never leaves a statement half-run while the saver writes.
+                stream::iter(units)
+                    .for_each_concurrent(in_flight, |unit| {
+                        let fetched = fetch(unit.clone());
+                        let tx = &tx;
+                        async move {
+                            let fetched = if self.shutdown.is_requested() {
+                                Err(EnjinError::Interrupted.into())
+                            } else {
+                                fetched.await
+                            };
+                            //If the saver has stopped, it has an error to report and we're done.
+                            let _ = tx.send((unit, fetched)).await;
+                        }
+                    })
+                    .await;
+                if tx.is_closed() {
+                    return Ok(());
+                }
+            }
+        };
+        let consume = async {
+            while let Some((unit, fetched)) = rx.recv().await {
+                let result = match fetched {
+                    Ok(fetched) => save(unit.clone(), fetched).await,
+                    Err(e) => Err(e),
+                };
+                self.settle(&unit, result).await?;
+            }
+            anyhow::Ok(())
+        };
+        futures::try_join!(produce, consume)?;
+        if self.shutdown.is_requested() {
+            return Err(EnjinError::Interrupted.into());
+        }
+        Ok(())
+    }
+
+    /// Crawls `kind` with `handler` at the same time as `feeder`, a crawl that queues up the units of
+    /// `kind`, instead of after it. Whatever has been queued is worked through as it comes, checking
+    /// for more every `FOLLOW_INTERVAL`, and once `feeder` is done, so is the rest.
+    ///
+    /// A shutdown stops both the same way as `crawl`.
+    pub async fn crawl_alongside<F, Fut, Feed>(
+        &self,
+        kind: UnitKind,
+        handler: F,
+        feeder: Feed,
+    ) -> anyhow::Result<()>
+    where
+        F: Fn(Unit) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+        Feed: Future<Output = anyhow::Result<()>>,
+    {
+        let fed = AtomicBool::new(false);
+        let more = Notify::new();
+        let feed = async {
+            let result = feeder.await;
+            fed.store(true, Ordering::SeqCst);
+            more.notify_one();
+            result
+        };
+        let follow = async {
+            loop {
+                //Checked first, so the last round picks up everything the feeder queued.
+                let feeding = !fed.load(Ordering::SeqCst);
+                match self.crawl(kind, &handler).await {
+                    //The feeder is still saving what it fetched; it stops on its own.
+                    Err(e) if matches!(e.downcast_ref(), Some(EnjinError::Interrupted)) => {
+                        return anyhow::Ok(());
+                    }
+                    result => result?,
+                }
+                if !feeding {
+                    return Ok(());
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(FOLLOW_INTERVAL) => {}
+                    _ = more.notified() => {}
+                }
+            }
+        };
+        futures::try_join!(feed, follow)?;
+        if self.shutdown.is_requested() {
+            return Err(EnjinError::Interrupted.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestExtractor;
+    use std::sync::Mutex;
+
+    fn images(names: &str) -> Vec<Unit> {
+        names
+            .chars()
+            .map(|c| Unit::image(&format!("http://example.com/{}.png", c), "1"))
+            .collect()
+    }
+
+    /// `b` for the unit of `b.png`.
+    fn name(url: &str) -> String {
+        url.trim_start_matches("http://example.com/")
+            .trim_end_matches(".png")
+            .to_string()
+    }
+
+    /// Every unit in the queue with its state, as `<image name> <state>`.
+    async fn states(ex: &TestExtractor) -> Vec<String> {
+        let mut units: Vec<String> = crawl_queue::Entity::find()
+            .all(&ex.conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| format!("{} {}", name(&u.item_id), u.state))
+            .collect();
+        units.sort();
+        units
+    }
+
+    /// Crawls the pending images, returning which ones were handled.
+    async fn crawl_images(ex: &TestExtractor) -> Vec<String> {
+        let handled = Mutex::new(vec![]);
+        ex.crawl(UnitKind::Image, |unit| {
+            handled.lock().unwrap().push(name(&unit.item_id));
+            async { anyhow::Ok(()) }
+        })
+        .await
+        .unwrap();
+        let mut handled = handled.into_inner().unwrap();
+        handled.sort();
+        handled
+    }
+
+    #[tokio::test]
+    async fn an_extraction_killed_midway_resumes_with_what_it_had_in_progress() {
+        let mut ex = TestExtractor::new("resume").await;
+        ex.start_crawl().await.unwrap();
+        ex.enqueue(images("abcd")).await.unwrap();
+        //It got through `a` and was killed while fetching `b` and `c`.
+        let started = ex.next_units(UnitKind::Image, 3).await.unwrap();
+        ex.settle(&started[0], Ok(())).await.unwrap();
+        assert_eq!(
+            states(&ex).await,
+            ["a done", "b in_progress", "c in_progress", "d pending"]
+        );
+
+        ex.start_crawl().await.unwrap();
+        assert_eq!(crawl_images(&ex).await, ["b", "c", "d"]);
+        assert_eq!(states(&ex).await, ["a done", "b done", "c done", "d done"]);
+        //With nothing left over, the next extraction starts afresh.
+        ex.start_crawl().await.unwrap();
+        assert_eq!(states(&ex).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn retrying_failed_items_leaves_what_an_interrupted_extraction_left_for_later() {
+        let mut ex = TestExtractor::new("hold").await;
+        ex.start_crawl().await.unwrap();
+        ex.enqueue(images("abc")).await.unwrap();
+        //`a` failed, and then the extraction was stopped before `b` and `c`.
+        let started = ex.next_units(UnitKind::Image, 1).await.unwrap();
+        let failed = Err(Skipped(anyhow::anyhow!("HTTP 404")).into());
+        ex.settle(&started[0], failed).await.unwrap();
+
+        assert_eq!(ex.hold_unfinished().await.unwrap(), 2);
+        assert_eq!(ex.requeue_failed().await.unwrap(), 1);
+        assert_eq!(crawl_images(&ex).await, ["a"]);
+        assert_eq!(states(&ex).await, ["a done", "b held", "c held"]);
+        ex.release_held().await.unwrap();
+        assert_eq!(states(&ex).await, ["a done", "b pending", "c pending"]);
+        assert_eq!(ex.failed_count().await.unwrap(), 0);
+
+        //A retry-failed that was stopped itself, before putting them back, doesn't lose them either.
+        ex.hold_unfinished().await.unwrap();
+        ex.start_crawl().await.unwrap();
+        assert_eq!(crawl_images(&ex).await, ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn units_are_crawled_alongside_the_crawl_queueing_them() {
+        let ex = TestExtractor::new("alongside").await;
+        let handled = Notify::new();
+        let feeder = async {
+            ex.enqueue([Unit::image("http://example.com/a.png", "1")])
+                .await?;
+            //Doesn't finish until that one has been handled.
+            handled.notified().await;
+            ex.enqueue([Unit::image("http://example.com/b.png", "2")])
+                .await
+        };
+        let crawl = ex.crawl_alongside(
+            UnitKind::Image,
+            |_| {
+                handled.notify_one();
+                async { anyhow::Ok(()) }
+            },
+            feeder,
+        );
+        tokio::time::timeout(Duration::from_secs(10), crawl)
+            .await
+            .expect("the units weren't crawled until the feeder was done")
+            .unwrap();
+
+        let done = crawl_queue::Entity::find()
+            .filter(crawl_queue::Column::State.eq(UnitState::Done.as_str()))
+            .count(&ex.conn)
+            .await
+            .unwrap();
+        assert_eq!(done, 2);
+    }
+}
@@ -0,0 +1,162 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use futures::future::BoxFuture;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::trace;
+
+struct Bucket {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// A token bucket shared by every request encuum sends, API calls and image downloads alike.
+///
+/// Tokens come back at `per_second` and up to `burst` can pile up while we're idle. Each request
+/// takes one token, waiting for it if there is none; waiters are served in the order they asked,
+/// however many futures are queued up behind them. Clones share the same bucket.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl RateLimiter {
+    /// `per_second` of zero means no limit at all.
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        if per_second <= 0.0 {
+            return RateLimiter::default();
+        }
+        let burst = burst.max(1) as f64;
+        RateLimiter {
+            bucket: Some(Arc::new(Mutex::new(Bucket {
+                per_second,
+                burst,
+                tokens: burst,
+                refilled: Instant::now(),
+            }))),
+        }
+    }
+
+    /// Waits until we're allowed to send one more request.
+    pub async fn acquire(&self) {
+        let wait = match &self.bucket {
+            None => return,
+            Some(bucket) => {
+                let mut b = bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(b.refilled).as_secs_f64() * b.per_second;
+                b.tokens = (b.tokens + refill).min(b.burst);
+                b.refilled = now;
+                //Going below zero reserves a future token, which keeps the waiters in order.
+                b.tokens -= 1.0;
+                if b.tokens >= 0.0 {
+                    return;
+                }
+                Duration::from_secs_f64(-b.tokens / b.per_second)
+            }
+        };
+        trace!("Rate limited; waiting {:?}", wait);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Tower middleware that takes a token from a `RateLimiter` before every request.
+///
+/// Unlike `tower::limit::RateLimitLayer`, the limit is shared by everything built from the same
+/// `RateLimiter`, so the API client and the image client draw from one budget.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: RateLimiter,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: RateLimiter) -> Self {
+        RateLimitLayer { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: RateLimiter,
+}
+
+impl<S, Req> Service<Req> for RateLimitService<S>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Future: Send,
+    Req: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        //The clone isn't necessarily ready; keep the one that is and leave the clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            //Nothing is sent until the inner call is made, so wait for the token first.
+            limiter.acquire().await;
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn time_to_acquire(limiter: &RateLimiter, requests: u32) -> Duration {
+        let start = Instant::now();
+        for _ in 0..requests {
+            limiter.acquire().await;
+        }
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_burst_goes_out_at_once_and_the_rest_wait_their_turn() {
+        let limiter = RateLimiter::new(20.0, 3);
+        assert_eq!(time_to_acquire(&limiter, 3).await, Duration::ZERO);
+        //Two more at 20 per second is another 100ms.
+        let waited = time_to_acquire(&limiter, 2).await;
+        assert_eq!(waited, Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clones_share_one_bucket() {
+        let limiter = RateLimiter::new(20.0, 1);
+        limiter.acquire().await;
+        let waited = time_to_acquire(&limiter.clone(), 1).await;
+        assert_eq!(waited, Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_per_second_means_no_limit() {
+        let limiter = RateLimiter::new(0.0, 1);
+        assert_eq!(time_to_acquire(&limiter, 1000).await, Duration::ZERO);
+    }
+}
@@ -0,0 +1,292 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::api::{describe, EnjinApi, Params};
+use crate::error::EnjinError;
+use futures::future::BoxFuture;
+use hyper::{header::RETRY_AFTER, Request, Response, StatusCode};
+use jsonrpsee::core::async_trait;
+use rand::Rng;
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// How often and how patiently to retry a failed call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total tries, including the first one.
+    pub max_attempts: u32,
+    /// Wait before the second try; it doubles for every try after that.
+    pub base_delay: Duration,
+    /// The wait never grows past this (unless Enjin asks for longer with `Retry-After`).
+    pub max_delay: Duration,
+    /// Up to this fraction of each wait is randomly taken off, so parallel retries don't stampede.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(600),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after try number `attempt` failed with `e`.
+    pub fn delay(&self, attempt: u32, e: &EnjinError) -> Duration {
+        if let EnjinError::RateLimited {
+            retry_after: Some(secs),
+        } = e
+        {
+            return Duration::from_secs(*secs);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        backoff.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=self.jitter))
+    }
+}
+
+/// The retry policy for every method, with optional overrides for some of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetryConfig {
+    pub default: RetryPolicy,
+    /// Keyed by method name, like `Forum.getThread`.
+    pub methods: HashMap<String, RetryPolicy>,
+}
+
+impl RetryConfig {
+    pub fn for_method(&self, method: &str) -> &RetryPolicy {
+        self.methods.get(method).unwrap_or(&self.default)
+    }
+}
+
+/// Retries failed calls to `inner` according to a `RetryConfig`. Errors that retrying can't fix
+/// (see `EnjinError::is_retryable`) are returned straight away.
+pub struct Retrying {
+    inner: Arc<dyn EnjinApi>,
+    config: RetryConfig,
+    sleep: bool,
+}
+
+impl Retrying {
+    pub fn new(inner: Arc<dyn EnjinApi>, config: RetryConfig) -> Self {
+        Retrying {
+            inner,
+            config,
+            sleep: true,
+        }
+    }
+
+    /// Retry without waiting in between, for when there's nobody on the other end to be patient with (replays).
+    pub fn without_delays(mut self) -> Self {
+        self.sleep = false;
+        self
+    }
+}
+
+#[async_trait]
+impl EnjinApi for Retrying {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        let policy = self.config.for_method(method);
+        let mut attempt = 1;
+        loop {
+            match self.inner.call(method, params.clone()).await {
+                Ok(v) => return Ok(v),
+                Err(e) if !e.is_retryable() || attempt >= policy.max_attempts => return Err(e),
+                Err(e) => {
+                    let delay = if self.sleep {
+                        policy.delay(attempt, &e)
+                    } else {
+                        Duration::ZERO
+                    };
+                    warn!(
+                        "{} {}: try #{} of {} failed: {}. Trying again in {:.1} seconds.",
+                        method,
+                        describe(&params),
+                        attempt,
+                        policy.max_attempts,
+                        e,
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+tokio::task_local! {
+    static LAST_RESPONSE: Cell<Option<ResponseStatus>>;
+}
+
+/// What `RetryAfterLayer` saw of the HTTP response to a call.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResponseStatus {
+    pub status: StatusCode,
+    /// The `Retry-After` seconds of a 429, if it gave them.
+    pub retry_after: Option<u64>,
+}
+
+/// Runs one HTTP call and returns the status of its response, if it got one.
+///
+/// The jsonrpsee client turns an HTTP error into an error that has lost the status and headers,
+/// so `RetryAfterLayer` stashes them in a task-local that this sets up around the call.
+pub async fn capture_response<F: Future>(call: F) -> (F::Output, Option<ResponseStatus>) {
+    LAST_RESPONSE
+        .scope(Cell::new(None), async move {
+            let output = call.await;
+            (output, LAST_RESPONSE.with(|c| c.get()))
+        })
+        .await
+}
+
+/// HTTP client middleware that remembers the status of responses, and the `Retry-After` (in
+/// seconds) of 429s.
+#[derive(Clone, Default)]
+pub struct RetryAfterLayer;
+
+impl<S> Layer<S> for RetryAfterLayer {
+    type Service = RetryAfterService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryAfterService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RetryAfterService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RetryAfterService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await?;
+            let status = response.status();
+            let retry_after = match status {
+                StatusCode::TOO_MANY_REQUESTS => response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse::<u64>().ok()),
+                _ => None,
+            };
+            //Not being inside `capture_response` just means nobody asked.
+            let _ = LAST_RESPONSE.try_with(|c| {
+                c.set(Some(ResponseStatus {
+                    status,
+                    retry_after,
+                }))
+            });
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_up_to_the_maximum() {
+        let e = EnjinError::Timeout;
+        let delays: Vec<u64> = (1..=4)
+            .map(|attempt| policy(0.0).delay(attempt, &e).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn jitter_only_ever_shortens_the_delay() {
+        let e = EnjinError::Timeout;
+        for _ in 0..100 {
+            let delay = policy(0.5).delay(2, &e);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn retry_after_overrides_the_backoff() {
+        let e = EnjinError::RateLimited {
+            retry_after: Some(42),
+        };
+        assert_eq!(policy(0.5).delay(1, &e), Duration::from_secs(42));
+        let e = EnjinError::RateLimited { retry_after: None };
+        assert_eq!(policy(0.0).delay(1, &e), Duration::from_secs(1));
+    }
+
+    async fn response_to(status: StatusCode, header: Option<&'static str>) -> ResponseStatus {
+        let service = RetryAfterLayer.layer(tower::service_fn(move |_: Request<()>| async move {
+            let mut response = Response::builder().status(status);
+            if let Some(value) = header {
+                response = response.header(RETRY_AFTER, value);
+            }
+            Ok::<_, Infallible>(response.body(()).unwrap())
+        }));
+        let (response, seen) = capture_response(service.oneshot(Request::new(()))).await;
+        response.unwrap();
+        seen.unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_status_and_the_retry_after_of_429s_are_read() {
+        let too_many = StatusCode::TOO_MANY_REQUESTS;
+        let retry_after = |secs| ResponseStatus {
+            status: too_many,
+            retry_after: secs,
+        };
+        assert_eq!(
+            response_to(too_many, Some("12")).await,
+            retry_after(Some(12))
+        );
+        assert_eq!(
+            response_to(too_many, Some(" 5 ")).await,
+            retry_after(Some(5))
+        );
+        //An HTTP date isn't understood, so the usual backoff applies.
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(response_to(too_many, Some(date)).await, retry_after(None));
+        assert_eq!(response_to(too_many, None).await, retry_after(None));
+        let ok = response_to(StatusCode::OK, Some("12")).await;
+        assert_eq!((ok.status, ok.retry_after), (StatusCode::OK, None));
+    }
+}
@@ -0,0 +1,168 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::extractor::Extractor;
+use crate::runs::now;
+use entity::{forum_posts, post_revisions};
+use sea_orm::prelude::DateTimeUtc;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect, Set};
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
+/// Whether two copies of a post are the same version of it.
+fn same_version(a: &forum_posts::ForumPost, b: &forum_posts::ForumPost) -> bool {
+    a.post_content == b.post_content
+        && a.last_edit_time == b.last_edit_time
+        && a.last_edit_user == b.last_edit_user
+}
+
+fn revision(
+    post: &forum_posts::ForumPost,
+    run_id: Option<i32>,
+    observed_at: Option<DateTimeUtc>,
+) -> post_revisions::ActiveModel {
+    post_revisions::ActiveModel {
+        post_id: Set(post.post_id.clone()),
+        post_content: Set(post.post_content.clone()),
+        last_edit_time: Set(post.last_edit_time),
+        last_edit_user: Set(post.last_edit_user.clone()),
+        run_id: Set(run_id),
+        observed_at: Set(observed_at),
+        ..Default::default()
+    }
+}
+
+impl Extractor {
+    /// The rows to add to `post_revisions` for `posts`: one for each post that we haven't got a
+    /// revision of yet, or that differs from the stored copy. Must be called before `posts` are
+    /// saved over that copy, and the rows saved with `save_revisions` after.
+    ///
+    /// A post that was stored before revisions were kept and has been edited since gets its stored
+    /// version recorded as well, without a run, so the old text isn't lost.
+    pub async fn new_revisions(
+        &self,
+        posts: &[forum_posts::ForumPost],
+    ) -> anyhow::Result<Vec<post_revisions::ActiveModel>> {
+        let ids: Vec<String> = posts.iter().map(|p| p.post_id.clone()).collect();
+        let stored: HashMap<String, forum_posts::ForumPost> = forum_posts::Entity::find()
+            .filter(forum_posts::Column::PostId.is_in(ids.clone()))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|p| (p.post_id.clone(), p))
+            .collect();
+        let revised: HashSet<String> = post_revisions::Entity::find()
+            .select_only()
+            .column(post_revisions::Column::PostId)
+            .distinct()
+            .filter(post_revisions::Column::PostId.is_in(ids))
+            .into_tuple()
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .collect();
+
+        let observed_at = Some(now());
+        let mut revisions = vec![];
+        for post in posts {
+            let has_revisions = revised.contains(&post.post_id);
+            match stored.get(&post.post_id) {
+                Some(old) if same_version(old, post) && has_revisions => continue,
+                Some(old) if !same_version(old, post) => {
+                    debug!("Post {} was edited since it was saved", post.post_id);
+                    if !has_revisions {
+                        revisions.push(revision(old, None, None));
+                    }
+                }
+                _ => {}
+            }
+            revisions.push(revision(post, self.run_id, observed_at));
+        }
+        Ok(revisions)
+    }
+
+    pub async fn save_revisions(
+        &self,
+        revisions: Vec<post_revisions::ActiveModel>,
+    ) -> anyhow::Result<()> {
+        //SQLite caps how many values one statement can bind.
+        for chunk in revisions.chunks(100) {
+            post_revisions::Entity::insert_many(chunk.to_vec())
+                .exec(&self.conn)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestExtractor;
+    use sea_orm::ActiveValue;
+
+    fn content(revision: &post_revisions::ActiveModel) -> (String, String, bool) {
+        let ActiveValue::Set(post_id) = &revision.post_id else {
+            panic!("no post_id");
+        };
+        let ActiveValue::Set(content) = &revision.post_content else {
+            panic!("no post_content");
+        };
+        let observed = !matches!(revision.observed_at, ActiveValue::Set(None));
+        (post_id.clone(), content.clone(), observed)
+    }
+
+    #[tokio::test]
+    async fn only_new_and_edited_posts_get_a_revision() {
+        let ex = TestExtractor::new("revisions").await;
+        ex.crawl_example(|_, _| {}).await;
+        let revisions = post_revisions::Entity::find().all(&ex.conn).await.unwrap();
+        assert_eq!(revisions.len(), 5);
+
+        let mut posts = forum_posts::Entity::find().all(&ex.conn).await.unwrap();
+        posts.sort_by(|a, b| a.post_id.cmp(&b.post_id));
+        assert!(ex.new_revisions(&posts).await.unwrap().is_empty());
+
+        posts[0].post_content = "Welcome to the guild, everyone!".to_string();
+        let revisions = ex.new_revisions(&posts).await.unwrap();
+        let expected = (
+            "1000".into(),
+            "Welcome to the guild, everyone!".into(),
+            true,
+        );
+        assert_eq!(
+            revisions.iter().map(content).collect::<Vec<_>>(),
+            [expected]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_post_stored_before_revisions_keeps_its_old_text() {
+        let ex = TestExtractor::new("revisions-before").await;
+        ex.crawl_example(|_, _| {}).await;
+        post_revisions::Entity::delete_many()
+            .exec(&ex.conn)
+            .await
+            .unwrap();
+        let mut posts = forum_posts::Entity::find()
+            .filter(forum_posts::Column::PostId.is_in(["1001", "1002"]))
+            .all(&ex.conn)
+            .await
+            .unwrap();
+        posts.sort_by(|a, b| a.post_id.cmp(&b.post_id));
+
+        posts[1].post_content = "Same here!".to_string();
+        let revisions = ex.new_revisions(&posts).await.unwrap();
+        let revisions: Vec<_> = revisions.iter().map(content).collect();
+        assert_eq!(
+            revisions,
+            [
+                //Not edited, so only the copy we have.
+                ("1001".into(), "Glad to be here".into(), true),
+                //Edited: the stored version, from who knows when, and then the new one.
+                ("1002".into(), "Same here".into(), false),
+                ("1002".into(), "Same here!".into(), true),
+            ]
+        );
+    }
+}
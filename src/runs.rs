@@ -0,0 +1,204 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::extractor::Extractor;
+use entity::runs;
+use sea_orm::prelude::DateTimeUtc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// A count that crawl tasks can bump side by side.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What this process has fetched and saved so far, for the `runs` table.
+#[derive(Default)]
+pub struct RunStats {
+    pub presets: Counter,
+    /// Subforums whose first page was fetched.
+    pub subforums: Counter,
+    /// Threads whose first page was fetched.
+    pub threads: Counter,
+    pub posts: Counter,
+    pub images: Counter,
+    pub applications: Counter,
+    /// Items that were skipped after failing, by error class.
+    errors: Mutex<BTreeMap<String, u64>>,
+}
+
+impl RunStats {
+    pub fn error(&self, class: &str) {
+        *self
+            .errors
+            .lock()
+            .unwrap()
+            .entry(class.to_string())
+            .or_default() += 1;
+    }
+
+    fn errors(&self) -> BTreeMap<String, u64> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+/// The time, to the second, as encuum records when things happened.
+pub(crate) fn now() -> DateTimeUtc {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    DateTimeUtc::from(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` gives the same answer in every build.
+fn fingerprint(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+impl Extractor {
+    /// Adds a row to the `runs` table for a run of `command` that's starting now, returning its ID.
+    pub async fn start_run(&mut self, command: &str) -> anyhow::Result<i32> {
+        let settings = self.config.settings().to_string();
+        let run = runs::ActiveModel {
+            command: Set(command.to_string()),
+            started_at: Set(now()),
+            finished_at: Set(None),
+            exit_code: Set(None),
+            encuum_version: Set(env!("CARGO_PKG_VERSION").to_string()),
+            config_fingerprint: Set(fingerprint(&settings)),
+            settings: Set(settings),
+            presets: Set(0),
+            subforums: Set(0),
+            threads: Set(0),
+            posts: Set(0),
+            images: Set(0),
+            applications: Set(0),
+            errors: Set(0),
+            errors_by_class: Set("{}".to_string()),
+            ..Default::default()
+        };
+        let run_id = runs::Entity::insert(run)
+            .exec(&self.conn)
+            .await?
+            .last_insert_id;
+        info!("*** Run {} started.", run_id);
+        self.run_id = Some(run_id);
+        Ok(run_id)
+    }
+
+    /// Works out when the extraction that's starting now (or, if `resuming`, being carried on) began,
+    /// and records it with this run.
+    pub async fn set_crawl_start(&mut self, resuming: bool) -> anyhow::Result<()> {
+        self.crawl_started_at = if resuming {
+            //Not known if the extraction began before runs recorded this.
+            let mut earlier =
+                runs::Entity::find().filter(runs::Column::CrawlStartedAt.is_not_null());
+            if let Some(run_id) = self.run_id {
+                earlier = earlier.filter(runs::Column::RunId.ne(run_id));
+            }
+            earlier
+                .order_by_desc(runs::Column::RunId)
+                .one(&self.conn)
+                .await?
+                .and_then(|run| run.crawl_started_at)
+        } else {
+            Some(now())
+        };
+        if let Some(run_id) = self.run_id {
+            runs::ActiveModel {
+                run_id: Set(run_id),
+                crawl_started_at: Set(self.crawl_started_at),
+                ..Default::default()
+            }
+            .update(&self.conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Fills in how run `run_id` ended and what it got.
+    pub async fn finish_run(&self, run_id: i32, exit_code: i32) -> anyhow::Result<()> {
+        let errors = self.stats.errors();
+        let stats = &self.stats;
+        runs::ActiveModel {
+            run_id: Set(run_id),
+            finished_at: Set(Some(now())),
+            exit_code: Set(Some(exit_code)),
+            presets: Set(stats.presets.get() as i64),
+            subforums: Set(stats.subforums.get() as i64),
+            threads: Set(stats.threads.get() as i64),
+            posts: Set(stats.posts.get() as i64),
+            images: Set(stats.images.get() as i64),
+            applications: Set(stats.applications.get() as i64),
+            errors: Set(errors.values().sum::<u64>() as i64),
+            errors_by_class: Set(serde_json::to_string(&errors)?),
+            ..Default::default()
+        }
+        .update(&self.conn)
+        .await?;
+        info!(
+            "*** Run {} fetched {} presets, {} subforums, {} threads, {} posts, {} images and {} applications; {} items failed.",
+            run_id,
+            stats.presets.get(),
+            stats.subforums.get(),
+            stats.threads.get(),
+            stats.posts.get(),
+            stats.images.get(),
+            stats.applications.get(),
+            errors.values().sum::<u64>()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestExtractor;
+    use entity::runs;
+    use sea_orm::EntityTrait;
+
+    #[tokio::test]
+    async fn a_finished_run_records_what_it_fetched_and_what_failed() {
+        let mut ex = TestExtractor::new("runs").await;
+        let run_id = ex.start_run("extract").await.unwrap();
+        ex.crawl_example(|_, _| {}).await;
+        //Counted where the preset and listings are fetched, which `crawl_example` doesn't do.
+        ex.stats.presets.add(1);
+        ex.stats.subforums.add(2);
+        ex.stats.error("no_access");
+        ex.stats.error("timeout");
+        ex.stats.error("no_access");
+        ex.finish_run(run_id, 1).await.unwrap();
+
+        let run = runs::Entity::find_by_id(run_id)
+            .one(&ex.conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(run.command, "extract");
+        assert!(run.finished_at.is_some());
+        assert_eq!(run.exit_code, Some(1));
+        assert_eq!(
+            (run.presets, run.subforums, run.threads, run.posts),
+            (1, 2, 3, 5)
+        );
+        assert_eq!(run.errors, 3);
+        assert_eq!(run.errors_by_class, r#"{"no_access":2,"timeout":1}"#);
+    }
+}
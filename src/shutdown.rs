@@ -0,0 +1,102 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::api::{EnjinApi, Params};
+use crate::error::EnjinError;
+use jsonrpsee::core::async_trait;
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+#[derive(Default)]
+struct Inner {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+/// A flag that is raised once, when we've been asked to stop (say, by Ctrl+C). Clones share it.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.inner.requested.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    /// Waits until `request` has been called.
+    pub async fn requested(&self) {
+        loop {
+            //Registered before the check, so a `request` in between isn't missed.
+            let notified = self.inner.notify.notified();
+            if self.is_requested() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Fails every call to `inner` with `EnjinError::Interrupted` once a shutdown is requested,
+/// including calls that are already waiting for their turn, backing off or in flight.
+///
+/// Nothing is saved until a call returns, so the database is never left half-written by this.
+pub struct Interruptible {
+    inner: Arc<dyn EnjinApi>,
+    shutdown: Shutdown,
+}
+
+impl Interruptible {
+    pub fn new(inner: Arc<dyn EnjinApi>, shutdown: Shutdown) -> Self {
+        Interruptible { inner, shutdown }
+    }
+}
+
+#[async_trait]
+impl EnjinApi for Interruptible {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        if self.shutdown.is_requested() {
+            return Err(EnjinError::Interrupted);
+        }
+        tokio::select! {
+            result = self.inner.call(method, params) => result,
+            _ = self.shutdown.requested() => Err(EnjinError::Interrupted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeApi;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn a_call_in_flight_is_given_up_on_when_a_shutdown_is_requested() {
+        let slow = Arc::new(FakeApi::new(|_, _| Ok(Value::Null)).slow(Duration::from_secs(60)));
+        let shutdown = Shutdown::default();
+        let api = Interruptible::new(slow.clone(), shutdown.clone());
+
+        let started = Instant::now();
+        let call = api.call("Forum.getThread", Params::new());
+        let (result, _) = tokio::join!(call, async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            shutdown.request();
+        });
+        assert!(matches!(result, Err(EnjinError::Interrupted)));
+        assert_eq!(started.elapsed(), Duration::from_millis(50));
+
+        //Calls made afterwards don't get as far as the API.
+        let result = api.call("Forum.getThread", Params::new()).await;
+        assert!(matches!(result, Err(EnjinError::Interrupted)));
+        assert_eq!(slow.calls.lock().unwrap().len(), 1);
+    }
+}
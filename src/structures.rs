@@ -1,18 +1,18 @@
-use dotenvy::var;
 /// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
 /// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
 /// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::config::Config;
+use crate::ratelimit::{RateLimitLayer, RateLimitService, RateLimiter};
+use crate::retry::{RetryAfterLayer, RetryAfterService};
+use anyhow::anyhow;
 use entity::{forum_posts::ForumPost, forum_threads::ForumThread, subforums::Subforum};
 use hyper::HeaderMap;
 use jsonrpsee::{
     core::{__reexports::serde::Deserialize, client::IdKind},
     http_client::{transport::HttpBackend, HttpClient, HttpClientBuilder},
 };
-use once_cell::sync::OnceCell;
-use reqwest::Client;
-use sea_orm::DatabaseConnection;
-use secrecy::SecretString;
 use std::{collections::HashMap, time::Duration};
+use tower::{util::BoxCloneService, ServiceExt};
 use tower_http::{
     classify::{ServerErrorsAsFailures, SharedClassifier},
     trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
@@ -20,65 +20,61 @@ use tower_http::{
 };
 use tracing::Level;
 
-lazy_static::lazy_static! {
-   pub static ref SEE: HttpClient<tower_http::trace::Trace<HttpBackend, SharedClassifier<ServerErrorsAsFailures>, DefaultMakeSpan>> = {
-        let proxy = var("proxy").ok();
-        let website = var("website").expect("Required .env variable missing: website");
-        let mut headers = HeaderMap::new();
-        headers.insert("Accept", "*/*".parse().unwrap());
-        headers.insert("User-Agent", "encuum-api".parse().unwrap());
-        let middleware = tower::ServiceBuilder::new()
+pub type EnjinHttpClient = HttpClient<
+    RateLimitService<
+        RetryAfterService<
+            tower_http::trace::Trace<
+                HttpBackend,
+                SharedClassifier<ServerErrorsAsFailures>,
+                DefaultMakeSpan,
+            >,
+        >,
+    >,
+>;
+
+/// Fetches images. It's a tower service rather than a bare `reqwest::Client` so that it can share
+/// the API client's `RateLimiter`.
+pub type ImageClient = BoxCloneService<reqwest::Request, reqwest::Response, reqwest::Error>;
+
+pub fn build_client(config: &Config, limiter: &RateLimiter) -> anyhow::Result<EnjinHttpClient> {
+    let mut headers = HeaderMap::new();
+    headers.insert("Accept", "*/*".parse().unwrap());
+    headers.insert("User-Agent", "encuum-api".parse().unwrap());
+    let middleware = tower::ServiceBuilder::new()
+        .layer(RateLimitLayer::new(limiter.clone()))
+        .layer(RetryAfterLayer)
         .layer(
             TraceLayer::new_for_http()
-                .on_request(
-                    DefaultOnRequest::new().level(Level::DEBUG),
-                )
-                .on_response(DefaultOnResponse::new().include_headers(true).latency_unit(LatencyUnit::Millis).level(Level::DEBUG)),
+                .on_request(DefaultOnRequest::new().level(Level::DEBUG))
+                .on_response(
+                    DefaultOnResponse::new()
+                        .include_headers(true)
+                        .latency_unit(LatencyUnit::Millis)
+                        .level(Level::DEBUG),
+                ),
         );
-        let mut client_builder = HttpClientBuilder::default().set_middleware(middleware).set_headers(headers)
-            .id_format(IdKind::String)
-            .request_timeout(Duration::from_secs(600))
-            .set_max_logging_length(99999999);
-
-        if let Some(prox) = proxy {
-            client_builder = client_builder.set_proxy(prox).unwrap();
-        }
-
-        client_builder
-        .build(format!("https://{}:443/api/v1/api.php", website))
-        .unwrap()
-    };
-}
+    let mut client_builder = HttpClientBuilder::default()
+        .set_middleware(middleware)
+        .set_headers(headers)
+        .id_format(IdKind::String)
+        .request_timeout(Duration::from_secs(600))
+        .set_max_logging_length(99999999);
 
-pub static STATE: OnceCell<State> = OnceCell::new();
+    if let Some(prox) = &config.proxy {
+        client_builder = client_builder
+            .set_proxy(prox.clone())
+            .map_err(|e| anyhow!("Invalid proxy {}: {:?}", prox, e))?;
+    }
 
-#[macro_export]
-macro_rules! state {
-    () => {
-        STATE.get().expect("State not initialized")
-    };
+    Ok(client_builder.build(&config.endpoint)?)
 }
 
-#[macro_export]
-macro_rules! exposed_session {
-    () => {
-        &state!().session_id.as_ref().unwrap().expose_secret()
-    };
-}
-
-#[derive(Debug)]
-pub struct State {
-    pub email: String,
-    pub password: SecretString,
-    pub session_id: Option<SecretString>,
-    pub forum_ids: Option<Vec<String>>,
-    pub subforum_ids: Option<Vec<String>>,
-    pub keep_going: bool,
-    pub do_images: bool,
-    pub do_apps: bool,
-    pub sanitize_log: bool,
-    pub req_client: Client,
-    pub conn: DatabaseConnection,
+pub fn build_image_client(limiter: &RateLimiter) -> ImageClient {
+    let client = reqwest::Client::new();
+    tower::ServiceBuilder::new()
+        .layer(RateLimitLayer::new(limiter.clone()))
+        .service(tower::service_fn(move |req| client.execute(req)))
+        .boxed_clone()
 }
 
 #[derive(Deserialize)]
@@ -91,18 +87,46 @@ pub struct ForumSettings {
     pub title_welcome: String,
 }
 
+/// A thread as a subforum listing has it: what we model of it, and the whole object Enjin sent.
+#[derive(Debug)]
+pub struct ListedThread {
+    pub thread: ForumThread,
+    pub json: serde_json::Value,
+}
+
+impl<'de> Deserialize<'de> for ListedThread {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = serde_json::Value::deserialize(deserializer)?;
+        let thread = ForumThread::deserialize(&json).map_err(serde::de::Error::custom)?;
+        Ok(ListedThread { thread, json })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct GetForumResult {
-    pub sticky: Vec<ForumThread>,
-    pub threads: Vec<ForumThread>,
-    pub notices: Vec<ForumThread>,
-    pub announcement_local: Vec<ForumThread>,
-    pub announcement_global: Vec<ForumThread>,
+    pub sticky: Vec<ListedThread>,
+    pub threads: Vec<ListedThread>,
+    pub notices: Vec<ListedThread>,
+    pub announcement_local: Vec<ListedThread>,
+    pub announcement_global: Vec<ListedThread>,
     pub forum: Subforum,
     pub page: serde_json::Value,
     pub pages: serde_json::Value,
 }
 
+impl GetForumResult {
+    /// The page's lists of threads by name, the pinned ones first.
+    pub fn listings(&self) -> [(&'static str, &[ListedThread]); 5] {
+        [
+            ("announcement_global", self.announcement_global.as_slice()),
+            ("announcement_local", self.announcement_local.as_slice()),
+            ("notices", self.notices.as_slice()),
+            ("sticky", self.sticky.as_slice()),
+            ("threads", self.threads.as_slice()),
+        ]
+    }
+}
+
 #[derive(Deserialize)]
 pub struct GetThreadResult {
     pub thread: ForumThread,
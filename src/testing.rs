@@ -0,0 +1,204 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::api::{EnjinApi, Params};
+use crate::config::{ConfigSource, SiteSettings};
+use crate::error::EnjinError;
+use crate::queue::{Unit, UnitKind};
+use crate::{Config, Extractor};
+use jsonrpsee::core::async_trait;
+use secrecy::SecretString;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The example site's forum listings and thread pages, as (ID, page).
+const LISTINGS: [(&str, u32); 3] = [("10", 1), ("10", 2), ("12", 1)];
+const THREAD_PAGES: [(&str, u32); 4] = [("100", 1), ("100", 2), ("101", 1), ("102", 1)];
+
+/// An `Extractor` for unit tests, on a new database of its own that's deleted again when it's
+/// dropped. It never logs in, and its endpoint refuses connections, so it can't talk to Enjin (nor
+/// does it queue up images to download).
+pub(crate) struct TestExtractor {
+    extractor: Extractor,
+    database_file: String,
+}
+
+impl TestExtractor {
+    /// `name` keeps the database apart from those of the other tests, which run at the same time.
+    pub(crate) async fn new(name: &str) -> TestExtractor {
+        TestExtractor::build(name, None).await
+    }
+
+    /// Like `new`, but with a session and talking to `api` instead.
+    pub(crate) async fn with_api(name: &str, api: FakeApi) -> TestExtractor {
+        TestExtractor::build(name, Some(api)).await
+    }
+
+    async fn build(name: &str, api: Option<FakeApi>) -> TestExtractor {
+        let database_file = format!("encuum-test-{}-{}.db", name, std::process::id());
+        remove(&database_file);
+        let config = Config::load(ConfigSource {
+            overrides: SiteSettings {
+                database_file: Some(database_file.clone()),
+                endpoint: Some("http://127.0.0.1:9".to_string()),
+                do_images: Some(false),
+                session_id: api
+                    .as_ref()
+                    .map(|_| SecretString::new("session".to_string())),
+                ..Default::default()
+            },
+            isolated: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let extractor = match api {
+            Some(api) => Extractor::with_api(config, Arc::new(api)).await,
+            None => Extractor::new(config).await,
+        };
+        TestExtractor {
+            extractor: extractor.unwrap(),
+            database_file,
+        }
+    }
+}
+
+/// Reads `path` (like `threads/100/1.json`) from the example fixtures, letting `edit` change it first.
+fn fixture<T: DeserializeOwned>(path: &str, edit: &impl Fn(&str, &mut Value)) -> Option<T> {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/example")
+        .join(path);
+    let mut value = serde_json::from_str(&std::fs::read_to_string(file).unwrap()).unwrap();
+    edit(path, &mut value);
+    (!value.is_null()).then(|| serde_json::from_value(value).unwrap())
+}
+
+impl TestExtractor {
+    /// Stores the example site as an extraction would, units of work and all, with the fixtures as
+    /// `edit` leaves them. A listing or thread page it sets to null is left out, as if it were gone.
+    pub(crate) async fn crawl_example(&self, edit: impl Fn(&str, &mut Value)) {
+        let caf = fixture("categories/1.json", &edit).unwrap();
+        self.save_preset(&"1".to_string(), &caf).await.unwrap();
+        let edit = &edit;
+        let page = |unit: &Unit| format!("{}/{}.json", unit.item_id, unit.page.unwrap());
+        self.enqueue(LISTINGS.map(|(id, page)| Unit::forum_page(id, page, "1")))
+            .await
+            .unwrap();
+        self.crawl(UnitKind::ForumPage, |unit| async move {
+            match fixture(&format!("forums/{}", page(&unit)), edit) {
+                Some(gfr) => self.save_subforum(&gfr).await,
+                None => Ok(()),
+            }
+        })
+        .await
+        .unwrap();
+        self.enqueue(THREAD_PAGES.map(|(id, page)| Unit::thread_page(id, page, "")))
+            .await
+            .unwrap();
+        self.crawl(UnitKind::ThreadPage, |unit| async move {
+            match fixture(&format!("threads/{}", page(&unit)), edit) {
+                Some(gtr) => self.save_thread_page(unit, gtr).await,
+                None => Ok(()),
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
+
+fn remove(database_file: &str) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", database_file, suffix));
+    }
+}
+
+impl Deref for TestExtractor {
+    type Target = Extractor;
+
+    fn deref(&self) -> &Extractor {
+        &self.extractor
+    }
+}
+
+impl DerefMut for TestExtractor {
+    fn deref_mut(&mut self) -> &mut Extractor {
+        &mut self.extractor
+    }
+}
+
+impl Drop for TestExtractor {
+    fn drop(&mut self) {
+        remove(&self.database_file);
+    }
+}
+
+type Answer = dyn Fn(&str, &Params) -> Result<Value, EnjinError> + Send + Sync;
+
+/// An `EnjinApi` for testing what wraps one: it answers every call with what `answer` makes of it,
+/// taking `delay` to do so, and keeps the methods it was called with in order.
+pub(crate) struct FakeApi {
+    answer: Box<Answer>,
+    delay: Duration,
+    pub(crate) calls: Mutex<Vec<String>>,
+    in_flight: Mutex<Vec<String>>,
+    /// The most calls that were in flight at once, of each method and (under "") of all of them.
+    pub(crate) most_in_flight: Mutex<HashMap<String, usize>>,
+}
+
+impl FakeApi {
+    pub(crate) fn new(
+        answer: impl Fn(&str, &Params) -> Result<Value, EnjinError> + Send + Sync + 'static,
+    ) -> Self {
+        FakeApi {
+            answer: Box::new(answer),
+            delay: Duration::ZERO,
+            calls: Mutex::new(vec![]),
+            in_flight: Mutex::new(vec![]),
+            most_in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn slow(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// A call `FakeApi` is answering, until it's done or given up on.
+struct InFlight<'a>(&'a FakeApi, String);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.0.in_flight.lock().unwrap();
+        if let Some(i) = in_flight.iter().position(|m| *m == self.1) {
+            in_flight.remove(i);
+        }
+    }
+}
+
+#[async_trait]
+impl EnjinApi for FakeApi {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        self.calls.lock().unwrap().push(method.to_string());
+        let _call = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            in_flight.push(method.to_string());
+            let mut most = self.most_in_flight.lock().unwrap();
+            for key in [method, ""] {
+                let now = in_flight
+                    .iter()
+                    .filter(|m| key.is_empty() || *m == key)
+                    .count();
+                let most = most.entry(key.to_string()).or_default();
+                *most = now.max(*most);
+            }
+            InFlight(self, method.to_string())
+        };
+        tokio::time::sleep(self.delay).await;
+        (self.answer)(method, &params)
+    }
+}
@@ -0,0 +1,178 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use crate::extractor::Extractor;
+use crate::queue::{UnitKind, UnitState};
+use crate::runs::now;
+use sea_orm::{ConnectionTrait, DbBackend, Statement};
+use tracing::info;
+
+/// The items of `kind` in the crawl queue whose every unit is done, i.e. that were crawled in full.
+fn fully_crawled(kind: UnitKind) -> String {
+    format!(
+        "SELECT item_id FROM crawl_queue WHERE kind = '{}' GROUP BY item_id HAVING SUM(state <> '{}') = 0",
+        kind.as_str(),
+        UnitState::Done.as_str()
+    )
+}
+
+impl Extractor {
+    async fn execute(&self, sql: String) -> anyhow::Result<u64> {
+        Ok(self
+            .conn
+            .execute(Statement::from_string(DbBackend::Sqlite, sql))
+            .await?
+            .rows_affected())
+    }
+
+    /// Once an extraction has crawled everything it was going to, marks the threads and posts it
+    /// should have come across but didn't with `deleted_detected_at`. Nothing is ever removed, and
+    /// saving a marked thread or post again clears the mark.
+    ///
+    /// A thread counts as gone if its subforum's listing was crawled without a failed page and didn't
+    /// have it. A post counts as gone if its thread is gone, or (except with `update`, which skips
+    /// pages it has) if every page of its thread was crawled and didn't have it. Subforums and
+    /// threads that weren't crawled, or only partly, are left alone.
+    pub async fn mark_deleted(&self) -> anyhow::Result<()> {
+        let started_at = match self.crawl_started_at {
+            Some(started_at) => started_at,
+            None => {
+                info!("Not looking for deleted threads and posts, since this extraction began before encuum kept track of when it did.");
+                return Ok(());
+            }
+        };
+        //Times are stored as RFC 3339 text in UTC, to the second, so they compare as text.
+        let started_at = format!("'{}'", started_at.to_rfc3339());
+        let deleted_at = format!("'{}'", now().to_rfc3339());
+        let unseen = format!(
+            "deleted_detected_at IS NULL AND (last_seen_at IS NULL OR last_seen_at < {})",
+            started_at
+        );
+        let threads = self
+            .execute(format!(
+                "UPDATE forum_threads SET deleted_detected_at = {} WHERE {} AND forum_id IN ({})",
+                deleted_at,
+                unseen,
+                fully_crawled(UnitKind::ForumPage)
+            ))
+            .await?;
+        let mut in_threads =
+            "thread_id IN (SELECT thread_id FROM forum_threads WHERE deleted_detected_at IS NOT NULL)"
+                .to_string();
+        if !self.config.update {
+            in_threads += &format!(" OR thread_id IN ({})", fully_crawled(UnitKind::ThreadPage));
+        }
+        let posts = self
+            .execute(format!(
+                "UPDATE forum_posts SET deleted_detected_at = {} WHERE {} AND ({})",
+                deleted_at, unseen, in_threads
+            ))
+            .await?;
+        if threads > 0 || posts > 0 {
+            info!(
+                "*** {} threads and {} posts are gone from Enjin; marked them deleted.",
+                threads, posts
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestExtractor;
+    use entity::{forum_posts, forum_threads};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+    use serde_json::{json, Value};
+
+    async fn marked(ex: &Extractor) -> (Vec<String>, Vec<String>) {
+        let threads = forum_threads::Entity::find()
+            .filter(forum_threads::Column::DeletedDetectedAt.is_not_null())
+            .order_by_asc(forum_threads::Column::ThreadId)
+            .all(&ex.conn)
+            .await
+            .unwrap();
+        let posts = forum_posts::Entity::find()
+            .filter(forum_posts::Column::DeletedDetectedAt.is_not_null())
+            .order_by_asc(forum_posts::Column::PostId)
+            .all(&ex.conn)
+            .await
+            .unwrap();
+        (
+            threads.into_iter().map(|t| t.thread_id).collect(),
+            posts.into_iter().map(|p| p.post_id).collect(),
+        )
+    }
+
+    /// Stores the example site, then makes it look as if that happened a while ago.
+    async fn earlier_extraction(ex: &mut TestExtractor) {
+        ex.crawl_example(|_, _| {}).await;
+        for table in ["forum_threads", "forum_posts"] {
+            ex.execute(format!(
+                "UPDATE {} SET last_seen_at = '2023-01-01T00:00:00+00:00'",
+                table
+            ))
+            .await
+            .unwrap();
+        }
+        ex.start_crawl().await.unwrap();
+    }
+
+    fn without_some(path: &str, value: &mut Value) {
+        match path {
+            "forums/12/1.json" => value["sticky"] = json!([]),
+            "threads/102/1.json" | "threads/101/1.json" => *value = Value::Null,
+            "threads/100/2.json" => value["posts"] = json!([]),
+            _ => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn what_a_full_extraction_didnt_come_across_is_marked_deleted() {
+        let mut ex = TestExtractor::new("tombstones").await;
+        earlier_extraction(&mut ex).await;
+        ex.crawl_example(without_some).await;
+        //Thread 101's page was never saved, so its post can't be told apart from a deleted one.
+        ex.execute(format!(
+            "UPDATE crawl_queue SET state = '{}' WHERE unit_id = 'thread_page/101/1'",
+            UnitState::Failed.as_str()
+        ))
+        .await
+        .unwrap();
+        ex.mark_deleted().await.unwrap();
+        //Thread 102 left its subforum, taking its post along, and page 2 of thread 100 lost its post.
+        let expected = (
+            vec!["102".to_string()],
+            vec!["1002".to_string(), "1004".to_string()],
+        );
+        assert_eq!(marked(&ex).await, expected);
+
+        //Showing up again clears the mark.
+        ex.start_crawl().await.unwrap();
+        ex.crawl_example(|_, _| {}).await;
+        ex.mark_deleted().await.unwrap();
+        assert_eq!(marked(&ex).await, (vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn an_update_only_marks_posts_of_threads_that_are_gone() {
+        let mut ex = TestExtractor::new("tombstones-update").await;
+        ex.config.update = true;
+        earlier_extraction(&mut ex).await;
+        ex.crawl_example(without_some).await;
+        ex.mark_deleted().await.unwrap();
+        let expected = (vec!["102".to_string()], vec!["1004".to_string()]);
+        assert_eq!(marked(&ex).await, expected);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_marked_without_knowing_when_the_extraction_began() {
+        let mut ex = TestExtractor::new("tombstones-unknown-start").await;
+        earlier_extraction(&mut ex).await;
+        ex.crawl_example(without_some).await;
+        ex.crawl_started_at = None;
+        ex.mark_deleted().await.unwrap();
+        assert_eq!(marked(&ex).await, (vec![], vec![]));
+    }
+}
@@ -0,0 +1,134 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use anyhow::Context;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection};
+use std::path::Path;
+
+/// Opens the SQLite database `filename`, creating it if it isn't there, without touching its schema.
+pub async fn connect(filename: &str) -> anyhow::Result<DatabaseConnection> {
+    Database::connect(format!("sqlite://./{}?mode=rwc", filename))
+        .await
+        .with_context(|| format!("Can't open DB {}", filename))
+}
+
+/// Opens the database `filename` for everything but `upgrade`. A new database is given the
+/// latest schema; one made by an older version of encuum is refused until it's been upgraded,
+/// which keeps a copy of it first.
+pub async fn open(filename: &str) -> anyhow::Result<DatabaseConnection> {
+    let conn = connect(filename).await?;
+    let pending = pending(&conn).await?;
+    if pending.len() == Migrator::migrations().len() {
+        upgrade(&conn).await?;
+    } else if !pending.is_empty() {
+        anyhow::bail!(
+            "The database {} was made by an older version of encuum and needs {} migration(s); run `encuum upgrade` first, which backs it up",
+            filename,
+            pending.len()
+        );
+    }
+    Ok(conn)
+}
+
+/// The migrations the database hasn't had yet, by name, in the order `upgrade` applies them, each
+/// with what it changes.
+pub async fn pending(conn: &DatabaseConnection) -> anyhow::Result<Vec<(String, &'static str)>> {
+    Ok(Migrator::pending(conn).await?)
+}
+
+/// Brings the database's schema up to date, keeping everything in it, and returns the migrations
+/// that did it, as `pending` does. A database from any older version of encuum can be upgraded.
+pub async fn upgrade(conn: &DatabaseConnection) -> anyhow::Result<Vec<(String, &'static str)>> {
+    let pending = pending(conn).await?;
+    Migrator::up(conn, None)
+        .await
+        .context("Failed to bring DB schema up")?;
+    Ok(pending)
+}
+
+/// Writes a copy of the database to `path`, which mustn't exist yet.
+pub async fn backup(conn: &DatabaseConnection, path: &Path) -> anyhow::Result<()> {
+    let quoted = path.to_string_lossy().replace('\'', "''");
+    conn.execute_unprepared(&format!("VACUUM INTO '{}'", quoted))
+        .await
+        .with_context(|| format!("Can't back the database up to {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::Statement;
+
+    struct Files(Vec<String>);
+
+    impl Files {
+        fn new(names: &[&str]) -> Files {
+            let files = Files(
+                names
+                    .iter()
+                    .map(|name| format!("encuum-test-{}-{}.db", name, std::process::id()))
+                    .collect(),
+            );
+            files.remove();
+            files
+        }
+
+        fn remove(&self) {
+            for file in &self.0 {
+                for suffix in ["", "-wal", "-shm"] {
+                    let _ = std::fs::remove_file(format!("{}{}", file, suffix));
+                }
+            }
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    async fn presets(conn: &DatabaseConnection) -> usize {
+        conn.query_all(Statement::from_string(
+            conn.get_database_backend(),
+            "SELECT * FROM forum_presets".to_string(),
+        ))
+        .await
+        .unwrap()
+        .len()
+    }
+
+    #[tokio::test]
+    async fn a_new_database_is_given_the_latest_schema() {
+        let files = Files::new(&["new"]);
+        let conn = open(&files.0[0]).await.unwrap();
+        assert!(pending(&conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_partly_migrated_database_is_only_opened_once_upgraded() {
+        let files = Files::new(&["partly-migrated", "partly-migrated-backup"]);
+        let (file, copy) = (&files.0[0], &files.0[1]);
+        let conn = connect(file).await.unwrap();
+        Migrator::up(&conn, Some(3)).await.unwrap();
+        conn.execute_unprepared("INSERT INTO forum_presets VALUES ('1', 'Forums', 1, 3)")
+            .await
+            .unwrap();
+        let e = open(file).await.unwrap_err().to_string();
+        assert!(e.contains("run `encuum upgrade` first"), "{}", e);
+
+        backup(&conn, Path::new(copy)).await.unwrap();
+        //The copy mustn't be overwritten by a later backup.
+        assert!(backup(&conn, Path::new(copy)).await.is_err());
+        let applied = upgrade(&conn).await.unwrap();
+        assert_eq!(applied.len(), Migrator::migrations().len() - 3);
+        assert_eq!(presets(&open(file).await.unwrap()).await, 1);
+
+        //The backup is the database as it was, still waiting for its upgrade.
+        let backed_up = connect(copy).await.unwrap();
+        assert_eq!(pending(&backed_up).await.unwrap().len(), applied.len());
+        assert_eq!(presets(&backed_up).await, 1);
+    }
+}
@@ -0,0 +1,173 @@
+/// Copyright (c) 2023, Sean McNamara <smcnam@gmail.com>.
+/// All code in this repository is disjunctively licensed under [CC-BY-SA 3.0](https://creativecommons.org/licenses/by-sa/3.0/) and [Apache 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+/// Direct dependencies are believed to be under a license which allows downstream code to have these licenses.
+use encuum::api::{describe, Params};
+use encuum::config::{ConfigSource, IdList, RetryOverride, RetrySettings, SiteSettings};
+use encuum::ratelimit::RateLimiter;
+use encuum::structures::build_client;
+use encuum::{Config, EnjinApi, EnjinError, Extractor};
+use entity::{failed_items, forum_posts, forum_presets, forum_threads, subforums};
+use jsonrpsee::core::async_trait;
+use sea_orm::{EntityTrait, QueryOrder};
+use secrecy::SecretString;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Passes calls through to the real HTTP client, noting each one.
+struct Counting {
+    inner: Arc<dyn EnjinApi>,
+    calls: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl EnjinApi for Counting {
+    async fn call(&self, method: &str, params: Params) -> Result<Value, EnjinError> {
+        let call = format!("{} {}", method, describe(&params));
+        self.calls.lock().unwrap().push(call);
+        self.inner.call(method, params).await
+    }
+}
+
+/// A database file that's deleted when this is dropped, even if the test fails.
+struct Database(String);
+
+impl Database {
+    fn new() -> Self {
+        let database = Database(format!("encuum-test-mock-server-{}.db", std::process::id()));
+        database.remove();
+        database
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.0, suffix));
+        }
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+#[tokio::test]
+async fn extracts_the_example_fixtures() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/example");
+    let (addr, server) = encuum::mock::serve(&fixtures, "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let database = Database::new();
+    let config = Config::load(ConfigSource {
+        overrides: SiteSettings {
+            endpoint: Some(format!("http://{}", addr)),
+            email: Some("someone@example.com".to_string()),
+            password: Some(SecretString::new("hunter2".to_string())),
+            database_file: Some(database.0.clone()),
+            forum_ids: Some(IdList::List(vec!["1".to_string()])),
+            keep_going: Some(true),
+            do_images: Some(false),
+            requests_per_second: Some(0.0),
+            retry: Some(RetrySettings {
+                max_attempts: Some(2),
+                base_delay_secs: Some(0.0),
+                jitter: Some(0.0),
+                //So the broken thread page is given up on rather than fixed by a second try.
+                methods: BTreeMap::from([(
+                    "Forum.getThread".to_string(),
+                    RetryOverride {
+                        max_attempts: Some(1),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        needs_site: true,
+        needs_login: true,
+        isolated: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let limiter = RateLimiter::new(config.requests_per_second, config.burst);
+    let api = Arc::new(Counting {
+        inner: Arc::new(build_client(&config, &limiter).unwrap()),
+        calls: Mutex::new(vec![]),
+    });
+
+    let mut extractor = Extractor::with_api(config, api.clone()).await.unwrap();
+    extractor.login().await.unwrap();
+    extractor.start_crawl().await.unwrap();
+    extractor.get_forums().await.unwrap();
+    extractor.get_apps().await.unwrap();
+    let conn = &extractor.conn;
+
+    let presets = forum_presets::Entity::find().all(conn).await.unwrap();
+    assert_eq!(presets.len(), 1);
+    assert_eq!(presets[0].preset_id, "1");
+    let subforums: Vec<String> = subforums::Entity::find()
+        .order_by_asc(subforums::Column::ForumId)
+        .all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|s| s.forum_id)
+        .collect();
+    assert_eq!(subforums, ["10", "12"]);
+    let threads: Vec<String> = forum_threads::Entity::find()
+        .order_by_asc(forum_threads::Column::ThreadId)
+        .all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|t| t.thread_id)
+        .collect();
+    assert_eq!(threads, ["100", "101", "102"]);
+    let posts: Vec<(Option<String>, Option<i64>)> = forum_posts::Entity::find()
+        .order_by_asc(forum_posts::Column::PostId)
+        .all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|p| (p.thread_id, p.thread_page))
+        .collect();
+    assert_eq!(
+        posts,
+        [
+            (Some("100".to_string()), Some(1)),
+            (Some("100".to_string()), Some(1)),
+            (Some("100".to_string()), Some(2)),
+            (Some("102".to_string()), Some(1)),
+        ]
+    );
+
+    let failed: Vec<(String, String)> = failed_items::Entity::find()
+        .order_by_asc(failed_items::Column::UnitId)
+        .all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|f| (f.unit_id, f.error_class))
+        .collect();
+    assert_eq!(
+        failed,
+        [
+            ("forum_page/11/1".to_string(), "no_access".to_string()),
+            ("thread_page/101/1".to_string(), "malformed".to_string()),
+        ]
+    );
+
+    //The 429 was waited out and tried again, which is how thread 101 got listed.
+    let calls = api.calls.lock().unwrap();
+    let page_2 = calls
+        .iter()
+        .filter(|c| c.starts_with("Forum.getForum") && c.contains(r#""forum_id":"10","page":"2""#))
+        .count();
+    assert_eq!(page_2, 2, "{:#?}", calls);
+
+    drop(calls);
+    server.stop().unwrap();
+}